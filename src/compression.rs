@@ -0,0 +1,228 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+};
+
+use flate2::{read::MultiGzDecoder, write::GzEncoder};
+use serde_json::json;
+
+use traceback_error::{traceback, TracebackError};
+
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xB5, 0x2F, 0xFD];
+
+/// A compression format the path-based APIs in this crate read and write transparently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Picks the compression from the last extension of `path`, `.gz` or `.zst`.
+    pub fn from_path(path: &str) -> Self {
+        let lower = path.to_ascii_lowercase();
+        if lower.ends_with(".gz") {
+            Compression::Gzip
+        } else if lower.ends_with(".zst") || lower.ends_with(".zstd") {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Picks the compression from the magic bytes at the start of a file.
+    pub fn from_magic(bytes: &[u8]) -> Self {
+        if bytes.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if bytes.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// The extension files in this format get, including the leading dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+}
+
+/// Splits a path into the path without its compression extension and the compression that extension stands for.
+///
+/// ## Example
+///
+/// ```rust
+/// use utils::compression::{strip_compression_extension, Compression};
+///
+/// assert_eq!(
+///     strip_compression_extension("data/orders.json.gz"),
+///     ("data/orders.json", Compression::Gzip)
+/// );
+/// ```
+pub fn strip_compression_extension(path: &str) -> (&str, Compression) {
+    let compression = Compression::from_path(path);
+    match path.rfind('.') {
+        Some(dot) if compression != Compression::None => (&path[..dot], compression),
+        _ => (path, Compression::None),
+    }
+}
+
+/// Opens a file for reading, decompressing it if it is gzip or zstd.
+/// The compression is detected from the magic bytes, so the extension doesn't matter.
+pub fn open_file(path: &str) -> Result<Box<dyn Read>, TracebackError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            return Err(traceback!("Failed to open file")
+                .with_extra_data(json!({ "error": e.to_string(), "path": path })))
+        }
+    };
+    let mut reader = BufReader::new(file);
+    let magic = match reader.fill_buf() {
+        Ok(buf) => Compression::from_magic(buf),
+        Err(e) => {
+            return Err(traceback!("Failed to read file")
+                .with_extra_data(json!({ "error": e.to_string(), "path": path })))
+        }
+    };
+    match magic {
+        Compression::None => Ok(Box::new(reader)),
+        Compression::Gzip => Ok(Box::new(MultiGzDecoder::new(reader))),
+        Compression::Zstd => match zstd::Decoder::with_buffer(reader) {
+            Ok(decoder) => Ok(Box::new(decoder)),
+            Err(e) => Err(traceback!("Failed to start zstd decoder")
+                .with_extra_data(json!({ "error": e.to_string(), "path": path }))),
+        },
+    }
+}
+
+/// Reads a whole file into memory, decompressing it if it is gzip or zstd.
+pub fn read_file(path: &str) -> Result<Vec<u8>, TracebackError> {
+    let mut reader = match open_file(path) {
+        Ok(reader) => reader,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    let mut bytes = vec![];
+    match reader.read_to_end(&mut bytes) {
+        Ok(_) => Ok(bytes),
+        Err(e) => Err(traceback!("Failed to read file")
+            .with_extra_data(json!({ "error": e.to_string(), "path": path }))),
+    }
+}
+
+/// A file being written, compressed according to its extension.
+///
+/// Call `finish` when done. Dropping the writer instead may leave a truncated compressed stream.
+pub enum CompressedWriter {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl CompressedWriter {
+    /// Creates a file, compressing what is written to it if the path ends in `.gz` or `.zst`.
+    pub fn create(path: &str) -> Result<Self, TracebackError> {
+        let file = match File::create(path) {
+            Ok(file) => BufWriter::new(file),
+            Err(e) => {
+                return Err(traceback!("Failed to create file")
+                    .with_extra_data(json!({ "error": e.to_string(), "path": path })))
+            }
+        };
+        match Compression::from_path(path) {
+            Compression::None => Ok(CompressedWriter::Plain(file)),
+            Compression::Gzip => Ok(CompressedWriter::Gzip(GzEncoder::new(
+                file,
+                flate2::Compression::default(),
+            ))),
+            Compression::Zstd => match zstd::Encoder::new(file, 0) {
+                Ok(encoder) => Ok(CompressedWriter::Zstd(encoder)),
+                Err(e) => Err(traceback!("Failed to start zstd encoder")
+                    .with_extra_data(json!({ "error": e.to_string(), "path": path }))),
+            },
+        }
+    }
+
+    /// Ends the compressed stream and flushes everything to disk.
+    pub fn finish(self) -> Result<(), TracebackError> {
+        let result = match self {
+            CompressedWriter::Plain(mut file) => file.flush(),
+            CompressedWriter::Gzip(encoder) => encoder.finish().and_then(|mut file| file.flush()),
+            CompressedWriter::Zstd(encoder) => encoder.finish().and_then(|mut file| file.flush()),
+        };
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => Err(traceback!("Failed to finish writing file")
+                .with_extra_data(json!({ "error": e.to_string() }))),
+        }
+    }
+}
+
+impl Write for CompressedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            CompressedWriter::Plain(file) => file.write(buf),
+            CompressedWriter::Gzip(encoder) => encoder.write(buf),
+            CompressedWriter::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            CompressedWriter::Plain(file) => file.flush(),
+            CompressedWriter::Gzip(encoder) => encoder.flush(),
+            CompressedWriter::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Writes `bytes` to a file, compressing them if the path ends in `.gz` or `.zst`.
+pub fn write_file(path: &str, bytes: &[u8]) -> Result<(), TracebackError> {
+    let mut writer = match CompressedWriter::create(path) {
+        Ok(writer) => writer,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    if let Err(e) = writer.write_all(bytes) {
+        return Err(traceback!("Failed to write file")
+            .with_extra_data(json!({ "error": e.to_string(), "path": path })));
+    }
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["plain.csv", "packed.csv.gz", "packed.csv.zst"] {
+            let path = dir.path().join(name);
+            let path = path.to_str().unwrap();
+            write_file(path, b"a,b\n1,2\n").unwrap();
+            assert_eq!(read_file(path).unwrap(), b"a,b\n1,2\n");
+        }
+        let raw = std::fs::read(dir.path().join("packed.csv.gz")).unwrap();
+        assert_eq!(Compression::from_magic(&raw), Compression::Gzip);
+        let raw = std::fs::read(dir.path().join("packed.csv.zst")).unwrap();
+        assert_eq!(Compression::from_magic(&raw), Compression::Zstd);
+    }
+
+    #[test]
+    fn test_detects_by_magic_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let packed = dir.path().join("packed.gz");
+        write_file(packed.to_str().unwrap(), b"hello").unwrap();
+        // A compressed file without a compression extension is still read transparently
+        let renamed = dir.path().join("packed.txt");
+        std::fs::rename(&packed, &renamed).unwrap();
+        assert_eq!(read_file(renamed.to_str().unwrap()).unwrap(), b"hello");
+    }
+}
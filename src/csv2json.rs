@@ -1,8 +1,15 @@
-use csv::Reader;
-use serde_json::{json, Value};
+use csv::{Position, Reader, ReaderBuilder};
+use encoding_rs::Encoding;
+use serde_json::{json, Map, Value};
 
 use traceback_error::{traceback, TracebackError};
 
+use crate::{
+    compression::read_file,
+    encoding::{decode_with, read_file_to_utf8, OutputEncoding},
+    mapping::ColumnMapping,
+};
+
 /// Converts a CSV data represented by a `csv::Reader<&[u8]>` into a `serde_json::Value`.
 ///
 /// ## Arguments
@@ -19,6 +26,8 @@ use traceback_error::{traceback, TracebackError};
 /// - The first row of the CSV is considered the header row.
 /// - All other rows are treated as data rows.
 /// - All data in the CSV is assumed to be in string format.
+/// - Every row has as many fields as the header row. Ragged rows are rejected,
+///   use `csv_to_json_with_options` to pad, skip, truncate or collect them instead.
 ///
 /// ## Notes
 ///
@@ -53,42 +62,207 @@ use traceback_error::{traceback, TracebackError};
 ///
 /// In this example, `csv_data` is a byte slice representing CSV data. The function `csv_to_json` is used to convert the CSV data into JSON format.
 /// The resulting JSON data can be used as needed.
-pub fn csv_to_json<T: std::io::Read>(
+pub fn csv_to_json<T: std::io::Read>(csv: Reader<T>) -> Result<serde_json::Value, TracebackError> {
+    csv_to_json_with_options(csv, &CsvToJsonOptions::default())
+}
+
+/// Decides what `csv_to_json_with_options` does with a row that has fewer fields than the header row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShortRowPolicy {
+    /// Fail with a `TracebackError` pointing at the offending row.
+    #[default]
+    Error,
+    /// Fill the missing fields with `null`.
+    PadNull,
+    /// Leave the row out of the output.
+    Skip,
+}
+
+/// Decides what `csv_to_json_with_options` does with a row that has more fields than the header row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LongRowPolicy {
+    /// Fail with a `TracebackError` pointing at the offending row.
+    #[default]
+    Error,
+    /// Drop the fields that have no matching header.
+    Truncate,
+    /// Keep the fields that have no matching header as an array of strings under the `_extra` key.
+    /// A header row that already has an `_extra` column is an error, rather than having it overwritten.
+    CollectExtra,
+}
+
+/// Options for `csv_to_json_with_options` and `csv_file_to_json_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct CsvToJsonOptions {
+    pub short_rows: ShortRowPolicy,
+    pub long_rows: LongRowPolicy,
+    /// Reshapes every row after it has been read, see `ColumnMapping`.
+    pub mapping: Option<ColumnMapping>,
+    /// The encoding of the file read by `csv_file_to_json_with_options`.
+    /// Detected with `encoding::detect_encoding` when `None`.
+    pub encoding: Option<&'static Encoding>,
+}
+
+/// The key under which `LongRowPolicy::CollectExtra` stores the surplus fields of a row.
+pub const EXTRA_FIELDS_KEY: &str = "_extra";
+
+/// Converts CSV data into a `serde_json::Value`, handling ragged rows according to `options`.
+///
+/// ## Arguments
+///
+/// * `csv` - A `csv::Reader` containing the CSV data to be converted.
+/// * `options` - How to treat rows that are shorter or longer than the header row,
+///   and an optional `ColumnMapping` applied to every row.
+///
+/// ## Returns
+///
+/// * `Result<serde_json::Value, TracebackError>` - A JSON array with one object per row,
+///   or a `TracebackError` carrying the line number and byte offset of the row that failed.
+///
+/// ## Notes
+///
+/// - The `csv` crate rejects ragged rows by itself unless the reader was built with
+///   `csv::ReaderBuilder::flexible(true)`, so the policies only take effect on flexible readers.
+///   For other readers, the error still reports where the ragged row was found.
+///
+/// ## Example
+///
+/// ```rust
+/// use csv::ReaderBuilder;
+/// use serde_json::json;
+/// use utils::csv2json::{csv_to_json_with_options, CsvToJsonOptions, LongRowPolicy, ShortRowPolicy};
+///
+/// let csv_data: &[u8] = b"name,age\nalice\nbob,30,extra";
+/// let reader = ReaderBuilder::new().flexible(true).from_reader(csv_data);
+/// let options = CsvToJsonOptions {
+///     short_rows: ShortRowPolicy::PadNull,
+///     long_rows: LongRowPolicy::CollectExtra,
+///     ..Default::default()
+/// };
+///
+/// let json_data = csv_to_json_with_options(reader, &options).unwrap();
+/// assert_eq!(
+///     json_data,
+///     json!([
+///         {"name": "alice", "age": null},
+///         {"name": "bob", "age": "30", "_extra": ["extra"]}
+///     ])
+/// );
+/// ```
+pub fn csv_to_json_with_options<T: std::io::Read>(
     mut csv: Reader<T>,
+    options: &CsvToJsonOptions,
 ) -> Result<serde_json::Value, TracebackError> {
     let headers = match csv.headers().cloned() {
         Ok(headers) => headers,
         Err(e) => {
-            return Err(traceback!("Failed to read CSV headers")
-                .with_extra_data(json!({ "error": e.to_string() })))
+            return Err(
+                traceback!("Failed to read CSV headers").with_extra_data(json!({
+                    "error": e.to_string(),
+                    "position": position_to_json(e.position()),
+                })),
+            )
         }
     };
+    if options.long_rows == LongRowPolicy::CollectExtra
+        && headers.iter().any(|header| header == EXTRA_FIELDS_KEY)
+    {
+        return Err(traceback!(format!(
+            "The CSV headers already have a {EXTRA_FIELDS_KEY} column, which LongRowPolicy::CollectExtra would overwrite"
+        ))
+        .with_extra_data(json!({ "headers": format!("{:?}", headers) })));
+    }
     let mut records = Vec::new();
     for result in csv.records() {
         let record = match result {
             Ok(record) => record,
             Err(e) => {
-                return Err(traceback!("Failed to read CSV record")
-                    .with_extra_data(json!({ "error": e.to_string() })))
+                return Err(
+                    traceback!("Failed to read CSV record").with_extra_data(json!({
+                        "error": e.to_string(),
+                        "position": position_to_json(e.position()),
+                    })),
+                )
             }
         };
-        let mut obj = serde_json::Map::new();
+        if record.len() < headers.len() {
+            match options.short_rows {
+                ShortRowPolicy::Error => {
+                    return Err(ragged_row_error(&record, headers.len(), "fewer"));
+                }
+                ShortRowPolicy::Skip => continue,
+                ShortRowPolicy::PadNull => {}
+            }
+        }
+        if record.len() > headers.len() && options.long_rows == LongRowPolicy::Error {
+            return Err(ragged_row_error(&record, headers.len(), "more"));
+        }
+        let mut obj = Map::new();
         for (i, header) in headers.iter().enumerate() {
-            let current_rec = match record.get(i) {
-                Some(current_rec) => current_rec,
-                None => {
-                    return Err(traceback!("Failed to get current record")
-                        .with_extra_data(json!({ "record": format!("{:?}", record) })))
+            let value = match record.get(i) {
+                Some(current_rec) => Value::String(current_rec.to_string()),
+                None => Value::Null,
+            };
+            obj.insert(header.to_string(), value);
+        }
+        if record.len() > headers.len() && options.long_rows == LongRowPolicy::CollectExtra {
+            let extra = record
+                .iter()
+                .skip(headers.len())
+                .map(|field| Value::String(field.to_string()))
+                .collect();
+            obj.insert(EXTRA_FIELDS_KEY.to_string(), Value::Array(extra));
+        }
+        if let Some(mapping) = &options.mapping {
+            obj = match mapping.apply(&obj) {
+                Ok(mapped) => mapped,
+                Err(e) => {
+                    return Err(
+                        traceback!(err e, "Failed to map CSV record").with_extra_data(
+                            json!({ "position": position_to_json(record.position()) }),
+                        ),
+                    )
                 }
             };
-            obj.insert(
-                header.to_string(),
-                serde_json::Value::String(current_rec.to_string()),
-            );
         }
-        records.push(serde_json::Value::Object(obj));
+        records.push(Value::Object(obj));
+    }
+    Ok(Value::Array(records))
+}
+
+/// Builds the error for a row whose length doesn't match the header row.
+/// `comparison` is either "fewer" or "more", and is only used in the message.
+fn ragged_row_error(
+    record: &csv::StringRecord,
+    expected: usize,
+    comparison: &str,
+) -> TracebackError {
+    let line = match record.position() {
+        Some(pos) => pos.line().to_string(),
+        None => "?".to_string(),
+    };
+    traceback!(format!(
+        "CSV record on line {line} has {comparison} fields than the header row ({} instead of {expected})",
+        record.len()
+    ))
+    .with_extra_data(json!({
+        "record": format!("{:?}", record),
+        "expected_len": expected,
+        "len": record.len(),
+        "position": position_to_json(record.position()),
+    }))
+}
+
+/// Converts a `csv::Position` into the JSON attached to `TracebackError`s.
+fn position_to_json(position: Option<&Position>) -> Value {
+    match position {
+        Some(pos) => json!({
+            "line": pos.line(),
+            "byte": pos.byte(),
+            "record": pos.record(),
+        }),
+        None => Value::Null,
     }
-    Ok(serde_json::Value::Array(records))
 }
 
 /// Converts a `serde_json::Value` into a CSV-formatted string.
@@ -132,8 +306,8 @@ pub fn csv_to_json<T: std::io::Read>(
 ///
 /// In this example, `json_data` is a JSON object containing an array of records. The function `json_to_csv` is used to convert the JSON data into a CSV-formatted string.
 /// The resulting CSV string can be used as needed.
-pub fn json_to_csv<'a>(json: Value) -> Result<String, TracebackError> {
-    let mut wtr = csv::Writer::from_writer(vec![]);
+pub fn json_to_csv(json: Value) -> Result<String, TracebackError> {
+    let mut wtr = new_csv_writer();
     let zeroth = match json.get(0) {
         Some(zeroth) => zeroth,
         None => {
@@ -198,6 +372,104 @@ pub fn json_to_csv<'a>(json: Value) -> Result<String, TracebackError> {
             }
         };
     }
+    finish_csv_writer(wtr)
+}
+
+/// Converts a JSON array of objects into a CSV-formatted string, reshaping every row with `mapping` first.
+///
+/// Unlike `json_to_csv`, the columns are written in the order given by `ColumnMapping::output_columns`,
+/// rows don't need to share the same keys, and values don't need to be strings:
+/// `null` and missing values are written as empty fields, and other non-string values as JSON.
+///
+/// ## Example
+///
+/// ```rust
+/// use serde_json::json;
+/// use utils::csv2json::json_to_csv_with_mapping;
+/// use utils::mapping::{ColumnMapping, Converter};
+///
+/// let json = json!([
+///     {"name": "Alice", "age": 25, "location": "New York"},
+///     {"name": "Bob", "age": 30}
+/// ]);
+/// let mapping = ColumnMapping::new()
+///     .select(["name", "location", "age"])
+///     .convert("name", Converter::Lowercase)
+///     .rename("location", "city");
+///
+/// let csv = json_to_csv_with_mapping(json, &mapping).unwrap();
+/// assert_eq!(csv, "name,city,age\nalice,New York,25\nbob,,30\n");
+/// ```
+pub fn json_to_csv_with_mapping(
+    json: Value,
+    mapping: &ColumnMapping,
+) -> Result<String, TracebackError> {
+    let arr = match json.as_array() {
+        Some(arr) => arr,
+        None => {
+            return Err(traceback!("Failed to get json as array")
+                .with_extra_data(json!({ "json": json.to_string() })))
+        }
+    };
+    let mut input_columns: Vec<String> = Vec::new();
+    for record in arr {
+        if let Some(obj) = record.as_object() {
+            for key in obj.keys() {
+                if !input_columns.contains(key) {
+                    input_columns.push(key.clone());
+                }
+            }
+        }
+    }
+    let columns = mapping.output_columns(&input_columns);
+    let mapped = match mapping.apply_to_array(&json) {
+        Ok(mapped) => mapped,
+        Err(e) => return Err(traceback!(err e, "Failed to map json records")),
+    };
+    let mut wtr = new_csv_writer();
+    match wtr.write_record(&columns) {
+        Ok(_) => (),
+        Err(e) => {
+            return Err(traceback!("Failed to write CSV headers")
+                .with_extra_data(json!({ "error": e.to_string() })))
+        }
+    }
+    for record in mapped.as_array().into_iter().flatten() {
+        let row = columns.iter().map(|column| match record.get(column) {
+            None | Some(Value::Null) => String::new(),
+            Some(Value::String(s)) => s.clone(),
+            Some(value) => value.to_string(),
+        });
+        match wtr.write_record(row) {
+            Ok(_) => (),
+            Err(e) => {
+                return Err(traceback!("Failed to write CSV record")
+                    .with_extra_data(json!({ "error": e.to_string() })))
+            }
+        };
+    }
+    finish_csv_writer(wtr)
+}
+
+/// Same as `json_to_csv`, but returns the CSV encoded as `encoding`.
+/// Use `OutputEncoding::Utf8Bom` or `OutputEncoding::Utf16Le` for files meant to be opened in Excel.
+pub fn json_to_csv_with_encoding(
+    json: Value,
+    encoding: OutputEncoding,
+) -> Result<Vec<u8>, TracebackError> {
+    match json_to_csv(json) {
+        Ok(csv) => Ok(encoding.encode(&csv)),
+        Err(e) => Err(traceback!(err e)),
+    }
+}
+
+/// Creates the in-memory CSV writer shared by every CSV export in this crate.
+pub(crate) fn new_csv_writer() -> csv::Writer<Vec<u8>> {
+    csv::Writer::from_writer(vec![])
+}
+
+/// Flushes a writer created by `new_csv_writer` and returns everything written to it.
+pub(crate) fn finish_csv_writer(wtr: csv::Writer<Vec<u8>>) -> Result<String, TracebackError> {
     let inner = match wtr.into_inner() {
         Ok(inner) => inner,
         Err(e) => {
@@ -207,26 +479,42 @@ pub fn json_to_csv<'a>(json: Value) -> Result<String, TracebackError> {
     };
     match String::from_utf8(inner) {
         Ok(string) => Ok(string),
-        Err(e) => {
-            return Err(traceback!("Failed to convert CSV writer to string")
-                .with_extra_data(json!({ "error": e.to_string() })))
-        }
+        Err(e) => Err(traceback!("Failed to convert CSV writer to string")
+            .with_extra_data(json!({ "error": e.to_string() }))),
     }
 }
 
 /// This function takes in a csv file path and returns a serde_json::Value
 /// NOTE: Some data will be lost in the conversion from csv to json.
 /// This happens because serde_json automatically sorts the CSV headers alphabetically.
+/// The file may be in any encoding `encoding::detect_encoding` recognises, such as Windows-1252 or UTF-16,
+/// and may be gzip or zstd compressed.
 pub fn csv_file_to_json(path: &str) -> Result<serde_json::Value, TracebackError> {
-    // read csv file, then pass it to csv_to_json
-    let rdr = match csv::Reader::from_path(path) {
-        Ok(rdr) => rdr,
-        Err(e) => {
-            return Err(traceback!("Failed to read CSV file")
-                .with_extra_data(json!({ "error": e.to_string() })))
-        }
+    csv_file_to_json_with_options(path, &CsvToJsonOptions::default())
+}
+
+/// Same as `csv_file_to_json`, but handles ragged rows and the file's encoding according to `options`.
+/// See `csv_to_json_with_options` for details.
+pub fn csv_file_to_json_with_options(
+    path: &str,
+    options: &CsvToJsonOptions,
+) -> Result<serde_json::Value, TracebackError> {
+    // read csv file and transcode it to UTF-8, then pass it to csv_to_json_with_options
+    let text = match options.encoding {
+        Some(encoding) => match read_file(path) {
+            Ok(bytes) => decode_with(&bytes, encoding),
+            Err(e) => Err(e),
+        },
+        None => read_file_to_utf8(path),
     };
-    match csv_to_json(rdr) {
+    let text = match text {
+        Ok(text) => text,
+        Err(e) => return Err(traceback!(err e, "Failed to read CSV file")),
+    };
+    let rdr = ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    match csv_to_json_with_options(rdr, options) {
         Ok(json) => Ok(json),
         Err(e) => Err(traceback!("Failed to parse CSV to json").with_parent(e)),
     }
@@ -260,3 +548,154 @@ fn test_json_to_csv() {
     let csv = json_to_csv(json);
     assert_eq!(csv.unwrap(), BASIC_CSV);
 }
+
+pub static RAGGED_CSV: &str = "name,age
+alice
+bob,30,extra
+";
+
+#[cfg(test)]
+fn ragged_reader() -> Reader<&'static [u8]> {
+    ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(RAGGED_CSV.as_bytes())
+}
+
+#[test]
+fn test_csv_to_json_pads_and_collects_ragged_rows() {
+    let options = CsvToJsonOptions {
+        short_rows: ShortRowPolicy::PadNull,
+        long_rows: LongRowPolicy::CollectExtra,
+        ..Default::default()
+    };
+    let json = csv_to_json_with_options(ragged_reader(), &options).unwrap();
+    assert_eq!(
+        json,
+        json!([
+            {"name": "alice", "age": null},
+            {"name": "bob", "age": "30", "_extra": ["extra"]}
+        ])
+    );
+}
+
+#[test]
+fn test_csv_to_json_rejects_extra_column_when_collecting() {
+    let reader = ReaderBuilder::new()
+        .flexible(true)
+        .from_reader("name,_extra\nbob,x,y\n".as_bytes());
+    let options = CsvToJsonOptions {
+        long_rows: LongRowPolicy::CollectExtra,
+        ..Default::default()
+    };
+    let mut err = csv_to_json_with_options(reader, &options).unwrap_err();
+    err.is_handled = true;
+    assert_eq!(
+        err.message,
+        "The CSV headers already have a _extra column, which LongRowPolicy::CollectExtra would overwrite"
+    );
+}
+
+#[test]
+fn test_csv_to_json_skips_and_truncates_ragged_rows() {
+    let options = CsvToJsonOptions {
+        short_rows: ShortRowPolicy::Skip,
+        long_rows: LongRowPolicy::Truncate,
+        ..Default::default()
+    };
+    let json = csv_to_json_with_options(ragged_reader(), &options).unwrap();
+    assert_eq!(json, json!([{"name": "bob", "age": "30"}]));
+}
+
+#[test]
+fn test_csv_to_json_reports_position_of_ragged_row() {
+    let mut err = csv_to_json(ragged_reader()).unwrap_err();
+    err.is_handled = true;
+    assert_eq!(
+        err.message,
+        "CSV record on line 2 has fewer fields than the header row (1 instead of 2)"
+    );
+    assert_eq!(err.extra_data[0]["position"]["line"], 2);
+    assert_eq!(err.extra_data[0]["position"]["byte"], 9);
+}
+
+#[test]
+fn test_csv_to_json_with_mapping() {
+    let options = CsvToJsonOptions {
+        mapping: Some(
+            ColumnMapping::new()
+                .select(["name", "age"])
+                .convert("name", crate::mapping::Converter::Uppercase)
+                .convert(
+                    "age",
+                    crate::mapping::Converter::ParseNumber {
+                        decimal_comma: false,
+                    },
+                )
+                .rename("age", "years"),
+        ),
+        ..Default::default()
+    };
+    let csv = Reader::from_reader(BASIC_CSV.as_bytes());
+    let json = csv_to_json_with_options(csv, &options).unwrap();
+    assert_eq!(
+        json,
+        json!([{"name": "ALICE", "years": 20}, {"name": "BOB", "years": 30}])
+    );
+}
+
+#[test]
+fn test_csv_file_to_json_with_options_transcodes() {
+    let dir = tempfile::tempdir().unwrap();
+    let windows_1252 = dir.path().join("windows-1252.csv");
+    std::fs::write(&windows_1252, b"name,city\nRen\xe9,Orl\xe9ans\n").unwrap();
+    let utf16le = dir.path().join("utf-16le.csv");
+    let text = "name,city\nRené,Orléans\n";
+    let bytes: Vec<u8> = [0xFF, 0xFE]
+        .into_iter()
+        .chain(text.encode_utf16().flat_map(|unit| unit.to_le_bytes()))
+        .collect();
+    std::fs::write(&utf16le, bytes).unwrap();
+
+    let options = CsvToJsonOptions::default();
+    let expected = json!([{"name": "René", "city": "Orléans"}]);
+    for path in [&windows_1252, &utf16le] {
+        let json = csv_file_to_json_with_options(path.to_str().unwrap(), &options).unwrap();
+        assert_eq!(json, expected);
+    }
+    let options = CsvToJsonOptions {
+        encoding: Some(encoding_rs::WINDOWS_1252),
+        ..Default::default()
+    };
+    let json = csv_file_to_json_with_options(windows_1252.to_str().unwrap(), &options).unwrap();
+    assert_eq!(json, expected);
+
+    // Read as UTF-8, the first é is invalid
+    let options = CsvToJsonOptions {
+        encoding: Some(encoding_rs::UTF_8),
+        ..Default::default()
+    };
+    let mut err =
+        csv_file_to_json_with_options(windows_1252.to_str().unwrap(), &options).unwrap_err();
+    err.is_handled = true;
+    let parent = err.parent.as_ref().unwrap();
+    assert_eq!(parent.extra_data[0]["offset"], 13);
+}
+
+#[test]
+fn test_csv_file_to_json_windows_1252() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("windows-1252.csv");
+    std::fs::write(
+        &path,
+        b"name,city\nJ\xf8ran,Troms\xf8\nRen\xe9,Orl\xe9ans\n",
+    )
+    .unwrap();
+    let json = csv_file_to_json(path.to_str().unwrap()).unwrap();
+    assert_eq!(
+        json,
+        json!([
+            {"name": "Jøran", "city": "Tromsø"},
+            {"name": "René", "city": "Orléans"}
+        ])
+    );
+}
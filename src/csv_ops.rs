@@ -0,0 +1,991 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet},
+    fs::create_dir_all,
+    io::{Read, Write},
+    path::Path,
+};
+
+use csv::{Reader, ReaderBuilder, StringRecord, Writer};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use traceback_error::{traceback, TracebackError};
+
+use crate::compression::{open_file, strip_compression_extension, CompressedWriter};
+
+/// The format the CSV operations in this module write their output in.
+///
+/// Independently of the format, output is gzip or zstd compressed when its path ends in `.gz` or `.zst`,
+/// and compressed input is always decompressed transparently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// A JSON array with one object per row, like the output of `csv2json::csv_to_json`.
+    Json,
+}
+
+/// A column to sort by.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortKey {
+    pub column: String,
+    pub descending: bool,
+    /// Compare values as numbers. Values that aren't numbers sort after all numbers when ascending.
+    pub numeric: bool,
+}
+
+impl SortKey {
+    /// Sorts by `column` in ascending order, comparing values as strings.
+    pub fn asc(column: impl Into<String>) -> Self {
+        Self {
+            column: column.into(),
+            descending: false,
+            numeric: false,
+        }
+    }
+
+    /// Sorts by `column` in descending order, comparing values as strings.
+    pub fn desc(column: impl Into<String>) -> Self {
+        Self {
+            descending: true,
+            ..Self::asc(column)
+        }
+    }
+
+    /// Compares the values of this column as numbers.
+    pub fn numeric(mut self) -> Self {
+        self.numeric = true;
+        self
+    }
+}
+
+/// Options for `sort_csv_file`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortOptions {
+    /// The columns to sort by, most significant first.
+    pub keys: Vec<SortKey>,
+    /// How many records to hold in memory at once.
+    /// Larger inputs are sorted in runs of this size, which are spilled to temporary files and merged.
+    pub max_rows_in_memory: usize,
+    pub output: OutputFormat,
+}
+
+impl Default for SortOptions {
+    fn default() -> Self {
+        Self {
+            keys: vec![],
+            max_rows_in_memory: 100_000,
+            output: OutputFormat::Csv,
+        }
+    }
+}
+
+/// Sorts a CSV file by one or more columns, writing the result to `output`.
+///
+/// The sort is stable, so rows with equal keys keep their original order.
+/// Files larger than `SortOptions::max_rows_in_memory` rows are sorted with an external merge sort,
+/// so memory use stays bounded no matter how large the file is.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use utils::csv_ops::{sort_csv_file, SortKey, SortOptions};
+///
+/// let options = SortOptions {
+///     keys: vec![SortKey::asc("country"), SortKey::desc("population").numeric()],
+///     ..Default::default()
+/// };
+/// sort_csv_file("cities.csv", "cities_sorted.csv", &options).unwrap();
+/// ```
+pub fn sort_csv_file(
+    input: &str,
+    output: &str,
+    options: &SortOptions,
+) -> Result<(), TracebackError> {
+    let mut rdr = match open_reader(input) {
+        Ok(rdr) => rdr,
+        Err(e) => return Err(traceback!(err e, "Failed to open CSV file to sort")),
+    };
+    let headers = match read_headers(&mut rdr) {
+        Ok(headers) => headers,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    let mut keys = Vec::with_capacity(options.keys.len());
+    for key in &options.keys {
+        match column_index(&headers, &key.column) {
+            Ok(index) => keys.push((index, key)),
+            Err(e) => return Err(traceback!(err e)),
+        }
+    }
+    let temp_dir = match tempfile::tempdir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            return Err(
+                traceback!("Failed to create temporary directory for sort runs")
+                    .with_extra_data(json!({ "error": e.to_string() })),
+            )
+        }
+    };
+    let max_rows = options.max_rows_in_memory.max(1);
+    let mut runs = Vec::new();
+    let mut chunk: Vec<SortItem> = Vec::new();
+    for result in rdr.records() {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => return Err(record_error(e, input)),
+        };
+        chunk.push(SortItem::new(record, &keys, 0));
+        if chunk.len() >= max_rows {
+            let run_path = temp_dir.path().join(format!("run-{}.csv", runs.len()));
+            if let Err(e) = write_run(&run_path, &mut chunk) {
+                return Err(traceback!(err e, "Failed to write sort run"));
+            }
+            runs.push(run_path);
+        }
+    }
+
+    let mut sink = match Sink::create(output, options.output, &headers) {
+        Ok(sink) => sink,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    if runs.is_empty() {
+        // Everything fit in memory, so there is nothing to merge
+        chunk.sort();
+        for item in &chunk {
+            if let Err(e) = sink.write(&item.record) {
+                return Err(traceback!(err e));
+            }
+        }
+        return sink.finish();
+    }
+    if !chunk.is_empty() {
+        let run_path = temp_dir.path().join(format!("run-{}.csv", runs.len()));
+        if let Err(e) = write_run(&run_path, &mut chunk) {
+            return Err(traceback!(err e, "Failed to write sort run"));
+        }
+        runs.push(run_path);
+    }
+
+    let mut readers = Vec::with_capacity(runs.len());
+    for run_path in &runs {
+        match ReaderBuilder::new().has_headers(false).from_path(run_path) {
+            Ok(rdr) => readers.push(rdr.into_records()),
+            Err(e) => {
+                return Err(traceback!("Failed to open sort run")
+                    .with_extra_data(json!({ "error": e.to_string() })))
+            }
+        }
+    }
+    let mut heap = BinaryHeap::new();
+    for (run, records) in readers.iter_mut().enumerate() {
+        match records.next() {
+            Some(Ok(record)) => heap.push(Reverse(SortItem::new(record, &keys, run))),
+            Some(Err(e)) => return Err(record_error(e, input)),
+            None => {}
+        }
+    }
+    while let Some(Reverse(item)) = heap.pop() {
+        if let Err(e) = sink.write(&item.record) {
+            return Err(traceback!(err e));
+        }
+        match readers[item.run].next() {
+            Some(Ok(record)) => heap.push(Reverse(SortItem::new(record, &keys, item.run))),
+            Some(Err(e)) => return Err(record_error(e, input)),
+            None => {}
+        }
+    }
+    sink.finish()
+}
+
+/// A part of a sort key, ordered the way `SortKey` describes.
+#[derive(Debug, PartialEq)]
+enum KeyValue {
+    Number(f64),
+    Text(String),
+}
+
+impl KeyValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (KeyValue::Number(a), KeyValue::Number(b)) => a.total_cmp(b),
+            (KeyValue::Number(_), KeyValue::Text(_)) => Ordering::Less,
+            (KeyValue::Text(_), KeyValue::Number(_)) => Ordering::Greater,
+            (KeyValue::Text(a), KeyValue::Text(b)) => a.cmp(b),
+        }
+    }
+}
+
+/// A record along with its precomputed sort key.
+/// `run` breaks ties when merging, which keeps the sort stable.
+#[derive(Debug)]
+struct SortItem {
+    key: Vec<(KeyValue, bool)>,
+    run: usize,
+    record: StringRecord,
+}
+
+impl SortItem {
+    fn new(record: StringRecord, keys: &[(usize, &SortKey)], run: usize) -> Self {
+        let key = keys
+            .iter()
+            .map(|(index, key)| {
+                let value = record.get(*index).unwrap_or("");
+                let value = match key.numeric {
+                    true => match value.trim().parse::<f64>() {
+                        Ok(number) => KeyValue::Number(number),
+                        Err(_) => KeyValue::Text(value.to_string()),
+                    },
+                    false => KeyValue::Text(value.to_string()),
+                };
+                (value, key.descending)
+            })
+            .collect();
+        Self { key, run, record }
+    }
+}
+
+impl Ord for SortItem {
+    fn cmp(&self, other: &Self) -> Ordering {
+        for ((a, descending), (b, _)) in self.key.iter().zip(&other.key) {
+            let ordering = match descending {
+                true => b.cmp(a),
+                false => a.cmp(b),
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        self.run.cmp(&other.run)
+    }
+}
+
+impl PartialOrd for SortItem {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for SortItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SortItem {}
+
+/// Sorts a chunk of records and writes it, without headers, to a temporary run file.
+fn write_run(path: &Path, chunk: &mut Vec<SortItem>) -> Result<(), TracebackError> {
+    chunk.sort();
+    let mut wtr = match Writer::from_path(path) {
+        Ok(wtr) => wtr,
+        Err(e) => {
+            return Err(traceback!("Failed to create sort run file")
+                .with_extra_data(json!({ "error": e.to_string() })))
+        }
+    };
+    for item in chunk.drain(..) {
+        if let Err(e) = wtr.write_record(&item.record) {
+            return Err(traceback!("Failed to write sort run record")
+                .with_extra_data(json!({ "error": e.to_string() })));
+        }
+    }
+    match wtr.flush() {
+        Ok(_) => Ok(()),
+        Err(e) => Err(traceback!("Failed to flush sort run file")
+            .with_extra_data(json!({ "error": e.to_string() }))),
+    }
+}
+
+/// Removes rows whose key columns repeat an earlier row, keeping the first occurrence.
+///
+/// Only the keys are held in memory, so the file itself can be larger than memory.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use utils::csv_ops::{dedup_csv_file, OutputFormat};
+///
+/// dedup_csv_file("customers.csv", "customers_unique.csv", &["email"], OutputFormat::Csv).unwrap();
+/// ```
+pub fn dedup_csv_file(
+    input: &str,
+    output: &str,
+    key_columns: &[&str],
+    format: OutputFormat,
+) -> Result<(), TracebackError> {
+    let mut rdr = match open_reader(input) {
+        Ok(rdr) => rdr,
+        Err(e) => return Err(traceback!(err e, "Failed to open CSV file to deduplicate")),
+    };
+    let headers = match read_headers(&mut rdr) {
+        Ok(headers) => headers,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    let key_indices = match column_indices(&headers, key_columns) {
+        Ok(indices) => indices,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    let mut sink = match Sink::create(output, format, &headers) {
+        Ok(sink) => sink,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    let mut seen = HashSet::new();
+    for result in rdr.records() {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => return Err(record_error(e, input)),
+        };
+        if !seen.insert(record_key(&record, &key_indices)) {
+            continue;
+        }
+        if let Err(e) = sink.write(&record) {
+            return Err(traceback!(err e));
+        }
+    }
+    sink.finish()
+}
+
+/// Which rows `join_csv_files` keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JoinKind {
+    /// Only left rows with a matching right row.
+    #[default]
+    Inner,
+    /// Every left row. Right columns are empty for left rows without a match.
+    Left,
+}
+
+/// Options for `join_csv_files`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct JoinOptions {
+    /// The key columns in the left file.
+    pub left_on: Vec<String>,
+    /// The key columns in the right file, in the same order as `left_on`.
+    pub right_on: Vec<String>,
+    pub kind: JoinKind,
+    pub output: OutputFormat,
+}
+
+/// The suffix added to right columns whose name is already used by a left column.
+pub const RIGHT_COLUMN_SUFFIX: &str = "_right";
+
+/// Joins two CSV files on key columns, writing the result to `output`.
+///
+/// The right file is loaded into memory and the left file is streamed, so the right file should be the smaller one.
+/// The output has every left column, followed by every right column except the key columns.
+/// Right columns named like an earlier column get `RIGHT_COLUMN_SUFFIX` appended until their name is unique.
+/// A left row matching several right rows is written once per match.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use utils::csv_ops::{join_csv_files, JoinKind, JoinOptions, OutputFormat};
+///
+/// let options = JoinOptions {
+///     left_on: vec!["customer_id".to_string()],
+///     right_on: vec!["id".to_string()],
+///     kind: JoinKind::Left,
+///     output: OutputFormat::Json,
+/// };
+/// join_csv_files("orders.csv", "customers.csv", "orders_with_customers.json", &options).unwrap();
+/// ```
+pub fn join_csv_files(
+    left: &str,
+    right: &str,
+    output: &str,
+    options: &JoinOptions,
+) -> Result<(), TracebackError> {
+    if options.left_on.len() != options.right_on.len() || options.left_on.is_empty() {
+        return Err(traceback!(
+            "Join needs the same, non-zero number of left and right key columns"
+        )
+        .with_extra_data(json!({
+            "left_on": options.left_on,
+            "right_on": options.right_on,
+        })));
+    }
+    let mut right_rdr = match open_reader(right) {
+        Ok(rdr) => rdr,
+        Err(e) => return Err(traceback!(err e, "Failed to open right CSV file")),
+    };
+    let right_headers = match read_headers(&mut right_rdr) {
+        Ok(headers) => headers,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    let right_on: Vec<&str> = options.right_on.iter().map(String::as_str).collect();
+    let right_keys = match column_indices(&right_headers, &right_on) {
+        Ok(indices) => indices,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    let mut right_rows: HashMap<Vec<String>, Vec<StringRecord>> = HashMap::new();
+    for result in right_rdr.records() {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => return Err(record_error(e, right)),
+        };
+        right_rows
+            .entry(record_key(&record, &right_keys))
+            .or_default()
+            .push(record);
+    }
+
+    let mut left_rdr = match open_reader(left) {
+        Ok(rdr) => rdr,
+        Err(e) => return Err(traceback!(err e, "Failed to open left CSV file")),
+    };
+    let left_headers = match read_headers(&mut left_rdr) {
+        Ok(headers) => headers,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    let left_on: Vec<&str> = options.left_on.iter().map(String::as_str).collect();
+    let left_keys = match column_indices(&left_headers, &left_on) {
+        Ok(indices) => indices,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    let right_columns: Vec<usize> = (0..right_headers.len())
+        .filter(|i| !right_keys.contains(i))
+        .collect();
+    let mut headers = left_headers.clone();
+    for &i in &right_columns {
+        let mut name = right_headers[i].clone();
+        while headers.contains(&name) {
+            name.push_str(RIGHT_COLUMN_SUFFIX);
+        }
+        headers.push(name);
+    }
+
+    let mut sink = match Sink::create(output, options.output, &headers) {
+        Ok(sink) => sink,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    let empty_right = StringRecord::new();
+    for result in left_rdr.records() {
+        let left_record = match result {
+            Ok(record) => record,
+            Err(e) => return Err(record_error(e, left)),
+        };
+        let matches = right_rows.get(&record_key(&left_record, &left_keys));
+        let matches: Vec<&StringRecord> = match (matches, options.kind) {
+            (Some(matches), _) => matches.iter().collect(),
+            (None, JoinKind::Left) => vec![&empty_right],
+            (None, JoinKind::Inner) => continue,
+        };
+        for right_record in matches {
+            let mut joined = left_record.clone();
+            for &i in &right_columns {
+                joined.push_field(right_record.get(i).unwrap_or(""));
+            }
+            if let Err(e) = sink.write(&joined) {
+                return Err(traceback!(err e));
+            }
+        }
+    }
+    sink.finish()
+}
+
+/// How large `split_csv_file` lets each chunk grow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitLimit {
+    /// At most this many data rows per chunk, not counting the repeated header row.
+    Rows(usize),
+    /// At most this many bytes per chunk, including the header row.
+    /// A single record larger than the limit still gets a chunk of its own.
+    /// Bytes are counted before compression.
+    Bytes(u64),
+}
+
+/// A file written by `split_csv_file`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitChunk {
+    pub path: String,
+    /// The zero-based index of the chunk's first data row in the input file.
+    pub first_row: usize,
+    pub row_count: usize,
+    /// The size of the chunk before compression.
+    pub byte_count: u64,
+}
+
+/// The files `split_csv_file` produced, in order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitManifest {
+    pub source: String,
+    pub headers: Vec<String>,
+    pub row_count: usize,
+    pub chunks: Vec<SplitChunk>,
+}
+
+impl SplitManifest {
+    pub fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+}
+
+/// Splits a CSV file into smaller CSV files, each starting with the header row of the input.
+///
+/// The input is streamed a record at a time, and records are always written whole,
+/// so quoted fields spanning several lines are never cut in two.
+/// Chunks are written to `output_dir` as `<input file stem>_<chunk index>.csv`, creating the directory if needed.
+/// If the input is named like a compressed file, such as `orders.csv.gz`, the chunks are compressed the same way.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use utils::csv_ops::{split_csv_file, SplitLimit};
+///
+/// let manifest = split_csv_file("orders.csv", "orders_split", SplitLimit::Rows(10_000)).unwrap();
+/// for chunk in &manifest.chunks {
+///     println!("{}: {} rows", chunk.path, chunk.row_count);
+/// }
+/// ```
+pub fn split_csv_file(
+    input: &str,
+    output_dir: &str,
+    limit: SplitLimit,
+) -> Result<SplitManifest, TracebackError> {
+    let mut rdr = match open_reader(input) {
+        Ok(rdr) => rdr,
+        Err(e) => return Err(traceback!(err e, "Failed to open CSV file to split")),
+    };
+    let headers = match read_headers(&mut rdr) {
+        Ok(headers) => headers,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    if let Err(e) = create_dir_all(output_dir) {
+        return Err(traceback!("Failed to create output directory")
+            .with_extra_data(json!({ "error": e.to_string(), "path": output_dir })));
+    }
+    // Chunks are compressed the same way as the input file
+    let (uncompressed, compression) = strip_compression_extension(input);
+    let stem = Path::new(uncompressed)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| "chunk".to_string());
+    let header_bytes = match encode_record(&headers) {
+        Ok(bytes) => bytes,
+        Err(e) => return Err(traceback!(err e)),
+    };
+
+    let mut manifest = SplitManifest {
+        source: input.to_string(),
+        headers,
+        row_count: 0,
+        chunks: vec![],
+    };
+    let mut current: Option<(CompressedWriter, SplitChunk)> = None;
+    for result in rdr.records() {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => return Err(record_error(e, input)),
+        };
+        let bytes = match encode_record(&record) {
+            Ok(bytes) => bytes,
+            Err(e) => return Err(traceback!(err e)),
+        };
+        let full = match (&current, limit) {
+            (None, _) => true,
+            (Some((_, chunk)), SplitLimit::Rows(rows)) => chunk.row_count >= rows.max(1),
+            (Some((_, chunk)), SplitLimit::Bytes(max)) => {
+                chunk.row_count > 0 && chunk.byte_count + bytes.len() as u64 > max
+            }
+        };
+        if full {
+            if let Some((writer, chunk)) = current.take() {
+                if let Err(e) = finish_chunk(writer, chunk, &mut manifest) {
+                    return Err(traceback!(err e));
+                }
+            }
+            let name = format!(
+                "{stem}_{}.csv{}",
+                manifest.chunks.len(),
+                compression.extension()
+            );
+            let path = Path::new(output_dir).join(name);
+            let path = path.to_string_lossy().to_string();
+            let mut writer = match CompressedWriter::create(&path) {
+                Ok(writer) => writer,
+                Err(e) => return Err(traceback!(err e, "Failed to create chunk file")),
+            };
+            if let Err(e) = writer.write_all(&header_bytes) {
+                return Err(traceback!("Failed to write chunk headers")
+                    .with_extra_data(json!({ "error": e.to_string(), "path": path })));
+            }
+            let chunk = SplitChunk {
+                path,
+                first_row: manifest.row_count,
+                row_count: 0,
+                byte_count: header_bytes.len() as u64,
+            };
+            current = Some((writer, chunk));
+        }
+        if let Some((writer, chunk)) = current.as_mut() {
+            if let Err(e) = writer.write_all(&bytes) {
+                return Err(traceback!("Failed to write chunk record")
+                    .with_extra_data(json!({ "error": e.to_string(), "path": chunk.path })));
+            }
+            chunk.row_count += 1;
+            chunk.byte_count += bytes.len() as u64;
+        }
+        manifest.row_count += 1;
+    }
+    if let Some((writer, chunk)) = current {
+        if let Err(e) = finish_chunk(writer, chunk, &mut manifest) {
+            return Err(traceback!(err e));
+        }
+    }
+    Ok(manifest)
+}
+
+/// Encodes a single record the way `csv::Writer` would write it, line terminator included.
+fn encode_record<I, T>(record: I) -> Result<Vec<u8>, TracebackError>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<[u8]>,
+{
+    let mut wtr = Writer::from_writer(vec![]);
+    if let Err(e) = wtr.write_record(record) {
+        return Err(traceback!("Failed to encode CSV record")
+            .with_extra_data(json!({ "error": e.to_string() })));
+    }
+    match wtr.into_inner() {
+        Ok(bytes) => Ok(bytes),
+        Err(e) => Err(traceback!("Failed to encode CSV record")
+            .with_extra_data(json!({ "error": e.to_string() }))),
+    }
+}
+
+fn finish_chunk(
+    writer: CompressedWriter,
+    chunk: SplitChunk,
+    manifest: &mut SplitManifest,
+) -> Result<(), TracebackError> {
+    if let Err(e) = writer.finish() {
+        return Err(traceback!(err e, "Failed to finish chunk file")
+            .with_extra_data(json!({ "path": chunk.path })));
+    }
+    manifest.chunks.push(chunk);
+    Ok(())
+}
+
+/// Where the operations in this module write their rows.
+enum Sink {
+    Csv(Box<Writer<CompressedWriter>>),
+    Json {
+        writer: CompressedWriter,
+        headers: Vec<String>,
+        first: bool,
+    },
+}
+
+impl Sink {
+    fn create(
+        path: &str,
+        format: OutputFormat,
+        headers: &[String],
+    ) -> Result<Self, TracebackError> {
+        let file = match CompressedWriter::create(path) {
+            Ok(file) => file,
+            Err(e) => return Err(traceback!(err e, "Failed to create output file")),
+        };
+        match format {
+            OutputFormat::Csv => {
+                let mut wtr = Writer::from_writer(file);
+                match wtr.write_record(headers) {
+                    Ok(_) => Ok(Sink::Csv(Box::new(wtr))),
+                    Err(e) => Err(traceback!("Failed to write CSV headers")
+                        .with_extra_data(json!({ "error": e.to_string(), "path": path }))),
+                }
+            }
+            OutputFormat::Json => {
+                let mut writer = file;
+                match writer.write_all(b"[") {
+                    Ok(_) => Ok(Sink::Json {
+                        writer,
+                        headers: headers.to_vec(),
+                        first: true,
+                    }),
+                    Err(e) => Err(traceback!("Failed to write to output file")
+                        .with_extra_data(json!({ "error": e.to_string(), "path": path }))),
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, record: &StringRecord) -> Result<(), TracebackError> {
+        match self {
+            Sink::Csv(wtr) => match wtr.write_record(record) {
+                Ok(_) => Ok(()),
+                Err(e) => Err(traceback!("Failed to write CSV record")
+                    .with_extra_data(json!({ "error": e.to_string() }))),
+            },
+            Sink::Json {
+                writer,
+                headers,
+                first,
+            } => {
+                let obj: Map<String, Value> = headers
+                    .iter()
+                    .enumerate()
+                    .map(|(i, header)| {
+                        let value = record.get(i).unwrap_or("");
+                        (header.clone(), Value::String(value.to_string()))
+                    })
+                    .collect();
+                let separator: &[u8] = if *first { b"" } else { b"," };
+                *first = false;
+                let result = writer
+                    .write_all(separator)
+                    .map_err(serde_json::Error::io)
+                    .and_then(|_| serde_json::to_writer(&mut *writer, &obj));
+                match result {
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(traceback!("Failed to write JSON record")
+                        .with_extra_data(json!({ "error": e.to_string() }))),
+                }
+            }
+        }
+    }
+
+    fn finish(self) -> Result<(), TracebackError> {
+        let writer = match self {
+            Sink::Csv(wtr) => wtr.into_inner().map_err(|e| e.to_string()),
+            Sink::Json { mut writer, .. } => match writer.write_all(b"]") {
+                Ok(_) => Ok(writer),
+                Err(e) => Err(e.to_string()),
+            },
+        };
+        match writer {
+            Ok(writer) => writer.finish(),
+            Err(e) => {
+                Err(traceback!("Failed to flush output file")
+                    .with_extra_data(json!({ "error": e })))
+            }
+        }
+    }
+}
+
+fn open_reader(path: &str) -> Result<Reader<Box<dyn Read>>, TracebackError> {
+    match open_file(path) {
+        Ok(file) => Ok(ReaderBuilder::new().from_reader(file)),
+        Err(e) => Err(traceback!(err e, "Failed to read CSV file")),
+    }
+}
+
+fn read_headers<R: Read>(rdr: &mut Reader<R>) -> Result<Vec<String>, TracebackError> {
+    match rdr.headers() {
+        Ok(headers) => Ok(headers.iter().map(str::to_string).collect()),
+        Err(e) => Err(traceback!("Failed to read CSV headers")
+            .with_extra_data(json!({ "error": e.to_string() }))),
+    }
+}
+
+fn record_error(e: csv::Error, path: &str) -> TracebackError {
+    let line = e.position().map(|pos| pos.line());
+    traceback!("Failed to read CSV record").with_extra_data(json!({
+        "error": e.to_string(),
+        "path": path,
+        "line": line,
+    }))
+}
+
+fn column_index(headers: &[String], column: &str) -> Result<usize, TracebackError> {
+    match headers.iter().position(|header| header == column) {
+        Some(index) => Ok(index),
+        None => Err(traceback!(format!("Column {column} does not exist"))
+            .with_extra_data(json!({ "column": column, "headers": headers }))),
+    }
+}
+
+fn column_indices(headers: &[String], columns: &[&str]) -> Result<Vec<usize>, TracebackError> {
+    columns
+        .iter()
+        .map(|column| column_index(headers, column))
+        .collect()
+}
+
+fn record_key(record: &StringRecord, indices: &[usize]) -> Vec<String> {
+    indices
+        .iter()
+        .map(|&i| record.get(i).unwrap_or("").to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_to_string, write};
+
+    use super::*;
+
+    #[test]
+    fn test_external_sort() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.csv");
+        let output = dir.path().join("output.csv");
+        write(&input, "name,score\nd,10\na,9\nc,10\nb,x\ne,100\n").unwrap();
+        let options = SortOptions {
+            keys: vec![SortKey::desc("score").numeric(), SortKey::asc("name")],
+            max_rows_in_memory: 2,
+            output: OutputFormat::Csv,
+        };
+        sort_csv_file(input.to_str().unwrap(), output.to_str().unwrap(), &options).unwrap();
+        assert_eq!(
+            read_to_string(&output).unwrap(),
+            "name,score\nb,x\ne,100\nc,10\nd,10\na,9\n"
+        );
+    }
+
+    #[test]
+    fn test_dedup() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.csv");
+        let output = dir.path().join("output.json");
+        write(
+            &input,
+            "id,email\n1,a@example.com\n2,b@example.com\n3,a@example.com\n",
+        )
+        .unwrap();
+        dedup_csv_file(
+            input.to_str().unwrap(),
+            output.to_str().unwrap(),
+            &["email"],
+            OutputFormat::Json,
+        )
+        .unwrap();
+        let json: Value = serde_json::from_str(&read_to_string(&output).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            json!([
+                {"id": "1", "email": "a@example.com"},
+                {"id": "2", "email": "b@example.com"}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_join() {
+        let dir = tempfile::tempdir().unwrap();
+        let left = dir.path().join("orders.csv");
+        let right = dir.path().join("customers.csv");
+        let output = dir.path().join("joined.csv");
+        write(
+            &left,
+            "order,customer,name\n1,c1,book\n2,c2,pen\n3,c1,ink\n",
+        )
+        .unwrap();
+        write(&right, "id,name\nc1,Alice\nc3,Carol\n").unwrap();
+        let mut options = JoinOptions {
+            left_on: vec!["customer".to_string()],
+            right_on: vec!["id".to_string()],
+            ..Default::default()
+        };
+        let paths = (
+            left.to_str().unwrap(),
+            right.to_str().unwrap(),
+            output.to_str().unwrap(),
+        );
+        join_csv_files(paths.0, paths.1, paths.2, &options).unwrap();
+        assert_eq!(
+            read_to_string(&output).unwrap(),
+            "order,customer,name,name_right\n1,c1,book,Alice\n3,c1,ink,Alice\n"
+        );
+        options.kind = JoinKind::Left;
+        join_csv_files(paths.0, paths.1, paths.2, &options).unwrap();
+        assert_eq!(
+            read_to_string(&output).unwrap(),
+            "order,customer,name,name_right\n1,c1,book,Alice\n2,c2,pen,\n3,c1,ink,Alice\n"
+        );
+    }
+
+    #[test]
+    fn test_join_renames_until_unique() {
+        let dir = tempfile::tempdir().unwrap();
+        let left = dir.path().join("left.csv");
+        let right = dir.path().join("right.csv");
+        let output = dir.path().join("joined.csv");
+        write(&left, "id,name,name_right\n1,a,b\n").unwrap();
+        write(&right, "id,name,name_right\n1,c,d\n").unwrap();
+        let options = JoinOptions {
+            left_on: vec!["id".to_string()],
+            right_on: vec!["id".to_string()],
+            ..Default::default()
+        };
+        join_csv_files(
+            left.to_str().unwrap(),
+            right.to_str().unwrap(),
+            output.to_str().unwrap(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(
+            read_to_string(&output).unwrap(),
+            "id,name,name_right,name_right_right,name_right_right_right\n1,a,b,c,d\n"
+        );
+    }
+
+    #[test]
+    fn test_split_by_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("notes.csv");
+        let output = dir.path().join("chunks");
+        write(&input, "id,note\n1,a\n2,\"multi\nline\"\n3,c\n").unwrap();
+        let manifest = split_csv_file(
+            input.to_str().unwrap(),
+            output.to_str().unwrap(),
+            SplitLimit::Rows(2),
+        )
+        .unwrap();
+        assert_eq!(manifest.row_count, 3);
+        assert_eq!(manifest.chunks.len(), 2);
+        assert_eq!(manifest.chunks[1].first_row, 2);
+        assert_eq!(
+            read_to_string(&manifest.chunks[0].path).unwrap(),
+            "id,note\n1,a\n2,\"multi\nline\"\n"
+        );
+        assert_eq!(
+            read_to_string(&manifest.chunks[1].path).unwrap(),
+            "id,note\n3,c\n"
+        );
+    }
+
+    #[test]
+    fn test_split_by_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("notes.csv");
+        let output = dir.path().join("chunks");
+        write(&input, "id,note\n1,aaaa\n2,bbbb\n3,a much longer note\n").unwrap();
+        let manifest = split_csv_file(
+            input.to_str().unwrap(),
+            output.to_str().unwrap(),
+            SplitLimit::Bytes(20),
+        )
+        .unwrap();
+        let rows: Vec<usize> = manifest.chunks.iter().map(|c| c.row_count).collect();
+        assert_eq!(rows, vec![1, 1, 1]);
+        for chunk in &manifest.chunks {
+            let contents = read_to_string(&chunk.path).unwrap();
+            assert!(contents.starts_with("id,note\n"));
+            assert_eq!(contents.len() as u64, chunk.byte_count);
+        }
+    }
+
+    #[test]
+    fn test_split_compressed() {
+        use crate::compression::{read_file, write_file};
+
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("notes.csv.gz");
+        let output = dir.path().join("chunks");
+        write_file(input.to_str().unwrap(), b"id,note\n1,a\n2,b\n3,c\n").unwrap();
+        let manifest = split_csv_file(
+            input.to_str().unwrap(),
+            output.to_str().unwrap(),
+            SplitLimit::Rows(2),
+        )
+        .unwrap();
+        assert!(manifest.chunks[0].path.ends_with("notes_0.csv.gz"));
+        assert_eq!(
+            read_file(&manifest.chunks[1].path).unwrap(),
+            b"id,note\n3,c\n"
+        );
+    }
+}
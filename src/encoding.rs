@@ -0,0 +1,254 @@
+use chardetng::EncodingDetector;
+use encoding_rs::{DecoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use serde_json::json;
+
+use traceback_error::{traceback, TracebackError};
+
+use crate::compression::read_file;
+
+/// How many bytes the heuristics look at when there is no BOM.
+const SNIFF_LENGTH: usize = 64 * 1024;
+
+/// The encoding `detect_encoding` settled on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DetectedEncoding {
+    pub encoding: &'static Encoding,
+    /// The length of the byte order mark at the start of the input, 0 if there is none.
+    pub bom_length: usize,
+}
+
+/// Works out which encoding some text is in.
+///
+/// A byte order mark wins if there is one. Otherwise the input is checked, in order, for
+/// - UTF-16 without a BOM, recognised by every other byte being zero, as is the case for mostly-ASCII text,
+/// - valid UTF-8,
+/// - any other encoding, guessed by `chardetng` from byte frequencies. This is usually Windows-1252 for our files.
+pub fn detect_encoding(bytes: &[u8]) -> DetectedEncoding {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(bytes) {
+        return DetectedEncoding {
+            encoding,
+            bom_length,
+        };
+    }
+    let sample = &bytes[..bytes.len().min(SNIFF_LENGTH)];
+    let encoding = match detect_utf16(sample) {
+        Some(encoding) => encoding,
+        None if is_utf8(sample, bytes.len() > SNIFF_LENGTH) => UTF_8,
+        None => {
+            let mut detector = EncodingDetector::new();
+            detector.feed(sample, sample.len() == bytes.len());
+            detector.guess(None, false)
+        }
+    };
+    DetectedEncoding {
+        encoding,
+        bom_length: 0,
+    }
+}
+
+/// Guesses UTF-16 from where the zero bytes are.
+fn detect_utf16(sample: &[u8]) -> Option<&'static Encoding> {
+    if sample.len() < 4 {
+        return None;
+    }
+    let pairs = sample.len() / 2;
+    let even_zeros = sample.iter().step_by(2).filter(|&&b| b == 0).count();
+    let odd_zeros = sample
+        .iter()
+        .skip(1)
+        .step_by(2)
+        .filter(|&&b| b == 0)
+        .count();
+    if odd_zeros * 10 >= pairs * 3 && even_zeros * 10 < pairs {
+        Some(UTF_16LE)
+    } else if even_zeros * 10 >= pairs * 3 && odd_zeros * 10 < pairs {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// Checks whether `sample` is UTF-8.
+/// If the sample was cut off from a longer input, a multi-byte character cut in two at the end is allowed.
+fn is_utf8(sample: &[u8], truncated: bool) -> bool {
+    match std::str::from_utf8(sample) {
+        Ok(_) => true,
+        Err(e) => truncated && e.error_len().is_none(),
+    }
+}
+
+/// Decodes text in any encoding `detect_encoding` recognises to a UTF-8 `String`, dropping any BOM.
+///
+/// Fails if the input contains bytes that are invalid in the detected encoding,
+/// instead of silently replacing them.
+///
+/// ## Example
+///
+/// ```rust
+/// use utils::encoding::decode_to_utf8;
+///
+/// // "café" in Windows-1252
+/// let text = decode_to_utf8(b"name\ncaf\xe9\n").unwrap();
+/// assert_eq!(text, "name\ncafé\n");
+/// ```
+pub fn decode_to_utf8(bytes: &[u8]) -> Result<String, TracebackError> {
+    let detected = detect_encoding(bytes);
+    decode_with(bytes, detected.encoding)
+}
+
+/// Decodes text in a known encoding to a UTF-8 `String`, dropping any BOM for that encoding.
+///
+/// Invalid input is an error reporting the offset and value of the first invalid bytes.
+pub fn decode_with(bytes: &[u8], encoding: &'static Encoding) -> Result<String, TracebackError> {
+    let (text, had_errors) = encoding.decode_with_bom_removal(bytes);
+    if had_errors {
+        let (offset, invalid) = first_invalid_bytes(bytes, encoding).unwrap_or((0, &[]));
+        let invalid: Vec<String> = invalid.iter().map(|b| format!("0x{b:02X}")).collect();
+        return Err(traceback!(format!(
+            "Input contains bytes that are invalid in {}: {} at byte {offset}",
+            encoding.name(),
+            invalid.join(" ")
+        ))
+        .with_extra_data(json!({
+            "encoding": encoding.name(),
+            "offset": offset,
+            "bytes": invalid,
+        })));
+    }
+    Ok(text.into_owned())
+}
+
+/// Finds the first malformed sequence in `bytes`, returning its offset and its bytes.
+fn first_invalid_bytes<'a>(
+    bytes: &'a [u8],
+    encoding: &'static Encoding,
+) -> Option<(usize, &'a [u8])> {
+    let mut decoder = encoding.new_decoder_with_bom_removal();
+    let mut text =
+        String::with_capacity(decoder.max_utf8_buffer_length_without_replacement(bytes.len())?);
+    let (result, read) = decoder.decode_to_string_without_replacement(bytes, &mut text, true);
+    match result {
+        // `read` includes the malformed sequence and the bytes consumed after it
+        DecoderResult::Malformed(length, consumed) => {
+            let end = read - consumed as usize;
+            let start = end - length as usize;
+            Some((start, &bytes[start..end]))
+        }
+        DecoderResult::InputEmpty | DecoderResult::OutputFull => None,
+    }
+}
+
+/// Reads a file, decompressing it if needed, and decodes it to UTF-8, detecting its encoding with `detect_encoding`.
+pub fn read_file_to_utf8(path: &str) -> Result<String, TracebackError> {
+    let bytes = match read_file(path) {
+        Ok(bytes) => bytes,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    match decode_to_utf8(&bytes) {
+        Ok(text) => Ok(text),
+        Err(e) => {
+            Err(traceback!(err e, "Failed to decode file").with_extra_data(json!({ "path": path })))
+        }
+    }
+}
+
+/// The encoding to write text output in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputEncoding {
+    /// UTF-8 without a BOM.
+    #[default]
+    Utf8,
+    /// UTF-8 with a BOM, which Excel needs to not read the file as the system code page.
+    Utf8Bom,
+    /// UTF-16 little-endian with a BOM, which is what Excel itself writes for "Unicode Text".
+    Utf16Le,
+}
+
+impl OutputEncoding {
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        match self {
+            OutputEncoding::Utf8 => text.as_bytes().to_vec(),
+            OutputEncoding::Utf8Bom => {
+                let mut bytes = Vec::with_capacity(text.len() + 3);
+                bytes.extend_from_slice(&[0xEF, 0xBB, 0xBF]);
+                bytes.extend_from_slice(text.as_bytes());
+                bytes
+            }
+            // encoding_rs only decodes UTF-16, so encode it by hand
+            OutputEncoding::Utf16Le => {
+                let mut bytes = Vec::with_capacity(text.len() * 2 + 2);
+                bytes.extend_from_slice(&[0xFF, 0xFE]);
+                for unit in text.encode_utf16() {
+                    bytes.extend_from_slice(&unit.to_le_bytes());
+                }
+                bytes
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::WINDOWS_1252;
+
+    use super::*;
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|unit| unit.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_detect_bom() {
+        let detected = detect_encoding(&OutputEncoding::Utf16Le.encode("a,b\n"));
+        assert_eq!(detected.encoding, UTF_16LE);
+        assert_eq!(detected.bom_length, 2);
+        let detected = detect_encoding(&OutputEncoding::Utf8Bom.encode("a,b\n"));
+        assert_eq!(detected.encoding, UTF_8);
+        assert_eq!(detected.bom_length, 3);
+    }
+
+    #[test]
+    fn test_detect_without_bom() {
+        assert_eq!(
+            detect_encoding("navn,by\nJøran,Tromsø\n".as_bytes()).encoding,
+            UTF_8
+        );
+        assert_eq!(
+            detect_encoding(&utf16le("name,city\nJøran,Tromsø\n")).encoding,
+            UTF_16LE
+        );
+        let (bytes, _, _) = WINDOWS_1252.encode("name,city\nJøran,Tromsø\nRené,Orléans\n");
+        assert_eq!(detect_encoding(&bytes).encoding, WINDOWS_1252);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let text = "name,city\nJøran,Tromsø\n";
+        for encoding in [
+            OutputEncoding::Utf8,
+            OutputEncoding::Utf8Bom,
+            OutputEncoding::Utf16Le,
+        ] {
+            assert_eq!(decode_to_utf8(&encoding.encode(text)).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn test_invalid_bytes() {
+        let mut err = decode_with(b"name\ncaf\xe9\n", UTF_8).unwrap_err();
+        err.is_handled = true;
+        assert_eq!(
+            err.message,
+            "Input contains bytes that are invalid in UTF-8: 0xE9 at byte 8"
+        );
+        assert_eq!(err.extra_data[0]["offset"], 8);
+        assert_eq!(err.extra_data[0]["bytes"], json!(["0xE9"]));
+
+        // An odd byte at the end of UTF-16 can't be a code unit
+        let mut err = decode_with(&[0xFF, 0xFE, b'a', 0, b'b'], UTF_16LE).unwrap_err();
+        err.is_handled = true;
+        assert_eq!(err.extra_data[0]["offset"], 4);
+    }
+}
@@ -0,0 +1,953 @@
+use calamine::{open_workbook_auto, DataType, Range, Reader};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Timelike};
+use rust_xlsxwriter::{Format, Workbook, Worksheet, XlsxError};
+use serde_json::{json, Map, Number, Value};
+
+use traceback_error::{traceback, TracebackError};
+
+use crate::csv2json::{finish_csv_writer, new_csv_writer};
+
+/// Selects a worksheet in a workbook, either by name or by its zero-based position.
+///
+/// `&str`, `String` and `usize` all convert into a `Sheet`,
+/// so `excel_file_to_json("file.xlsx", "Sheet1")` and `excel_file_to_json("file.xlsx", 0)` both work.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Sheet {
+    Name(String),
+    Index(usize),
+}
+
+impl From<&str> for Sheet {
+    fn from(name: &str) -> Self {
+        Sheet::Name(name.to_string())
+    }
+}
+
+impl From<String> for Sheet {
+    fn from(name: String) -> Self {
+        Sheet::Name(name)
+    }
+}
+
+impl From<usize> for Sheet {
+    fn from(index: usize) -> Self {
+        Sheet::Index(index)
+    }
+}
+
+impl std::fmt::Display for Sheet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Sheet::Name(name) => write!(f, "{name}"),
+            Sheet::Index(index) => write!(f, "#{index}"),
+        }
+    }
+}
+
+/// Lists the names of all worksheets in a workbook, in workbook order.
+///
+/// The file format (xlsx, xlsm, xlsb, xls or ods) is detected from the extension,
+/// falling back to trying every format.
+pub fn list_excel_sheets(path: &str) -> Result<Vec<String>, TracebackError> {
+    let workbook = match open_workbook_auto(path) {
+        Ok(workbook) => workbook,
+        Err(e) => {
+            return Err(traceback!("Failed to open workbook")
+                .with_extra_data(json!({ "error": e.to_string(), "path": path })))
+        }
+    };
+    Ok(workbook.sheet_names().to_vec())
+}
+
+/// Reads a worksheet from a workbook into a `calamine::Range`.
+pub fn read_excel_range(
+    path: &str,
+    sheet: impl Into<Sheet>,
+) -> Result<Range<DataType>, TracebackError> {
+    let sheet = sheet.into();
+    let mut workbook = match open_workbook_auto(path) {
+        Ok(workbook) => workbook,
+        Err(e) => {
+            return Err(traceback!("Failed to open workbook")
+                .with_extra_data(json!({ "error": e.to_string(), "path": path })))
+        }
+    };
+    let range = match &sheet {
+        Sheet::Name(name) => workbook.worksheet_range(name),
+        Sheet::Index(index) => workbook.worksheet_range_at(*index),
+    };
+    match range {
+        Some(Ok(range)) => Ok(range),
+        Some(Err(e)) => Err(traceback!(format!("Failed to read worksheet {sheet}"))
+            .with_extra_data(json!({ "error": e.to_string(), "path": path }))),
+        None => Err(
+            traceback!(format!("Worksheet {sheet} does not exist")).with_extra_data(json!({
+                "path": path,
+                "sheets": workbook.sheet_names(),
+            })),
+        ),
+    }
+}
+
+/// Converts a worksheet of an Excel or OpenDocument spreadsheet into a `serde_json::Value`.
+///
+/// ## Arguments
+///
+/// * `path` - The path to the workbook. xlsx, xlsm, xlsb, xls and ods files are supported.
+/// * `sheet` - The worksheet to convert, either by name (`"Sheet1"`) or by zero-based index (`0`).
+///
+/// ## Returns
+///
+/// * `Result<serde_json::Value, TracebackError>` - A JSON array with one object per row,
+///   or a `TracebackError` if the workbook or worksheet can't be read.
+///
+/// ## Assumptions
+///
+/// - The first row of the worksheet is considered the header row.
+/// - Cells are converted as described in `cell_to_json`.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use utils::excel::excel_file_to_json;
+///
+/// let by_name = excel_file_to_json("report.xlsx", "Sales").unwrap();
+/// let by_index = excel_file_to_json("report.xlsx", 0).unwrap();
+/// ```
+pub fn excel_file_to_json(path: &str, sheet: impl Into<Sheet>) -> Result<Value, TracebackError> {
+    let range = match read_excel_range(path, sheet) {
+        Ok(range) => range,
+        Err(e) => return Err(traceback!(err e, "Failed to convert worksheet to json")),
+    };
+    Ok(range_to_json(&range))
+}
+
+/// Converts every worksheet of a workbook into JSON, see `excel_file_to_json`.
+///
+/// The returned map is keyed by sheet name, in workbook order.
+pub fn excel_file_to_json_all_sheets(path: &str) -> Result<Map<String, Value>, TracebackError> {
+    let mut workbook = match open_workbook_auto(path) {
+        Ok(workbook) => workbook,
+        Err(e) => {
+            return Err(traceback!("Failed to open workbook")
+                .with_extra_data(json!({ "error": e.to_string(), "path": path })))
+        }
+    };
+    let mut result = Map::new();
+    for sheet in workbook.sheet_names().to_vec() {
+        let range = match workbook.worksheet_range(&sheet) {
+            Some(Ok(range)) => range,
+            Some(Err(e)) => {
+                return Err(traceback!(format!("Failed to read worksheet {sheet}"))
+                    .with_extra_data(json!({ "error": e.to_string(), "path": path })))
+            }
+            None => continue,
+        };
+        result.insert(sheet, range_to_json(&range));
+    }
+    Ok(result)
+}
+
+/// A rectangular block of cells in A1 notation, e.g. `B2:F100`.
+///
+/// Positions are zero-based `(row, column)` pairs, like the ones used by `calamine::Range`.
+/// An `end` of `None` means the block extends to the last used cell of the worksheet,
+/// which is what a lone start cell such as `B2` parses into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CellRange {
+    pub start: (u32, u32),
+    pub end: Option<(u32, u32)>,
+}
+
+impl std::str::FromStr for CellRange {
+    type Err = TracebackError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = match s.split_once(':') {
+            Some((start, end)) => (start, Some(end)),
+            None => (s, None),
+        };
+        let start = match parse_cell_reference(start) {
+            Some(start) => start,
+            None => {
+                return Err(traceback!(format!("Invalid start cell in range {s}"))
+                    .with_extra_data(json!({ "range": s })))
+            }
+        };
+        let end = match end.map(parse_cell_reference) {
+            Some(Some(end)) => Some(end),
+            Some(None) => {
+                return Err(traceback!(format!("Invalid end cell in range {s}"))
+                    .with_extra_data(json!({ "range": s })))
+            }
+            None => None,
+        };
+        if let Some(end) = end {
+            if end.0 < start.0 || end.1 < start.1 {
+                return Err(traceback!(format!("Range {s} ends before it starts"))
+                    .with_extra_data(json!({ "range": s })));
+            }
+        }
+        Ok(CellRange { start, end })
+    }
+}
+
+/// Parses a cell reference such as `B2` or `$B$2` into a zero-based `(row, column)` pair.
+fn parse_cell_reference(reference: &str) -> Option<(u32, u32)> {
+    let reference = reference.trim().replace('$', "");
+    let split = reference.find(|c: char| c.is_ascii_digit())?;
+    let (letters, digits) = reference.split_at(split);
+    if letters.is_empty() || !letters.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let mut column: u32 = 0;
+    for c in letters.chars() {
+        let value = c.to_ascii_uppercase() as u32 - 'A' as u32 + 1;
+        column = column.checked_mul(26)?.checked_add(value)?;
+    }
+    let row: u32 = digits.parse().ok()?;
+    if row == 0 {
+        return None;
+    }
+    Some((row - 1, column - 1))
+}
+
+/// Options for reading a worksheet with `excel_file_to_csv` and `excel_file_to_json_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct ExcelReadOptions {
+    /// Only read this block of cells, e.g. `"B2:F100".parse()?`. Reads the whole used range when `None`.
+    pub range: Option<CellRange>,
+    /// Drop blank rows before the header row, which title rows above a table often leave behind.
+    pub skip_leading_blank_rows: bool,
+    /// Fill empty cells with the value of the cell above them.
+    ///
+    /// calamine doesn't report merged regions, and a merged block only stores its value
+    /// in its top-left cell, so this is how vertically merged cells get a value on every row.
+    pub fill_down: bool,
+    /// A `chrono` format string for dates and times, e.g. `"%d.%m.%Y"`.
+    /// Dates are written in ISO 8601 when `None`, see `format_excel_datetime`.
+    pub date_format: Option<String>,
+}
+
+/// Applies `options` to a worksheet, returning the cells that should be converted.
+///
+/// The first row of the returned range is the header row.
+pub fn prepare_range(range: &Range<DataType>, options: &ExcelReadOptions) -> Range<DataType> {
+    let (sheet_start, sheet_end) = match (range.start(), range.end()) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return Range::empty(),
+    };
+    let (start, end) = match options.range {
+        Some(selection) => {
+            let end = selection.end.unwrap_or(sheet_end);
+            // Clamp the end, so that `A1:Z1048576` doesn't allocate a million empty rows
+            let end = (end.0.min(sheet_end.0), end.1.min(sheet_end.1));
+            if selection.start.0 > end.0 || selection.start.1 > end.1 {
+                return Range::empty();
+            }
+            (selection.start, end)
+        }
+        None => (sheet_start, sheet_end),
+    };
+    let mut start_row = start.0;
+    if options.skip_leading_blank_rows {
+        while start_row <= end.0 && is_blank_row(range, start_row, start.1, end.1) {
+            start_row += 1;
+        }
+        if start_row > end.0 {
+            return Range::empty();
+        }
+    }
+    let mut selected = range.range((start_row, start.1), end);
+    if options.fill_down {
+        for column in start.1..=end.1 {
+            let mut previous = DataType::Empty;
+            // Start below the header row, so that headers never leak into the data
+            for row in start_row + 1..=end.0 {
+                match selected.get_value((row, column)) {
+                    Some(DataType::Empty) | None => {
+                        selected.set_value((row, column), previous.clone())
+                    }
+                    Some(value) => previous = value.clone(),
+                }
+            }
+        }
+    }
+    if let Some(date_format) = &options.date_format {
+        for row in start_row..=end.0 {
+            for column in start.1..=end.1 {
+                if let Some(DataType::DateTime(serial)) = selected.get_value((row, column)) {
+                    if let Some(datetime) = excel_serial_to_datetime(*serial) {
+                        let formatted = datetime.format(date_format).to_string();
+                        selected.set_value((row, column), DataType::String(formatted));
+                    }
+                }
+            }
+        }
+    }
+    selected
+}
+
+fn is_blank_row(range: &Range<DataType>, row: u32, start_column: u32, end_column: u32) -> bool {
+    (start_column..=end_column).all(|column| match range.get_value((row, column)) {
+        Some(DataType::String(s)) => s.trim().is_empty(),
+        Some(cell) => cell.is_empty(),
+        None => true,
+    })
+}
+
+/// Same as `excel_file_to_json`, but reads the worksheet according to `options`.
+pub fn excel_file_to_json_with_options(
+    path: &str,
+    sheet: impl Into<Sheet>,
+    options: &ExcelReadOptions,
+) -> Result<Value, TracebackError> {
+    let range = match read_excel_range(path, sheet) {
+        Ok(range) => range,
+        Err(e) => return Err(traceback!(err e, "Failed to convert worksheet to json")),
+    };
+    Ok(range_to_json(&prepare_range(&range, options)))
+}
+
+/// Converts a worksheet, or a block of cells in it, into a CSV-formatted string.
+///
+/// ## Arguments
+///
+/// * `path` - The path to the workbook. xlsx, xlsm, xlsb, xls and ods files are supported.
+/// * `sheet` - The worksheet to convert, either by name (`"Sheet1"`) or by zero-based index (`0`).
+/// * `options` - Which cells to read, and how to fill and format them.
+///
+/// ## Returns
+///
+/// * `Result<String, TracebackError>` - The CSV-formatted string, written with the same writer as `json_to_csv`.
+///   Cells are written as described in `cell_to_string`, empty cells as empty fields.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use utils::excel::{excel_file_to_csv, ExcelReadOptions};
+///
+/// let options = ExcelReadOptions {
+///     range: Some("B3:F100".parse().unwrap()),
+///     skip_leading_blank_rows: true,
+///     fill_down: true,
+///     date_format: Some("%d.%m.%Y".to_string()),
+/// };
+/// let csv = excel_file_to_csv("report.xlsx", "Sales", &options).unwrap();
+/// ```
+pub fn excel_file_to_csv(
+    path: &str,
+    sheet: impl Into<Sheet>,
+    options: &ExcelReadOptions,
+) -> Result<String, TracebackError> {
+    let range = match read_excel_range(path, sheet) {
+        Ok(range) => range,
+        Err(e) => return Err(traceback!(err e, "Failed to convert worksheet to CSV")),
+    };
+    range_to_csv(&prepare_range(&range, options))
+}
+
+/// Writes every row of a `calamine::Range` as a CSV record.
+pub fn range_to_csv(range: &Range<DataType>) -> Result<String, TracebackError> {
+    let mut wtr = new_csv_writer();
+    for (i, row) in range.rows().enumerate() {
+        let record = row
+            .iter()
+            .map(|cell| cell_to_string(cell).unwrap_or_default());
+        match wtr.write_record(record) {
+            Ok(_) => (),
+            Err(e) => {
+                return Err(traceback!("Failed to write CSV record")
+                    .with_extra_data(json!({ "error": e.to_string(), "row": i })))
+            }
+        };
+    }
+    finish_csv_writer(wtr)
+}
+
+/// Converts a `calamine::Range` into a JSON array of objects, using its first row as the header row.
+///
+/// Header cells are converted to strings. Empty header cells are named after their
+/// column letter (`A`, `B`, ..., `AA`, ...) and repeated headers get a suffix (`name`, `name_2`, ...),
+/// so that no data is lost.
+pub fn range_to_json(range: &Range<DataType>) -> Value {
+    let mut rows = range.rows();
+    let headers: Vec<String> = match rows.next() {
+        Some(header_row) => {
+            let start_column = range
+                .start()
+                .map(|(_, column)| column as usize)
+                .unwrap_or(0);
+            let named: Vec<String> = header_row
+                .iter()
+                .enumerate()
+                .map(|(i, cell)| match cell_to_string(cell) {
+                    Some(header) if !header.is_empty() => header,
+                    _ => column_name(start_column + i),
+                })
+                .collect();
+            dedupe_headers(named)
+        }
+        None => return Value::Array(vec![]),
+    };
+    let records = rows
+        .map(|row| {
+            let obj: Map<String, Value> = headers
+                .iter()
+                .zip(row)
+                .map(|(header, cell)| (header.clone(), cell_to_json(cell)))
+                .collect();
+            Value::Object(obj)
+        })
+        .collect();
+    Value::Array(records)
+}
+
+/// Renames repeated headers to `name_2`, `name_3` and so on, skipping names already taken by other headers.
+fn dedupe_headers(headers: Vec<String>) -> Vec<String> {
+    let mut deduped: Vec<String> = Vec::with_capacity(headers.len());
+    for header in &headers {
+        let mut name = header.clone();
+        let mut n = 1;
+        while deduped.contains(&name) || (n > 1 && headers.contains(&name)) {
+            n += 1;
+            name = format!("{header}_{n}");
+        }
+        deduped.push(name);
+    }
+    deduped
+}
+
+/// Converts a single spreadsheet cell into the matching JSON value.
+///
+/// - Integers, floats and booleans become JSON numbers and booleans.
+///   Floats without a fractional part become integers, since spreadsheets store all numbers as floats.
+/// - Dates and times become ISO 8601 strings, see `format_excel_datetime`.
+/// - Durations become a number of seconds.
+/// - Errors such as `#DIV/0!` become the string shown by the spreadsheet.
+/// - Empty cells become `null`.
+pub fn cell_to_json(cell: &DataType) -> Value {
+    match cell {
+        DataType::Int(i) => Value::from(*i),
+        DataType::Float(f) => float_to_json(*f),
+        DataType::String(s) => Value::String(s.clone()),
+        DataType::Bool(b) => Value::Bool(*b),
+        DataType::DateTime(serial) => match format_excel_datetime(*serial) {
+            Some(formatted) => Value::String(formatted),
+            None => float_to_json(*serial),
+        },
+        DataType::Duration(days) => float_to_json(days * 86_400.0),
+        DataType::DateTimeIso(s) => Value::String(s.clone()),
+        DataType::DurationIso(s) => Value::String(s.clone()),
+        DataType::Error(e) => Value::String(e.to_string()),
+        DataType::Empty => Value::Null,
+    }
+}
+
+/// Converts a spreadsheet cell into the text a user would see in it,
+/// or `None` for empty cells.
+pub fn cell_to_string(cell: &DataType) -> Option<String> {
+    match cell {
+        DataType::Empty => None,
+        DataType::String(s) | DataType::DateTimeIso(s) | DataType::DurationIso(s) => {
+            Some(s.clone())
+        }
+        other => match cell_to_json(other) {
+            Value::String(s) => Some(s),
+            value => Some(value.to_string()),
+        },
+    }
+}
+
+fn float_to_json(f: f64) -> Value {
+    if f.fract() == 0.0 && f.abs() < i64::MAX as f64 {
+        return Value::from(f as i64);
+    }
+    match Number::from_f64(f) {
+        Some(n) => Value::Number(n),
+        None => Value::Null,
+    }
+}
+
+/// The number of days between the 1900 and 1904 date systems.
+/// Add it to a serial from a 1904-based workbook to get the matching 1900-based serial.
+pub const EXCEL_1904_OFFSET: f64 = 1462.0;
+
+/// The serial of 1900-03-01. Excel counts a 1900-02-29 that never existed,
+/// so the serials before it are one day ahead of the calendar.
+const FIRST_SERIAL_AFTER_LEAP_BUG: f64 = 61.0;
+
+/// The date serial 0 stands for: 1899-12-30, or 1899-12-31 for serials before the fictitious leap day.
+fn excel_epoch(before_leap_bug: bool) -> Option<NaiveDateTime> {
+    let day = if before_leap_bug { 31 } else { 30 };
+    NaiveDate::from_ymd_opt(1899, 12, day)?.and_hms_opt(0, 0, 0)
+}
+
+/// Converts a spreadsheet serial date in the 1900 date system into a `chrono::NaiveDateTime`.
+///
+/// Serial 1 is 1900-01-01, and serials from 61 on count days since 1899-12-30.
+/// Like Excel, serials between them are corrected for the 1900-02-29 that Excel counts but that never existed,
+/// and serial 60, that fictitious day, returns `None`.
+///
+/// Workbooks using the 1904 date system are converted to the 1900 system by calamine when they are read,
+/// so only serials taken from elsewhere need `EXCEL_1904_OFFSET` added first.
+///
+/// Returns `None` for serials outside the range chrono can represent.
+pub fn excel_serial_to_datetime(serial: f64) -> Option<NaiveDateTime> {
+    if !serial.is_finite() || (60.0..FIRST_SERIAL_AFTER_LEAP_BUG).contains(&serial) {
+        return None;
+    }
+    let epoch = excel_epoch((1.0..FIRST_SERIAL_AFTER_LEAP_BUG).contains(&serial))?;
+    // Round to the millisecond, as spreadsheets can't store more precise times anyway
+    let millis = (serial * 86_400_000.0).round() as i64;
+    epoch.checked_add_signed(Duration::milliseconds(millis))
+}
+
+/// Formats a spreadsheet serial date as an ISO 8601 string.
+///
+/// - Serials without a time component become dates: `2023-09-14`.
+/// - Serials below 1 (times of day without a date) become times: `13:45:00`.
+/// - All other serials become date-times: `2023-09-14T13:45:00`.
+pub fn format_excel_datetime(serial: f64) -> Option<String> {
+    let datetime = excel_serial_to_datetime(serial)?;
+    let has_time = datetime.num_seconds_from_midnight() != 0 || datetime.nanosecond() != 0;
+    let formatted = if (0.0..1.0).contains(&serial) {
+        datetime.format("%H:%M:%S").to_string()
+    } else if has_time {
+        datetime.format("%Y-%m-%dT%H:%M:%S").to_string()
+    } else {
+        datetime.format("%Y-%m-%d").to_string()
+    };
+    Some(formatted)
+}
+
+/// Converts a `chrono::NaiveDateTime` into a spreadsheet serial date in the 1900 date system.
+///
+/// This is the inverse of `excel_serial_to_datetime`.
+pub fn datetime_to_excel_serial(datetime: NaiveDateTime) -> f64 {
+    let before_leap_bug = NaiveDate::from_ymd_opt(1900, 1, 1)
+        .zip(NaiveDate::from_ymd_opt(1900, 3, 1))
+        .is_some_and(|(start, end)| (start..end).contains(&datetime.date()));
+    let epoch = excel_epoch(before_leap_bug).unwrap_or_default();
+    let millis = datetime.signed_duration_since(epoch).num_milliseconds();
+    millis as f64 / 86_400_000.0
+}
+
+/// The name of the worksheet `json_to_xlsx` writes a plain array into.
+pub const DEFAULT_SHEET_NAME: &str = "Sheet1";
+
+/// The widest a column written by `json_to_xlsx` gets, in characters.
+const MAX_COLUMN_WIDTH: usize = 80;
+
+/// Writes JSON data into an Excel workbook at `path`.
+///
+/// ## Arguments
+///
+/// * `json` - Either an array of objects, which is written into a single worksheet named `Sheet1`,
+///   or an object whose values are arrays of objects, which writes one worksheet per key.
+/// * `path` - Where to save the workbook. The file is overwritten if it already exists.
+///
+/// ## Returns
+///
+/// * `Result<(), TracebackError>` - `Ok(())` if the workbook was written,
+///   or a `TracebackError` if `json` has the wrong shape or the file couldn't be saved.
+///
+/// ## Notes
+///
+/// - The first row of every worksheet is a bold header row, made of every key found in the objects.
+///   Objects missing a key get an empty cell.
+/// - Numbers and booleans are written as native cells. Strings in ISO 8601 date (`2023-09-14`)
+///   or date-time (`2023-09-14T13:45:00`) format are written as native dates.
+///   Nested arrays and objects are written as JSON strings, and `null` as empty cells.
+/// - Columns are sized to fit their content.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use serde_json::json;
+/// use utils::excel::json_to_xlsx;
+///
+/// let sales = json!({
+///     "North": [{"date": "2023-09-14", "amount": 1200.5, "paid": true}],
+///     "South": [{"date": "2023-09-15", "amount": 800, "paid": false}]
+/// });
+/// json_to_xlsx(&sales, "sales.xlsx").unwrap();
+/// ```
+pub fn json_to_xlsx(json: &Value, path: &str) -> Result<(), TracebackError> {
+    let mut workbook = match json_to_workbook(json) {
+        Ok(workbook) => workbook,
+        Err(e) => return Err(traceback!(err e, "Failed to convert json to workbook")),
+    };
+    match workbook.save(path) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(traceback!("Failed to save workbook")
+            .with_extra_data(json!({ "error": e.to_string(), "path": path }))),
+    }
+}
+
+/// Same as `json_to_xlsx`, but returns the workbook file as bytes instead of saving it.
+pub fn json_to_xlsx_buffer(json: &Value) -> Result<Vec<u8>, TracebackError> {
+    let mut workbook = match json_to_workbook(json) {
+        Ok(workbook) => workbook,
+        Err(e) => return Err(traceback!(err e, "Failed to convert json to workbook")),
+    };
+    match workbook.save_to_buffer() {
+        Ok(buffer) => Ok(buffer),
+        Err(e) => Err(traceback!("Failed to save workbook to buffer")
+            .with_extra_data(json!({ "error": e.to_string() }))),
+    }
+}
+
+fn json_to_workbook(json: &Value) -> Result<Workbook, TracebackError> {
+    let mut workbook = Workbook::new();
+    match json {
+        Value::Array(records) => {
+            if let Err(e) = write_worksheet(workbook.add_worksheet(), DEFAULT_SHEET_NAME, records) {
+                return Err(traceback!(err e));
+            }
+        }
+        Value::Object(sheets) => {
+            for (name, records) in sheets {
+                let records = match records.as_array() {
+                    Some(records) => records,
+                    None => {
+                        return Err(traceback!(format!(
+                            "Expected an array of objects for sheet {name}"
+                        ))
+                        .with_extra_data(json!({ "sheet": name, "value": records })))
+                    }
+                };
+                if let Err(e) = write_worksheet(workbook.add_worksheet(), name, records) {
+                    return Err(traceback!(err e));
+                }
+            }
+        }
+        _ => {
+            return Err(traceback!(
+                "Expected an array of objects, or an object of arrays of objects"
+            )
+            .with_extra_data(json!({ "json": json })))
+        }
+    }
+    Ok(workbook)
+}
+
+fn write_worksheet(
+    worksheet: &mut Worksheet,
+    name: &str,
+    records: &[Value],
+) -> Result<(), TracebackError> {
+    let xlsx_error = |e: XlsxError| {
+        traceback!(format!("Failed to write worksheet {name}"))
+            .with_extra_data(json!({ "error": e.to_string(), "sheet": name }))
+    };
+    if let Err(e) = worksheet.set_name(name) {
+        return Err(xlsx_error(e));
+    }
+    let mut headers: Vec<String> = Vec::new();
+    for (i, record) in records.iter().enumerate() {
+        let obj = match record.as_object() {
+            Some(obj) => obj,
+            None => {
+                return Err(
+                    traceback!(format!("Expected an object at index {i} of sheet {name}"))
+                        .with_extra_data(json!({ "sheet": name, "index": i, "value": record })),
+                )
+            }
+        };
+        for key in obj.keys() {
+            if !headers.contains(key) {
+                headers.push(key.clone());
+            }
+        }
+    }
+    if headers.len() > u16::MAX as usize || records.len() >= u32::MAX as usize {
+        return Err(
+            traceback!(format!("Sheet {name} is too large for a worksheet"))
+                .with_extra_data(json!({ "rows": records.len(), "columns": headers.len() })),
+        );
+    }
+
+    let header_format = Format::new().set_bold();
+    let date_format = Format::new().set_num_format("yyyy-mm-dd");
+    let datetime_format = Format::new().set_num_format("yyyy-mm-dd hh:mm:ss");
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for (col, header) in headers.iter().enumerate() {
+        if let Err(e) = worksheet.write_string_with_format(0, col as u16, header, &header_format) {
+            return Err(xlsx_error(e));
+        }
+    }
+    for (i, record) in records.iter().enumerate() {
+        let row = i as u32 + 1;
+        for (col, header) in headers.iter().enumerate() {
+            let col_num = col as u16;
+            let value = match record.get(header) {
+                Some(value) => value,
+                None => continue,
+            };
+            let (result, width) = match value {
+                Value::Null => continue,
+                Value::Bool(b) => (worksheet.write_boolean(row, col_num, *b), 5),
+                Value::Number(n) => (
+                    worksheet.write_number(row, col_num, n.as_f64().unwrap_or_default()),
+                    n.to_string().len(),
+                ),
+                Value::String(s) => match parse_date_string(s) {
+                    Some((serial, true)) => (
+                        worksheet.write_number_with_format(row, col_num, serial, &datetime_format),
+                        19,
+                    ),
+                    Some((serial, false)) => (
+                        worksheet.write_number_with_format(row, col_num, serial, &date_format),
+                        10,
+                    ),
+                    None => (worksheet.write_string(row, col_num, s), s.chars().count()),
+                },
+                Value::Array(_) | Value::Object(_) => {
+                    let s = value.to_string();
+                    let width = s.chars().count();
+                    (worksheet.write_string(row, col_num, s), width)
+                }
+            };
+            if let Err(e) = result {
+                return Err(xlsx_error(e));
+            }
+            widths[col] = widths[col].max(width);
+        }
+    }
+    for (col, width) in widths.iter().enumerate() {
+        // Leave some room for the filter buttons and padding Excel draws around text
+        let width = (*width + 2).min(MAX_COLUMN_WIDTH);
+        if let Err(e) = worksheet.set_column_width(col as u16, width as f64) {
+            return Err(xlsx_error(e));
+        }
+    }
+    Ok(())
+}
+
+/// Parses an ISO 8601 date or date-time string into a spreadsheet serial date.
+/// The returned boolean tells whether the string had a time component.
+fn parse_date_string(s: &str) -> Option<(f64, bool)> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Some((datetime_to_excel_serial(date.and_hms_opt(0, 0, 0)?), false));
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"] {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(s, format) {
+            return Some((datetime_to_excel_serial(datetime), true));
+        }
+    }
+    match DateTime::parse_from_rfc3339(s) {
+        Ok(datetime) => Some((datetime_to_excel_serial(datetime.naive_local()), true)),
+        Err(_) => None,
+    }
+}
+
+/// Converts a zero-based column index into its spreadsheet letter, e.g. `0` into `A` and `27` into `AB`.
+pub fn column_name(index: usize) -> String {
+    let mut name = Vec::new();
+    let mut n = index + 1;
+    while n > 0 {
+        let rem = (n - 1) % 26;
+        name.push(b'A' + rem as u8);
+        n = (n - 1) / 26;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn test_range() -> Range<DataType> {
+        let mut range = Range::new((0, 0), (2, 2));
+        range.set_value((0, 0), DataType::String("name".to_string()));
+        range.set_value((0, 1), DataType::String("joined".to_string()));
+        range.set_value((1, 0), DataType::String("alice".to_string()));
+        range.set_value((1, 1), DataType::DateTime(45183.0));
+        range.set_value((1, 2), DataType::Float(2.5));
+        range.set_value((2, 0), DataType::String("bob".to_string()));
+        range.set_value((2, 1), DataType::DateTime(45183.5));
+        range.set_value((2, 2), DataType::Error(calamine::CellErrorType::Div0));
+        range
+    }
+
+    #[test]
+    fn test_range_to_json() {
+        let expected = json!([
+            {"name": "alice", "joined": "2023-09-14", "C": 2.5},
+            {"name": "bob", "joined": "2023-09-14T12:00:00", "C": "#DIV/0!"}
+        ]);
+        assert_eq!(range_to_json(&test_range()), expected);
+    }
+
+    #[test]
+    fn test_range_to_json_dedupes_headers() {
+        let mut range = Range::new((0, 0), (1, 3));
+        for (column, header) in ["name", "name", "name_2", "name"].iter().enumerate() {
+            range.set_value((0, column as u32), DataType::String(header.to_string()));
+            range.set_value((1, column as u32), DataType::Int(column as i64));
+        }
+        assert_eq!(
+            range_to_json(&range),
+            json!([{"name": 0, "name_3": 1, "name_2": 2, "name_4": 3}])
+        );
+    }
+
+    #[test]
+    fn test_excel_serial_leap_bug() {
+        let date = |serial: f64| {
+            excel_serial_to_datetime(serial).map(|datetime| datetime.date().to_string())
+        };
+        assert_eq!(date(1.0).as_deref(), Some("1900-01-01"));
+        assert_eq!(date(59.5).as_deref(), Some("1900-02-28"));
+        assert_eq!(date(60.0), None);
+        assert_eq!(date(61.0).as_deref(), Some("1900-03-01"));
+        assert_eq!(date(45183.0).as_deref(), Some("2023-09-14"));
+        assert_eq!(date(EXCEL_1904_OFFSET).as_deref(), Some("1904-01-01"));
+        for serial in [1.0, 59.5, 61.0, 45183.25] {
+            let datetime = excel_serial_to_datetime(serial).unwrap();
+            assert_eq!(datetime_to_excel_serial(datetime), serial);
+        }
+    }
+
+    #[test]
+    fn test_read_workbook_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let xlsx = dir.path().join("people.xlsx");
+        let xlsx = xlsx.to_str().unwrap();
+        let people = json!([
+            {"born": "1990-05-01", "name": "alice", "score": 2.5},
+            {"born": "1985-11-30", "name": "bob", "score": 7}
+        ]);
+        json_to_xlsx(&people, xlsx).unwrap();
+        assert_eq!(list_excel_sheets(xlsx).unwrap(), [DEFAULT_SHEET_NAME]);
+        assert_eq!(
+            excel_file_to_json(xlsx, DEFAULT_SHEET_NAME).unwrap(),
+            people
+        );
+        assert_eq!(excel_file_to_json(xlsx, 0).unwrap(), people);
+        let mut err = excel_file_to_json(xlsx, "Missing").unwrap_err();
+        err.is_handled = true;
+        assert_eq!(
+            err.parent.as_ref().unwrap().message,
+            "Worksheet Missing does not exist"
+        );
+
+        let ods = dir.path().join("people.ods");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&ods).unwrap());
+        let stored =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("mimetype", stored).unwrap();
+        zip.write_all(b"application/vnd.oasis.opendocument.spreadsheet")
+            .unwrap();
+        zip.start_file("content.xml", zip::write::FileOptions::default())
+            .unwrap();
+        // calamine doesn't allow whitespace between cells
+        zip.write_all(ODS_CONTENT.replace('\n', "").as_bytes())
+            .unwrap();
+        zip.finish().unwrap();
+        assert_eq!(
+            excel_file_to_json(ods.to_str().unwrap(), "People").unwrap(),
+            json!([{"name": "alice", "born": "1990-05-01", "score": 2.5}])
+        );
+    }
+
+    const ODS_CONTENT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document-content xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0" xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0" office:version="1.2">
+<office:body><office:spreadsheet><table:table table:name="People">
+<table:table-row>
+<table:table-cell office:value-type="string"><text:p>name</text:p></table:table-cell>
+<table:table-cell office:value-type="string"><text:p>born</text:p></table:table-cell>
+<table:table-cell office:value-type="string"><text:p>score</text:p></table:table-cell>
+</table:table-row>
+<table:table-row>
+<table:table-cell office:value-type="string"><text:p>alice</text:p></table:table-cell>
+<table:table-cell office:value-type="date" office:date-value="1990-05-01"><text:p>01.05.1990</text:p></table:table-cell>
+<table:table-cell office:value-type="float" office:value="2.5"><text:p>2.5</text:p></table:table-cell>
+</table:table-row>
+</table:table></office:spreadsheet></office:body></office:document-content>"#;
+
+    #[test]
+    fn test_cell_to_json() {
+        assert_eq!(cell_to_json(&DataType::Float(3.0)), json!(3));
+        assert_eq!(cell_to_json(&DataType::Int(-7)), json!(-7));
+        assert_eq!(cell_to_json(&DataType::Bool(true)), json!(true));
+        assert_eq!(cell_to_json(&DataType::Duration(0.5)), json!(43200));
+        assert_eq!(cell_to_json(&DataType::DateTime(0.25)), json!("06:00:00"));
+        assert_eq!(cell_to_json(&DataType::Empty), Value::Null);
+    }
+
+    #[test]
+    fn test_parse_cell_range() {
+        let range: CellRange = "B2:F100".parse().unwrap();
+        assert_eq!(range.start, (1, 1));
+        assert_eq!(range.end, Some((99, 5)));
+        let range: CellRange = "$AA$3".parse().unwrap();
+        assert_eq!(
+            range,
+            CellRange {
+                start: (2, 26),
+                end: None
+            }
+        );
+    }
+
+    #[test]
+    fn test_range_to_csv_with_options() {
+        let mut range = Range::new((0, 0), (4, 1));
+        range.set_value((0, 0), DataType::String("Quarterly report".to_string()));
+        range.set_value((2, 0), DataType::String("region".to_string()));
+        range.set_value((2, 1), DataType::String("date".to_string()));
+        range.set_value((3, 0), DataType::String("north".to_string()));
+        range.set_value((3, 1), DataType::DateTime(45183.0));
+        range.set_value((4, 1), DataType::DateTime(45184.0));
+        let options = ExcelReadOptions {
+            range: Some("A2".parse().unwrap()),
+            skip_leading_blank_rows: true,
+            fill_down: true,
+            date_format: Some("%d.%m.%Y".to_string()),
+        };
+        let csv = range_to_csv(&prepare_range(&range, &options)).unwrap();
+        assert_eq!(csv, "region,date\nnorth,14.09.2023\nnorth,15.09.2023\n");
+    }
+
+    #[test]
+    fn test_json_to_xlsx_round_trip() {
+        let json = json!({
+            "North": [
+                {"region": "north", "date": "2023-09-14", "amount": 1200.5, "paid": true},
+                {"region": "north", "date": "2023-09-14T12:00:00", "amount": 800}
+            ],
+            "South": [{"region": "south", "tags": ["new"]}]
+        });
+        let buffer = json_to_xlsx_buffer(&json).unwrap();
+        let mut workbook =
+            calamine::open_workbook_auto_from_rs(std::io::Cursor::new(buffer)).unwrap();
+        assert_eq!(workbook.sheet_names(), ["North", "South"]);
+        let north = workbook.worksheet_range("North").unwrap().unwrap();
+        assert_eq!(
+            range_to_json(&north),
+            json!([
+                {"amount": 1200.5, "date": "2023-09-14", "paid": true, "region": "north"},
+                {"amount": 800, "date": "2023-09-14T12:00:00", "paid": null, "region": "north"}
+            ])
+        );
+        let south = workbook.worksheet_range("South").unwrap().unwrap();
+        assert_eq!(
+            range_to_json(&south),
+            json!([{"region": "south", "tags": "[\"new\"]"}])
+        );
+    }
+
+    #[test]
+    fn test_column_name() {
+        assert_eq!(column_name(0), "A");
+        assert_eq!(column_name(25), "Z");
+        assert_eq!(column_name(26), "AA");
+        assert_eq!(column_name(27), "AB");
+        assert_eq!(column_name(702), "AAA");
+    }
+}
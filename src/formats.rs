@@ -0,0 +1,467 @@
+use quick_xml::{
+    events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event},
+    Reader, Writer,
+};
+use serde_json::{json, Map, Number, Value};
+
+use traceback_error::{traceback, TracebackError};
+
+/// The key prefix the XML mapping uses for attributes.
+pub const XML_ATTRIBUTE_PREFIX: &str = "@";
+/// The key the XML mapping uses for the text of an element that also has attributes or children.
+pub const XML_TEXT_KEY: &str = "#text";
+
+/// Parses a TOML document into a `serde_json::Value`.
+///
+/// Dates and times become strings in their TOML (RFC 3339) form.
+pub fn toml_to_json(toml: &str) -> Result<Value, TracebackError> {
+    match toml.parse::<toml::Table>() {
+        Ok(table) => Ok(toml_value_to_json(toml::Value::Table(table))),
+        Err(e) => Err(traceback!("Failed to parse TOML")
+            .with_extra_data(json!({ "error": e.to_string(), "span": e.span() }))),
+    }
+}
+
+fn toml_value_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => Number::from_f64(f).map_or(Value::Null, Value::Number),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(dt) => Value::String(dt.to_string()),
+        toml::Value::Array(arr) => Value::Array(arr.into_iter().map(toml_value_to_json).collect()),
+        toml::Value::Table(table) => Value::Object(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, toml_value_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Serializes a JSON object as a TOML document.
+///
+/// TOML has no null, so a null anywhere in `json` is an error naming its path, like `$.server.port`.
+/// The top level must be an object, since a TOML document is always a table.
+///
+/// ## Example
+///
+/// ```rust
+/// use serde_json::json;
+/// use utils::formats::json_to_toml;
+///
+/// let toml = json_to_toml(&json!({"name": "utils", "server": {"port": 8080}})).unwrap();
+/// assert_eq!(toml, "name = \"utils\"\n\n[server]\nport = 8080\n");
+/// ```
+pub fn json_to_toml(json: &Value) -> Result<String, TracebackError> {
+    let table = match json_value_to_toml(json, "$") {
+        Ok(toml::Value::Table(table)) => table,
+        Ok(_) => {
+            return Err(
+                traceback!("Only JSON objects can be converted to a TOML document")
+                    .with_extra_data(json!({ "json": json.to_string() })),
+            )
+        }
+        Err(e) => return Err(traceback!(err e)),
+    };
+    match toml::to_string(&table) {
+        Ok(toml) => Ok(toml),
+        Err(e) => Err(traceback!("Failed to serialize TOML")
+            .with_extra_data(json!({ "error": e.to_string() }))),
+    }
+}
+
+fn json_value_to_toml(value: &Value, path: &str) -> Result<toml::Value, TracebackError> {
+    match value {
+        Value::Null => Err(traceback!(format!("TOML has no null, found one at {path}"))
+            .with_extra_data(json!({ "path": path }))),
+        Value::Bool(b) => Ok(toml::Value::Boolean(*b)),
+        Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) => Ok(toml::Value::Integer(i)),
+            (None, Some(f)) => Ok(toml::Value::Float(f)),
+            (None, None) => Err(traceback!(format!("Number at {path} does not fit in TOML"))
+                .with_extra_data(json!({ "path": path, "number": n }))),
+        },
+        Value::String(s) => Ok(toml::Value::String(s.clone())),
+        Value::Array(arr) => {
+            let mut items = Vec::with_capacity(arr.len());
+            for (i, item) in arr.iter().enumerate() {
+                match json_value_to_toml(item, &format!("{path}[{i}]")) {
+                    Ok(item) => items.push(item),
+                    Err(e) => return Err(traceback!(err e)),
+                }
+            }
+            Ok(toml::Value::Array(items))
+        }
+        Value::Object(obj) => {
+            let mut table = toml::Table::new();
+            for (key, item) in obj {
+                match json_value_to_toml(item, &format!("{path}.{key}")) {
+                    Ok(item) => {
+                        table.insert(key.clone(), item);
+                    }
+                    Err(e) => return Err(traceback!(err e)),
+                }
+            }
+            Ok(toml::Value::Table(table))
+        }
+    }
+}
+
+/// Parses a YAML document into a `serde_json::Value`.
+///
+/// Mapping keys must be strings, since JSON object keys are.
+pub fn yaml_to_json(yaml: &str) -> Result<Value, TracebackError> {
+    match serde_norway::from_str(yaml) {
+        Ok(value) => Ok(value),
+        Err(e) => {
+            let location = e
+                .location()
+                .map(|loc| json!({ "line": loc.line(), "column": loc.column() }));
+            Err(traceback!("Failed to parse YAML")
+                .with_extra_data(json!({ "error": e.to_string(), "location": location })))
+        }
+    }
+}
+
+/// Serializes any `serde_json::Value` as a YAML document.
+pub fn json_to_yaml(json: &Value) -> Result<String, TracebackError> {
+    match serde_norway::to_string(json) {
+        Ok(yaml) => Ok(yaml),
+        Err(e) => Err(traceback!("Failed to serialize YAML")
+            .with_extra_data(json!({ "error": e.to_string() }))),
+    }
+}
+
+/// Parses an XML document into a `serde_json::Value`.
+///
+/// The mapping is
+/// - the result is an object with the root element's name as its only key,
+/// - an element with neither attributes nor children becomes its text as a string, or null if it is empty,
+/// - any other element becomes an object, where
+///   - attributes are keys prefixed with `@` (`XML_ATTRIBUTE_PREFIX`),
+///   - the element's own text, if any, is under `#text` (`XML_TEXT_KEY`),
+///   - child elements are keys named after the element, and a child element that repeats becomes an array.
+///
+/// Names keep their namespace prefix, e.g. `soap:Envelope`. Comments and processing instructions are dropped,
+/// and every value stays a string, since XML doesn't say whether `1` is a number.
+///
+/// ## Example
+///
+/// ```rust
+/// use serde_json::json;
+/// use utils::formats::xml_to_json;
+///
+/// let xml = r#"<order id="7"><item>pen</item><item>ink</item><note/></order>"#;
+/// assert_eq!(
+///     xml_to_json(xml).unwrap(),
+///     json!({"order": {"@id": "7", "item": ["pen", "ink"], "note": null}})
+/// );
+/// ```
+pub fn xml_to_json(xml: &str) -> Result<Value, TracebackError> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+    // The elements that have been opened but not closed yet, with their attributes, children and text
+    let mut stack: Vec<(String, Map<String, Value>, String)> = vec![];
+    let mut root: Option<Value> = None;
+    loop {
+        let event = match reader.read_event() {
+            Ok(event) => event,
+            Err(e) => {
+                return Err(traceback!("Failed to parse XML").with_extra_data(json!({
+                    "error": e.to_string(),
+                    "position": reader.error_position(),
+                })))
+            }
+        };
+        let finished = match event {
+            Event::Start(start) => {
+                match xml_element_start(&start) {
+                    Ok((name, attributes)) => stack.push((name, attributes, String::new())),
+                    Err(e) => return Err(traceback!(err e)),
+                };
+                None
+            }
+            Event::Empty(start) => match xml_element_start(&start) {
+                Ok((name, attributes)) => Some((name, attributes, String::new())),
+                Err(e) => return Err(traceback!(err e)),
+            },
+            Event::End(_) => stack.pop(),
+            Event::Text(text) => {
+                let text = match text.unescape() {
+                    Ok(text) => text,
+                    Err(e) => {
+                        return Err(traceback!("Failed to unescape XML text")
+                            .with_extra_data(json!({ "error": e.to_string() })))
+                    }
+                };
+                if let Some((_, _, current)) = stack.last_mut() {
+                    current.push_str(&text);
+                }
+                None
+            }
+            Event::CData(data) => {
+                if let Some((_, _, current)) = stack.last_mut() {
+                    current.push_str(&String::from_utf8_lossy(&data));
+                }
+                None
+            }
+            Event::Eof => break,
+            _ => None,
+        };
+        let Some((name, mut map, text)) = finished else {
+            continue;
+        };
+        let value = match (map.is_empty(), text.is_empty()) {
+            (true, true) => Value::Null,
+            (true, false) => Value::String(text),
+            (false, true) => Value::Object(map),
+            (false, false) => {
+                map.insert(XML_TEXT_KEY.to_string(), Value::String(text));
+                Value::Object(map)
+            }
+        };
+        match stack.last_mut() {
+            Some((_, parent, _)) => match parent.get_mut(&name) {
+                // Element values are never arrays themselves, so an array means the element already repeated
+                Some(Value::Array(siblings)) => siblings.push(value),
+                Some(sibling) => {
+                    let first = sibling.take();
+                    *sibling = Value::Array(vec![first, value]);
+                }
+                None => {
+                    parent.insert(name, value);
+                }
+            },
+            None if root.is_some() => {
+                return Err(traceback!(format!(
+                    "XML document has more than one root element, found <{name}> after the first"
+                ))
+                .with_extra_data(json!({ "element": name, "position": reader.buffer_position() })))
+            }
+            None => root = Some(json!({ name: value })),
+        }
+    }
+    match root {
+        Some(root) => Ok(root),
+        None => Err(traceback!("XML document has no root element")),
+    }
+}
+
+fn xml_element_start(start: &BytesStart) -> Result<(String, Map<String, Value>), TracebackError> {
+    let name = String::from_utf8_lossy(start.name().as_ref()).to_string();
+    let mut attributes = Map::new();
+    for attribute in start.attributes() {
+        let attribute = match attribute {
+            Ok(attribute) => attribute,
+            Err(e) => {
+                return Err(traceback!("Failed to parse XML attribute")
+                    .with_extra_data(json!({ "error": e.to_string(), "element": name })))
+            }
+        };
+        let key = String::from_utf8_lossy(attribute.key.as_ref());
+        let value = match attribute.unescape_value() {
+            Ok(value) => value,
+            Err(e) => {
+                return Err(traceback!("Failed to unescape XML attribute")
+                    .with_extra_data(json!({ "error": e.to_string(), "element": name })))
+            }
+        };
+        attributes.insert(
+            format!("{XML_ATTRIBUTE_PREFIX}{key}"),
+            Value::String(value.to_string()),
+        );
+    }
+    Ok((name, attributes))
+}
+
+/// Serializes a `serde_json::Value` as an XML document, using the mapping described on `xml_to_json`.
+///
+/// `json` must be an object with a single key, the root element.
+/// Numbers and booleans are written as text, null becomes an empty element,
+/// and an array becomes one element per item. Arrays directly inside arrays can't be represented and are an error.
+pub fn json_to_xml(json: &Value) -> Result<String, TracebackError> {
+    let root = json
+        .as_object()
+        .filter(|obj| obj.len() == 1)
+        .and_then(|obj| obj.iter().next());
+    let (name, value) = match root {
+        Some(root) => root,
+        None => {
+            return Err(traceback!(
+                "XML needs a JSON object with exactly one key, the root element"
+            )
+            .with_extra_data(json!({ "json": json.to_string() })))
+        }
+    };
+    if value.is_array() {
+        return Err(traceback!("The XML root element can't be an array")
+            .with_extra_data(json!({ "path": format!("$.{name}") })));
+    }
+    let mut writer = Writer::new(vec![]);
+    let decl = Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None));
+    if let Err(e) = writer.write_event(decl) {
+        return Err(traceback!("Failed to write XML declaration")
+            .with_extra_data(json!({ "error": e.to_string() })));
+    }
+    if let Err(e) = write_xml_element(&mut writer, name, value, &format!("$.{name}")) {
+        return Err(traceback!(err e));
+    }
+    match String::from_utf8(writer.into_inner()) {
+        Ok(xml) => Ok(xml),
+        Err(e) => Err(traceback!("Failed to convert XML to string")
+            .with_extra_data(json!({ "error": e.to_string() }))),
+    }
+}
+
+fn write_xml_element(
+    writer: &mut Writer<Vec<u8>>,
+    name: &str,
+    value: &Value,
+    path: &str,
+) -> Result<(), TracebackError> {
+    let mut start = BytesStart::new(name);
+    let mut text = None;
+    let mut children = vec![];
+    match value {
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                let item_path = format!("{path}[{i}]");
+                if item.is_array() {
+                    return Err(traceback!(format!(
+                        "Nested arrays can't be converted to XML, found one at {item_path}"
+                    ))
+                    .with_extra_data(json!({ "path": item_path })));
+                }
+                if let Err(e) = write_xml_element(writer, name, item, &item_path) {
+                    return Err(traceback!(err e));
+                }
+            }
+            return Ok(());
+        }
+        Value::Object(obj) => {
+            for (key, item) in obj {
+                if let Some(attribute) = key.strip_prefix(XML_ATTRIBUTE_PREFIX) {
+                    match xml_scalar_text(item) {
+                        Some(item) => start.push_attribute((attribute, item.as_str())),
+                        None => {
+                            return Err(traceback!(format!(
+                                "XML attributes must be scalars, {path}.{key} is not"
+                            ))
+                            .with_extra_data(json!({ "path": format!("{path}.{key}") })))
+                        }
+                    }
+                } else if key == XML_TEXT_KEY {
+                    text = xml_scalar_text(item);
+                } else {
+                    children.push((key, item));
+                }
+            }
+        }
+        scalar => text = xml_scalar_text(scalar),
+    }
+    let result = if text.is_none() && children.is_empty() {
+        writer.write_event(Event::Empty(start))
+    } else {
+        writer.write_event(Event::Start(start))
+    };
+    if let Err(e) = result {
+        return Err(traceback!("Failed to write XML element")
+            .with_extra_data(json!({ "error": e.to_string(), "path": path })));
+    }
+    if text.is_none() && children.is_empty() {
+        return Ok(());
+    }
+    if let Some(text) = text {
+        if let Err(e) = writer.write_event(Event::Text(BytesText::new(&text))) {
+            return Err(traceback!("Failed to write XML text")
+                .with_extra_data(json!({ "error": e.to_string(), "path": path })));
+        }
+    }
+    for (key, item) in children {
+        if let Err(e) = write_xml_element(writer, key, item, &format!("{path}.{key}")) {
+            return Err(traceback!(err e));
+        }
+    }
+    match writer.write_event(Event::End(BytesEnd::new(name))) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(traceback!("Failed to write XML element")
+            .with_extra_data(json!({ "error": e.to_string(), "path": path }))),
+    }
+}
+
+/// The text of a scalar value, or `None` for null and anything that isn't a scalar.
+fn xml_scalar_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toml_round_trip() {
+        let toml = "title = \"config\"\ncreated = 2024-01-02T03:04:05Z\n\n[server]\nhosts = [\"a\", \"b\"]\nport = 8080\n";
+        let json = toml_to_json(toml).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "title": "config",
+                "created": "2024-01-02T03:04:05Z",
+                "server": {"hosts": ["a", "b"], "port": 8080}
+            })
+        );
+        assert_eq!(toml_to_json(&json_to_toml(&json).unwrap()).unwrap(), json);
+    }
+
+    #[test]
+    fn test_toml_null() {
+        let mut err = json_to_toml(&json!({"server": {"port": null}})).unwrap_err();
+        err.is_handled = true;
+        assert!(err.message.contains("$.server.port"));
+    }
+
+    #[test]
+    fn test_yaml_round_trip() {
+        let json =
+            json!({"name": "utils", "tags": ["csv", "json"], "nested": {"ok": true, "none": null}});
+        let yaml = json_to_yaml(&json).unwrap();
+        assert_eq!(yaml_to_json(&yaml).unwrap(), json);
+    }
+
+    #[test]
+    fn test_xml_round_trip() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?><soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/"><soap:Body><price currency="NOK">12.50</price><item>a &amp; b</item><item>c</item><empty/></soap:Body></soap:Envelope>"#;
+        let json = xml_to_json(xml).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "soap:Envelope": {
+                    "@xmlns:soap": "http://schemas.xmlsoap.org/soap/envelope/",
+                    "soap:Body": {
+                        "price": {"@currency": "NOK", "#text": "12.50"},
+                        "item": ["a & b", "c"],
+                        "empty": null
+                    }
+                }
+            })
+        );
+        assert_eq!(xml_to_json(&json_to_xml(&json).unwrap()).unwrap(), json);
+    }
+
+    #[test]
+    fn test_xml_with_several_roots() {
+        let mut err = xml_to_json("<first>1</first><second/>").unwrap_err();
+        err.is_handled = true;
+        assert_eq!(
+            err.message,
+            "XML document has more than one root element, found <second> after the first"
+        );
+        assert_eq!(err.extra_data[0]["element"], "second");
+    }
+}
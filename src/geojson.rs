@@ -1,8 +1,639 @@
+use std::{fmt, str::FromStr};
+
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
 use traceback_error::{
-    serde_json::{json, Value},
+    serde_json::{json, Map, Number, Value},
     traceback, TracebackError,
 };
 
+pub mod crs;
+pub mod index;
+pub mod measure;
+pub mod precision;
+pub mod stream;
+pub mod tabular;
+pub mod topojson;
+pub mod validate;
+pub mod wkb;
+pub mod wkt;
+
+/// A GeoJSON position: longitude, latitude and optionally altitude, in that order.
+pub type Position = Vec<f64>;
+
+/// A GeoJSON bounding box: all the minimums, then all the maximums, e.g. `[west, south, east, north]`.
+pub type Bbox = Vec<f64>;
+
+/// Any GeoJSON document.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeoJson {
+    Geometry(Geometry),
+    Feature(Feature),
+    FeatureCollection(FeatureCollection),
+}
+
+/// The coordinates of a geometry, by geometry type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeometryValue {
+    Point(Position),
+    MultiPoint(Vec<Position>),
+    LineString(Vec<Position>),
+    MultiLineString(Vec<Vec<Position>>),
+    /// The exterior ring followed by any holes.
+    Polygon(Vec<Vec<Position>>),
+    MultiPolygon(Vec<Vec<Vec<Position>>>),
+    GeometryCollection(Vec<Geometry>),
+}
+
+/// A GeoJSON geometry object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Geometry {
+    pub value: GeometryValue,
+    pub bbox: Option<Bbox>,
+    /// Members that aren't part of the GeoJSON spec, kept so they survive a round trip.
+    pub foreign_members: Option<Map<String, Value>>,
+}
+
+/// The `id` of a feature, which GeoJSON allows to be a string or a number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeatureId {
+    String(String),
+    Number(Number),
+}
+
+/// A GeoJSON feature: a geometry, which may be missing, along with its properties.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Feature {
+    pub id: Option<FeatureId>,
+    pub geometry: Option<Geometry>,
+    pub properties: Option<Map<String, Value>>,
+    pub bbox: Option<Bbox>,
+    pub foreign_members: Option<Map<String, Value>>,
+}
+
+/// A GeoJSON feature collection.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FeatureCollection {
+    pub features: Vec<Feature>,
+    pub bbox: Option<Bbox>,
+    pub foreign_members: Option<Map<String, Value>>,
+}
+
+impl GeometryValue {
+    /// The GeoJSON `type` of this geometry.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            GeometryValue::Point(_) => "Point",
+            GeometryValue::MultiPoint(_) => "MultiPoint",
+            GeometryValue::LineString(_) => "LineString",
+            GeometryValue::MultiLineString(_) => "MultiLineString",
+            GeometryValue::Polygon(_) => "Polygon",
+            GeometryValue::MultiPolygon(_) => "MultiPolygon",
+            GeometryValue::GeometryCollection(_) => "GeometryCollection",
+        }
+    }
+}
+
+impl Geometry {
+    pub fn new(value: GeometryValue) -> Self {
+        Self {
+            value,
+            bbox: None,
+            foreign_members: None,
+        }
+    }
+
+    /// Parses a geometry object. Errors name the JSON path of the offending value, like `$.coordinates[2]`.
+    pub fn from_json_value(value: &Value) -> Result<Self, TracebackError> {
+        parse_geometry(value, "$")
+    }
+
+    pub fn to_json_value(&self) -> Value {
+        let mut obj = Map::new();
+        obj.insert("type".to_string(), json!(self.value.type_name()));
+        match &self.value {
+            GeometryValue::Point(p) => obj.insert("coordinates".to_string(), position_to_json(p)),
+            GeometryValue::MultiPoint(ps) | GeometryValue::LineString(ps) => {
+                obj.insert("coordinates".to_string(), positions_to_json(ps))
+            }
+            GeometryValue::MultiLineString(lines) | GeometryValue::Polygon(lines) => obj.insert(
+                "coordinates".to_string(),
+                Value::Array(lines.iter().map(|l| positions_to_json(l)).collect()),
+            ),
+            GeometryValue::MultiPolygon(polygons) => obj.insert(
+                "coordinates".to_string(),
+                Value::Array(
+                    polygons
+                        .iter()
+                        .map(|rings| {
+                            Value::Array(rings.iter().map(|r| positions_to_json(r)).collect())
+                        })
+                        .collect(),
+                ),
+            ),
+            GeometryValue::GeometryCollection(geometries) => obj.insert(
+                "geometries".to_string(),
+                Value::Array(geometries.iter().map(Geometry::to_json_value).collect()),
+            ),
+        };
+        finish_object(obj, &self.bbox, &self.foreign_members)
+    }
+}
+
+impl From<GeometryValue> for Geometry {
+    fn from(value: GeometryValue) -> Self {
+        Geometry::new(value)
+    }
+}
+
+impl Feature {
+    /// Parses a feature object. Errors name the JSON path of the offending value, like `$.geometry.type`.
+    pub fn from_json_value(value: &Value) -> Result<Self, TracebackError> {
+        parse_feature(value, "$")
+    }
+
+    pub fn to_json_value(&self) -> Value {
+        let mut obj = Map::new();
+        obj.insert("type".to_string(), json!("Feature"));
+        match &self.id {
+            Some(FeatureId::String(id)) => obj.insert("id".to_string(), json!(id)),
+            Some(FeatureId::Number(id)) => obj.insert("id".to_string(), Value::Number(id.clone())),
+            None => None,
+        };
+        let geometry = match &self.geometry {
+            Some(geometry) => geometry.to_json_value(),
+            None => Value::Null,
+        };
+        obj.insert("geometry".to_string(), geometry);
+        let properties = match &self.properties {
+            Some(properties) => Value::Object(properties.clone()),
+            None => Value::Null,
+        };
+        obj.insert("properties".to_string(), properties);
+        finish_object(obj, &self.bbox, &self.foreign_members)
+    }
+
+    /// Gets a property by name, if the feature has properties and that one is among them.
+    pub fn property(&self, key: &str) -> Option<&Value> {
+        self.properties.as_ref().and_then(|p| p.get(key))
+    }
+}
+
+impl FeatureCollection {
+    /// Parses a feature collection. Errors name the JSON path of the offending value, like `$.features[3].geometry`.
+    pub fn from_json_value(value: &Value) -> Result<Self, TracebackError> {
+        parse_feature_collection(value, "$")
+    }
+
+    pub fn to_json_value(&self) -> Value {
+        let mut obj = Map::new();
+        obj.insert("type".to_string(), json!("FeatureCollection"));
+        obj.insert(
+            "features".to_string(),
+            Value::Array(self.features.iter().map(Feature::to_json_value).collect()),
+        );
+        finish_object(obj, &self.bbox, &self.foreign_members)
+    }
+}
+
+impl GeoJson {
+    /// Parses any GeoJSON object, picking the kind from its `type` member.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use utils::geojson::{GeoJson, GeometryValue};
+    ///
+    /// let geojson: GeoJson = r#"{"type": "Point", "coordinates": [10.75, 59.91]}"#.parse().unwrap();
+    /// match geojson {
+    ///     GeoJson::Geometry(geometry) => {
+    ///         assert_eq!(geometry.value, GeometryValue::Point(vec![10.75, 59.91]))
+    ///     }
+    ///     _ => unreachable!(),
+    /// }
+    /// ```
+    pub fn from_json_value(value: &Value) -> Result<Self, TracebackError> {
+        let result = match object_type(value, "$") {
+            Ok("Feature") => parse_feature(value, "$").map(GeoJson::Feature),
+            Ok("FeatureCollection") => {
+                parse_feature_collection(value, "$").map(GeoJson::FeatureCollection)
+            }
+            Ok(_) => parse_geometry(value, "$").map(GeoJson::Geometry),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(geojson) => Ok(geojson),
+            Err(e) => Err(traceback!(err e)),
+        }
+    }
+
+    pub fn to_json_value(&self) -> Value {
+        match self {
+            GeoJson::Geometry(geometry) => geometry.to_json_value(),
+            GeoJson::Feature(feature) => feature.to_json_value(),
+            GeoJson::FeatureCollection(collection) => collection.to_json_value(),
+        }
+    }
+}
+
+impl FromStr for GeoJson {
+    type Err = TracebackError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match traceback_error::serde_json::from_str::<Value>(s) {
+            Ok(value) => GeoJson::from_json_value(&value),
+            Err(e) => Err(
+                traceback!("Failed to parse GeoJSON").with_extra_data(json!({
+                    "error": e.to_string(),
+                    "line": e.line(),
+                    "column": e.column(),
+                })),
+            ),
+        }
+    }
+}
+
+impl fmt::Display for GeoJson {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_json_value())
+    }
+}
+
+/// Options for `GeoJson::to_string_with_options`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WriteOptions {
+    /// Round coordinates and bounding boxes to this many decimal places, see `precision::round_geojson`.
+    pub decimals: Option<u32>,
+    /// Indent the output instead of writing it on one line.
+    pub pretty: bool,
+}
+
+impl GeoJson {
+    /// Writes the GeoJSON as text, with coordinates as short as `options.decimals` allows.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use utils::geojson::{GeoJson, WriteOptions};
+    ///
+    /// let geojson: GeoJson = r#"{"type": "Point", "coordinates": [10.752245798, 59.913868212]}"#.parse().unwrap();
+    /// let options = WriteOptions { decimals: Some(5), ..Default::default() };
+    /// assert_eq!(
+    ///     geojson.to_string_with_options(&options),
+    ///     r#"{"coordinates":[10.75225,59.91387],"type":"Point"}"#
+    /// );
+    /// ```
+    pub fn to_string_with_options(&self, options: &WriteOptions) -> String {
+        let value = match options.decimals {
+            Some(decimals) => precision::round_geojson(self, decimals).to_json_value(),
+            None => self.to_json_value(),
+        };
+        match options.pretty {
+            true => traceback_error::serde_json::to_string_pretty(&value).unwrap_or_default(),
+            false => value.to_string(),
+        }
+    }
+}
+
+/// Implements `Serialize` and `Deserialize` through `to_json_value` and `from_json_value`,
+/// so the GeoJSON types can be nested in other serde types.
+macro_rules! impl_serde_via_json {
+    ($($ty:ty),*) => {
+        $(
+            impl Serialize for $ty {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    self.to_json_value().serialize(serializer)
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $ty {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    let value = Value::deserialize(deserializer)?;
+                    match <$ty>::from_json_value(&value) {
+                        Ok(parsed) => Ok(parsed),
+                        Err(mut e) => {
+                            // The message carries the path, so the TracebackError itself isn't needed
+                            e.is_handled = true;
+                            Err(D::Error::custom(e.message.clone()))
+                        }
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_serde_via_json!(GeoJson, Geometry, Feature, FeatureCollection);
+
+/// The error for a value that doesn't have the shape GeoJSON requires at `path`.
+fn invalid(message: &str, path: &str, value: &Value) -> TracebackError {
+    traceback!(format!("{message} at {path}")).with_extra_data(json!({
+        "path": path,
+        "found": value_kind(value),
+    }))
+}
+
+fn value_kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn object_type<'a>(value: &'a Value, path: &str) -> Result<&'a str, TracebackError> {
+    let obj = match value.as_object() {
+        Some(obj) => obj,
+        None => return Err(invalid("Expected a GeoJSON object", path, value)),
+    };
+    match obj.get("type") {
+        Some(Value::String(kind)) => Ok(kind),
+        Some(other) => Err(invalid("Expected a string", &format!("{path}.type"), other)),
+        None => Err(invalid("Missing type member", path, value)),
+    }
+}
+
+/// Collects the members of `obj` that aren't in `known`.
+fn foreign_members(obj: &Map<String, Value>, known: &[&str]) -> Option<Map<String, Value>> {
+    let foreign: Map<String, Value> = obj
+        .iter()
+        .filter(|(key, _)| !known.contains(&key.as_str()))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    match foreign.is_empty() {
+        true => None,
+        false => Some(foreign),
+    }
+}
+
+fn parse_bbox(obj: &Map<String, Value>, path: &str) -> Result<Option<Bbox>, TracebackError> {
+    match obj.get("bbox") {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => {
+            let path = format!("{path}.bbox");
+            let bbox = match parse_numbers(value, &path) {
+                Ok(bbox) => bbox,
+                Err(e) => return Err(traceback!(err e)),
+            };
+            if bbox.len() < 4 || bbox.len() % 2 != 0 {
+                return Err(invalid(
+                    "Expected a bbox with an even number of at least 4 numbers",
+                    &path,
+                    value,
+                ));
+            }
+            Ok(Some(bbox))
+        }
+    }
+}
+
+fn parse_numbers(value: &Value, path: &str) -> Result<Vec<f64>, TracebackError> {
+    let arr = match value.as_array() {
+        Some(arr) => arr,
+        None => return Err(invalid("Expected an array of numbers", path, value)),
+    };
+    let mut numbers = Vec::with_capacity(arr.len());
+    for (i, item) in arr.iter().enumerate() {
+        match item.as_f64() {
+            Some(number) => numbers.push(number),
+            None => return Err(invalid("Expected a number", &format!("{path}[{i}]"), item)),
+        }
+    }
+    Ok(numbers)
+}
+
+fn parse_position(value: &Value, path: &str) -> Result<Position, TracebackError> {
+    let position = match parse_numbers(value, path) {
+        Ok(position) => position,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    if position.len() < 2 {
+        return Err(invalid(
+            "Expected a position with at least 2 coordinates",
+            path,
+            value,
+        ));
+    }
+    Ok(position)
+}
+
+/// Parses an array of whatever `parse_item` parses, extending the path with each index.
+fn parse_array<T>(
+    value: &Value,
+    path: &str,
+    parse_item: impl Fn(&Value, &str) -> Result<T, TracebackError>,
+) -> Result<Vec<T>, TracebackError> {
+    let arr = match value.as_array() {
+        Some(arr) => arr,
+        None => return Err(invalid("Expected an array", path, value)),
+    };
+    let mut items = Vec::with_capacity(arr.len());
+    for (i, item) in arr.iter().enumerate() {
+        match parse_item(item, &format!("{path}[{i}]")) {
+            Ok(item) => items.push(item),
+            Err(e) => return Err(traceback!(err e)),
+        }
+    }
+    Ok(items)
+}
+
+fn parse_positions(value: &Value, path: &str) -> Result<Vec<Position>, TracebackError> {
+    parse_array(value, path, parse_position)
+}
+
+fn parse_rings(value: &Value, path: &str) -> Result<Vec<Vec<Position>>, TracebackError> {
+    parse_array(value, path, parse_positions)
+}
+
+fn parse_geometry(value: &Value, path: &str) -> Result<Geometry, TracebackError> {
+    let kind = match object_type(value, path) {
+        Ok(kind) => kind,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    let obj = match value.as_object() {
+        Some(obj) => obj,
+        None => return Err(invalid("Expected a geometry object", path, value)),
+    };
+    let (member, known): (&str, &[&str]) = match kind {
+        "GeometryCollection" => ("geometries", &["type", "geometries", "bbox"]),
+        _ => ("coordinates", &["type", "coordinates", "bbox"]),
+    };
+    let member_path = format!("{path}.{member}");
+    let member_value = match obj.get(member) {
+        Some(member_value) => member_value,
+        None => return Err(invalid(&format!("Missing {member} member"), path, value)),
+    };
+    let parsed = match kind {
+        "Point" => parse_position(member_value, &member_path).map(GeometryValue::Point),
+        "MultiPoint" => parse_positions(member_value, &member_path).map(GeometryValue::MultiPoint),
+        "LineString" => parse_positions(member_value, &member_path).map(GeometryValue::LineString),
+        "MultiLineString" => {
+            parse_rings(member_value, &member_path).map(GeometryValue::MultiLineString)
+        }
+        "Polygon" => parse_rings(member_value, &member_path).map(GeometryValue::Polygon),
+        "MultiPolygon" => {
+            parse_array(member_value, &member_path, parse_rings).map(GeometryValue::MultiPolygon)
+        }
+        "GeometryCollection" => parse_array(member_value, &member_path, parse_geometry)
+            .map(GeometryValue::GeometryCollection),
+        other => {
+            return Err(invalid(
+                &format!("Unknown geometry type {other}"),
+                &format!("{path}.type"),
+                value,
+            ))
+        }
+    };
+    let parsed = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    let bbox = match parse_bbox(obj, path) {
+        Ok(bbox) => bbox,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    Ok(Geometry {
+        value: parsed,
+        bbox,
+        foreign_members: foreign_members(obj, known),
+    })
+}
+
+fn parse_feature(value: &Value, path: &str) -> Result<Feature, TracebackError> {
+    match object_type(value, path) {
+        Ok("Feature") => {}
+        Ok(other) => {
+            return Err(invalid(
+                &format!("Expected a Feature, not a {other},"),
+                path,
+                value,
+            ))
+        }
+        Err(e) => return Err(traceback!(err e)),
+    }
+    let obj = match value.as_object() {
+        Some(obj) => obj,
+        None => return Err(invalid("Expected a feature object", path, value)),
+    };
+    let id = match obj.get("id") {
+        None | Some(Value::Null) => None,
+        Some(Value::String(id)) => Some(FeatureId::String(id.clone())),
+        Some(Value::Number(id)) => Some(FeatureId::Number(id.clone())),
+        Some(other) => {
+            return Err(invalid(
+                "Expected a string or number",
+                &format!("{path}.id"),
+                other,
+            ))
+        }
+    };
+    let geometry = match obj.get("geometry") {
+        None | Some(Value::Null) => None,
+        Some(geometry) => match parse_geometry(geometry, &format!("{path}.geometry")) {
+            Ok(geometry) => Some(geometry),
+            Err(e) => return Err(traceback!(err e)),
+        },
+    };
+    let properties = match obj.get("properties") {
+        None | Some(Value::Null) => None,
+        Some(Value::Object(properties)) => Some(properties.clone()),
+        Some(other) => {
+            return Err(invalid(
+                "Expected an object or null",
+                &format!("{path}.properties"),
+                other,
+            ))
+        }
+    };
+    let bbox = match parse_bbox(obj, path) {
+        Ok(bbox) => bbox,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    Ok(Feature {
+        id,
+        geometry,
+        properties,
+        bbox,
+        foreign_members: foreign_members(obj, &["type", "id", "geometry", "properties", "bbox"]),
+    })
+}
+
+fn parse_feature_collection(
+    value: &Value,
+    path: &str,
+) -> Result<FeatureCollection, TracebackError> {
+    match object_type(value, path) {
+        Ok("FeatureCollection") => {}
+        Ok(other) => {
+            return Err(invalid(
+                &format!("Expected a FeatureCollection, not a {other},"),
+                path,
+                value,
+            ))
+        }
+        Err(e) => return Err(traceback!(err e)),
+    }
+    let obj = match value.as_object() {
+        Some(obj) => obj,
+        None => return Err(invalid("Expected a feature collection object", path, value)),
+    };
+    let features = match obj.get("features") {
+        Some(features) => parse_array(features, &format!("{path}.features"), parse_feature),
+        None => return Err(invalid("Missing features member", path, value)),
+    };
+    let features = match features {
+        Ok(features) => features,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    let bbox = match parse_bbox(obj, path) {
+        Ok(bbox) => bbox,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    Ok(FeatureCollection {
+        features,
+        bbox,
+        foreign_members: foreign_members(obj, &["type", "features", "bbox"]),
+    })
+}
+
+/// Writes a coordinate, as an integer if it is a whole number, so `[10, 59]` round-trips unchanged.
+pub(crate) fn coordinate_to_json(coordinate: f64) -> Value {
+    if coordinate.fract() == 0.0 && coordinate.abs() < 9_007_199_254_740_992.0 {
+        json!(coordinate as i64)
+    } else {
+        Number::from_f64(coordinate).map_or(Value::Null, Value::Number)
+    }
+}
+
+pub(crate) fn position_to_json(position: &Position) -> Value {
+    Value::Array(position.iter().map(|c| coordinate_to_json(*c)).collect())
+}
+
+fn positions_to_json(positions: &[Position]) -> Value {
+    Value::Array(positions.iter().map(position_to_json).collect())
+}
+
+fn finish_object(
+    mut obj: Map<String, Value>,
+    bbox: &Option<Bbox>,
+    foreign_members: &Option<Map<String, Value>>,
+) -> Value {
+    if let Some(bbox) = bbox {
+        obj.insert(
+            "bbox".to_string(),
+            Value::Array(bbox.iter().map(|c| coordinate_to_json(*c)).collect()),
+        );
+    }
+    if let Some(foreign_members) = foreign_members {
+        for (key, value) in foreign_members {
+            obj.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+    }
+    Value::Object(obj)
+}
+
 /// Converts a vector of JSON `Value` objects representing coordinates to a nested vector of floating-point numbers.
 /// # Arguments
 /// * `coordinates` - A reference to a vector of JSON `Value` objects representing coordinates.
@@ -113,3 +744,69 @@ fn map_to_vec_inner(value: &Value) -> Result<Vec<f64>, TracebackError> {
     // Return the vector as a Result
     Ok(result_vec)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let value = json!({
+            "type": "FeatureCollection",
+            "bbox": [-10, -10, 10, 10],
+            "name": "test",
+            "features": [
+                {
+                    "type": "Feature",
+                    "id": 1,
+                    "geometry": {"type": "Point", "coordinates": [10.5, 59.9, 12]},
+                    "properties": {"name": "a"}
+                },
+                {
+                    "type": "Feature",
+                    "id": "b",
+                    "geometry": {
+                        "type": "GeometryCollection",
+                        "geometries": [
+                            {"type": "LineString", "coordinates": [[0, 0], [1, 1]]},
+                            {"type": "MultiPolygon", "coordinates": [[[[0, 0], [1, 0], [1, 1], [0, 0]]]]}
+                        ]
+                    },
+                    "properties": null
+                },
+                {"type": "Feature", "geometry": null, "properties": {}}
+            ]
+        });
+        let collection = FeatureCollection::from_json_value(&value).unwrap();
+        assert_eq!(collection.features.len(), 3);
+        assert_eq!(collection.features[0].property("name"), Some(&json!("a")));
+        assert_eq!(
+            collection.foreign_members,
+            Some(json!({"name": "test"}).as_object().unwrap().clone())
+        );
+        assert_eq!(collection.to_json_value(), value);
+
+        let parsed: GeoJson = value.to_string().parse().unwrap();
+        assert_eq!(parsed, GeoJson::FeatureCollection(collection));
+    }
+
+    #[test]
+    fn test_error_path() {
+        let value = json!({
+            "type": "FeatureCollection",
+            "features": [
+                {"type": "Feature", "geometry": null, "properties": null},
+                {
+                    "type": "Feature",
+                    "geometry": {"type": "Polygon", "coordinates": [[[0, 0], [1, "x"]]]},
+                    "properties": null
+                }
+            ]
+        });
+        let mut err = GeoJson::from_json_value(&value).unwrap_err();
+        err.is_handled = true;
+        assert!(err
+            .message
+            .ends_with("at $.features[1].geometry.coordinates[0][1][1]"));
+    }
+}
@@ -0,0 +1,443 @@
+use std::f64::consts::FRAC_PI_4;
+
+use traceback_error::{serde_json::json, traceback, TracebackError};
+
+use super::{
+    measure::{bbox, collection_bbox, try_map_positions},
+    Feature, FeatureCollection, Geometry, GeometryValue, Position,
+};
+
+/// The latitude where Web Mercator turns the world into a square, beyond which latitudes are clamped.
+pub const WEB_MERCATOR_MAX_LATITUDE: f64 = 85.051_128_779_806_59;
+
+/// The WGS84 ellipsoid.
+const WGS84_A: f64 = 6_378_137.0;
+const WGS84_F: f64 = 1.0 / 298.257_223_563;
+
+/// The scale factor on the central meridian of every UTM zone.
+const UTM_K0: f64 = 0.9996;
+const UTM_FALSE_EASTING: f64 = 500_000.0;
+/// Added to northings in the southern hemisphere, so they stay positive.
+const UTM_FALSE_NORTHING_SOUTH: f64 = 10_000_000.0;
+
+/// A coordinate reference system that geometries can be reprojected between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crs {
+    /// Longitude and latitude in degrees, EPSG:4326, the CRS of GeoJSON.
+    Wgs84,
+    /// Spherical Web Mercator in meters, EPSG:3857, as used by web map tiles.
+    WebMercator,
+    /// A Universal Transverse Mercator zone in meters on WGS84, EPSG:326xx in the north and 327xx in the south.
+    Utm { zone: u8, north: bool },
+}
+
+impl Crs {
+    /// Looks up a CRS by its EPSG code.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use utils::geojson::crs::Crs;
+    ///
+    /// assert_eq!(Crs::from_epsg(32633).unwrap(), Crs::Utm { zone: 33, north: true });
+    /// assert_eq!(Crs::Utm { zone: 33, north: true }.epsg(), 32633);
+    /// ```
+    pub fn from_epsg(code: u32) -> Result<Crs, TracebackError> {
+        match code {
+            4326 => Ok(Crs::Wgs84),
+            3857 | 900913 => Ok(Crs::WebMercator),
+            32601..=32660 => Ok(Crs::Utm {
+                zone: (code - 32600) as u8,
+                north: true,
+            }),
+            32701..=32760 => Ok(Crs::Utm {
+                zone: (code - 32700) as u8,
+                north: false,
+            }),
+            _ => Err(traceback!(format!("Unsupported CRS EPSG:{code}"))
+                .with_extra_data(json!({ "epsg": code }))),
+        }
+    }
+
+    /// The EPSG code of the CRS.
+    pub fn epsg(&self) -> u32 {
+        match self {
+            Crs::Wgs84 => 4326,
+            Crs::WebMercator => 3857,
+            Crs::Utm { zone, north: true } => 32600 + *zone as u32,
+            Crs::Utm { zone, north: false } => 32700 + *zone as u32,
+        }
+    }
+
+    /// The UTM zone a WGS84 longitude and latitude fall in,
+    /// including the wider zones around Norway and Svalbard.
+    pub fn utm_zone_for(lon: f64, lat: f64) -> Crs {
+        let lon = (lon + 180.0).rem_euclid(360.0) - 180.0;
+        let mut zone = ((lon + 180.0) / 6.0).floor() as u8 + 1;
+        if (56.0..64.0).contains(&lat) && (3.0..12.0).contains(&lon) {
+            zone = 32;
+        }
+        if (72.0..84.0).contains(&lat) && (0.0..42.0).contains(&lon) {
+            zone = match lon {
+                lon if lon < 9.0 => 31,
+                lon if lon < 21.0 => 33,
+                lon if lon < 33.0 => 35,
+                _ => 37,
+            };
+        }
+        Crs::Utm {
+            zone: zone.min(60),
+            north: lat >= 0.0,
+        }
+    }
+}
+
+/// Reprojects a position from one CRS to another. A third coordinate is kept as it is.
+///
+/// Latitudes beyond `WEB_MERCATOR_MAX_LATITUDE` are clamped when projecting to Web Mercator.
+/// UTM is computed with Krüger's series, accurate to about a millimeter within a few zones of the central meridian.
+///
+/// ## Example
+///
+/// ```rust
+/// use utils::geojson::crs::{reproject_position, Crs};
+///
+/// let utm = reproject_position(&vec![9.0, 0.0], Crs::Wgs84, Crs::Utm { zone: 32, north: true }).unwrap();
+/// assert!((utm[0] - 500_000.0).abs() < 1e-6 && utm[1].abs() < 1e-6);
+/// ```
+pub fn reproject_position(
+    position: &Position,
+    from: Crs,
+    to: Crs,
+) -> Result<Position, TracebackError> {
+    if position.len() < 2 {
+        return Err(traceback!("Position has fewer than 2 coordinates")
+            .with_extra_data(json!({ "position": position })));
+    }
+    let error = |message: &str| {
+        traceback!(format!("Failed to reproject position: {message}")).with_extra_data(json!({
+            "position": position,
+            "from": from.epsg(),
+            "to": to.epsg(),
+        }))
+    };
+    if position[..2].iter().any(|c| !c.is_finite()) {
+        return Err(error("coordinates are not finite"));
+    }
+    let (lon, lat) = match from {
+        Crs::Wgs84 => (position[0], position[1]),
+        Crs::WebMercator => web_mercator_to_wgs84(position[0], position[1]),
+        Crs::Utm { zone, north } => match utm_zone_is_valid(zone) {
+            true => utm_to_wgs84(position[0], position[1], zone, north),
+            false => return Err(error(&format!("UTM zone {zone} is not between 1 and 60"))),
+        },
+    };
+    if !(-90.0..=90.0).contains(&lat) {
+        return Err(error(&format!("latitude {lat} is outside -90 to 90")));
+    }
+    let (x, y) = match to {
+        Crs::Wgs84 => (lon, lat),
+        Crs::WebMercator => wgs84_to_web_mercator(lon, lat),
+        Crs::Utm { zone, north } => match utm_zone_is_valid(zone) {
+            true => wgs84_to_utm(lon, lat, zone, north),
+            false => return Err(error(&format!("UTM zone {zone} is not between 1 and 60"))),
+        },
+    };
+    if !x.is_finite() || !y.is_finite() {
+        return Err(error("the projection is undefined there"));
+    }
+    let mut reprojected = position.clone();
+    reprojected[0] = x;
+    reprojected[1] = y;
+    Ok(reprojected)
+}
+
+/// Reprojects every position of a geometry, see `reproject_position`.
+///
+/// Bounding boxes that were set are recomputed, in 2D, for the new coordinates.
+pub fn reproject_geometry(
+    geometry: &Geometry,
+    from: Crs,
+    to: Crs,
+) -> Result<Geometry, TracebackError> {
+    let mut reprojected =
+        match try_map_positions(geometry, &mut |p| reproject_position(p, from, to)) {
+            Ok(reprojected) => reprojected,
+            Err(e) => return Err(traceback!(err e)),
+        };
+    refresh_bbox(&mut reprojected);
+    Ok(reprojected)
+}
+
+/// Reprojects the geometry of every feature in a collection, see `reproject_geometry`.
+///
+/// Errors name the index of the feature that couldn't be reprojected.
+pub fn reproject_feature_collection(
+    collection: &FeatureCollection,
+    from: Crs,
+    to: Crs,
+) -> Result<FeatureCollection, TracebackError> {
+    let mut features = Vec::with_capacity(collection.features.len());
+    for (i, feature) in collection.features.iter().enumerate() {
+        let geometry = match &feature.geometry {
+            Some(geometry) => match reproject_geometry(geometry, from, to) {
+                Ok(geometry) => Some(geometry),
+                Err(e) => {
+                    return Err(
+                        traceback!(err e, format!("Failed to reproject feature {i}"))
+                            .with_extra_data(json!({ "feature": i })),
+                    )
+                }
+            },
+            None => None,
+        };
+        let bbox = match (&feature.bbox, &geometry) {
+            (Some(_), Some(geometry)) => bbox(geometry),
+            _ => None,
+        };
+        features.push(Feature {
+            geometry,
+            bbox,
+            ..feature.clone()
+        });
+    }
+    let mut reprojected = FeatureCollection {
+        features,
+        bbox: None,
+        foreign_members: collection.foreign_members.clone(),
+    };
+    if collection.bbox.is_some() {
+        reprojected.bbox = collection_bbox(&reprojected);
+    }
+    Ok(reprojected)
+}
+
+fn refresh_bbox(geometry: &mut Geometry) {
+    if geometry.bbox.is_some() {
+        geometry.bbox = bbox(geometry);
+    }
+    if let GeometryValue::GeometryCollection(geometries) = &mut geometry.value {
+        geometries.iter_mut().for_each(refresh_bbox);
+    }
+}
+
+fn utm_zone_is_valid(zone: u8) -> bool {
+    (1..=60).contains(&zone)
+}
+
+fn wgs84_to_web_mercator(lon: f64, lat: f64) -> (f64, f64) {
+    let lat = lat.clamp(-WEB_MERCATOR_MAX_LATITUDE, WEB_MERCATOR_MAX_LATITUDE);
+    let x = WGS84_A * lon.to_radians();
+    let y = WGS84_A * (FRAC_PI_4 + lat.to_radians() / 2.0).tan().ln();
+    (x, y)
+}
+
+fn web_mercator_to_wgs84(x: f64, y: f64) -> (f64, f64) {
+    let lon = (x / WGS84_A).to_degrees();
+    let lat = (2.0 * (y / WGS84_A).exp().atan() - 2.0 * FRAC_PI_4).to_degrees();
+    (lon, lat)
+}
+
+/// The constants of Krüger's series for the WGS84 ellipsoid, to the third order of the third flattening.
+struct Kruger {
+    /// The rectifying radius, scaled by the central meridian scale factor.
+    k0_a: f64,
+    alpha: [f64; 3],
+    beta: [f64; 3],
+    delta: [f64; 3],
+    /// `2 * sqrt(n) / (1 + n)`, which is the first eccentricity.
+    eccentricity: f64,
+}
+
+fn kruger() -> Kruger {
+    let n = WGS84_F / (2.0 - WGS84_F);
+    let (n2, n3) = (n * n, n * n * n);
+    Kruger {
+        k0_a: UTM_K0 * WGS84_A / (1.0 + n) * (1.0 + n2 / 4.0 + n2 * n2 / 64.0),
+        alpha: [
+            n / 2.0 - 2.0 * n2 / 3.0 + 5.0 * n3 / 16.0,
+            13.0 * n2 / 48.0 - 3.0 * n3 / 5.0,
+            61.0 * n3 / 240.0,
+        ],
+        beta: [
+            n / 2.0 - 2.0 * n2 / 3.0 + 37.0 * n3 / 96.0,
+            n2 / 48.0 + n3 / 15.0,
+            17.0 * n3 / 480.0,
+        ],
+        delta: [
+            2.0 * n - 2.0 * n2 / 3.0 - 2.0 * n3,
+            7.0 * n2 / 3.0 - 8.0 * n3 / 5.0,
+            56.0 * n3 / 15.0,
+        ],
+        eccentricity: 2.0 * n.sqrt() / (1.0 + n),
+    }
+}
+
+fn central_meridian(zone: u8) -> f64 {
+    (zone as f64 * 6.0 - 183.0).to_radians()
+}
+
+fn wgs84_to_utm(lon: f64, lat: f64, zone: u8, north: bool) -> (f64, f64) {
+    let k = kruger();
+    let phi = lat.to_radians();
+    let lambda = lon.to_radians() - central_meridian(zone);
+    let t = (phi.sin().atanh() - k.eccentricity * (k.eccentricity * phi.sin()).atanh()).sinh();
+    let xi = (t / lambda.cos()).atan();
+    let eta = (lambda.sin() / (1.0 + t * t).sqrt()).atanh();
+    let (mut easting, mut northing) = (eta, xi);
+    for (j, alpha) in (1..=3).zip(k.alpha) {
+        let j = 2.0 * j as f64;
+        easting += alpha * (j * xi).cos() * (j * eta).sinh();
+        northing += alpha * (j * xi).sin() * (j * eta).cosh();
+    }
+    let false_northing = match north {
+        true => 0.0,
+        false => UTM_FALSE_NORTHING_SOUTH,
+    };
+    (
+        UTM_FALSE_EASTING + k.k0_a * easting,
+        false_northing + k.k0_a * northing,
+    )
+}
+
+fn utm_to_wgs84(easting: f64, northing: f64, zone: u8, north: bool) -> (f64, f64) {
+    let k = kruger();
+    let false_northing = match north {
+        true => 0.0,
+        false => UTM_FALSE_NORTHING_SOUTH,
+    };
+    let xi = (northing - false_northing) / k.k0_a;
+    let eta = (easting - UTM_FALSE_EASTING) / k.k0_a;
+    let (mut xi_prime, mut eta_prime) = (xi, eta);
+    for (j, beta) in (1..=3).zip(k.beta) {
+        let j = 2.0 * j as f64;
+        xi_prime -= beta * (j * xi).sin() * (j * eta).cosh();
+        eta_prime -= beta * (j * xi).cos() * (j * eta).sinh();
+    }
+    let chi = (xi_prime.sin() / eta_prime.cosh()).asin();
+    let mut phi = chi;
+    for (j, delta) in (1..=3).zip(k.delta) {
+        phi += delta * (2.0 * j as f64 * chi).sin();
+    }
+    let lambda = central_meridian(zone) + (eta_prime.sinh() / xi_prime.cos()).atan();
+    (lambda.to_degrees(), phi.to_degrees())
+}
+
+#[cfg(test)]
+mod tests {
+    use traceback_error::serde_json::json;
+
+    use super::*;
+
+    fn assert_close(a: &Position, b: &[f64], tolerance: f64) {
+        assert!(
+            a.iter().zip(b).all(|(a, b)| (a - b).abs() < tolerance),
+            "{a:?} is not within {tolerance} of {b:?}"
+        );
+    }
+
+    #[test]
+    fn test_reproject_position() {
+        let web_mercator =
+            reproject_position(&vec![180.0, 0.0], Crs::Wgs84, Crs::WebMercator).unwrap();
+        assert_close(&web_mercator, &[20_037_508.342_789_244, 0.0], 1e-6);
+        let clamped = reproject_position(&vec![0.0, 89.0], Crs::Wgs84, Crs::WebMercator).unwrap();
+        assert_close(&clamped, &[0.0, 20_037_508.342_789_244], 1e-6);
+
+        // The Eiffel Tower
+        let eiffel = vec![2.294_481, 48.858_37, 330.0];
+        let utm = Crs::utm_zone_for(eiffel[0], eiffel[1]);
+        assert_eq!(
+            utm,
+            Crs::Utm {
+                zone: 31,
+                north: true
+            }
+        );
+        let projected = reproject_position(&eiffel, Crs::Wgs84, utm).unwrap();
+        assert_close(&projected, &[448_250.577, 5_411_951.588, 330.0], 1e-2);
+
+        let south = Crs::Utm {
+            zone: 23,
+            north: false,
+        };
+        let rio = vec![-43.2, -22.9];
+        let paris_mercator = vec![255_422.6, 6_250_835.0];
+        for (position, from, to, tolerance) in [
+            (&eiffel, Crs::Wgs84, utm, 1e-7),
+            (&rio, Crs::Wgs84, south, 1e-7),
+            (&paris_mercator, Crs::WebMercator, utm, 1e-2),
+        ] {
+            let there = reproject_position(position, from, to).unwrap();
+            let back = reproject_position(&there, to, from).unwrap();
+            assert_close(&back, position, tolerance);
+        }
+
+        let mut err =
+            reproject_position(&vec![0.0, 91.0], Crs::Wgs84, Crs::WebMercator).unwrap_err();
+        err.is_handled = true;
+        assert_eq!(
+            err.message,
+            "Failed to reproject position: latitude 91 is outside -90 to 90"
+        );
+        assert_eq!(err.extra_data[0]["to"], 3857);
+        let mut err = Crs::from_epsg(2154).unwrap_err();
+        err.is_handled = true;
+        assert_eq!(err.message, "Unsupported CRS EPSG:2154");
+    }
+
+    #[test]
+    fn test_utm_zone_for() {
+        let zone = |lon, lat| match Crs::utm_zone_for(lon, lat) {
+            Crs::Utm { zone, .. } => zone,
+            other => panic!("expected a UTM zone, got {other:?}"),
+        };
+        assert_eq!(zone(-180.0, 0.0), 1);
+        assert_eq!(zone(179.9, 0.0), 60);
+        assert_eq!(zone(10.75, 59.91), 32);
+        // Southwestern Norway belongs to the widened zone 32
+        assert_eq!(zone(5.0, 60.0), 32);
+        assert_eq!(zone(5.0, 55.0), 31);
+        // Svalbard is split into zones 31, 33, 35 and 37
+        assert_eq!(zone(8.0, 78.0), 31);
+        assert_eq!(zone(15.0, 78.0), 33);
+        assert_eq!(zone(25.0, 78.0), 35);
+        assert_eq!(zone(35.0, 78.0), 37);
+        assert_eq!(
+            Crs::utm_zone_for(-43.2, -22.9),
+            Crs::Utm {
+                zone: 23,
+                north: false
+            }
+        );
+    }
+
+    #[test]
+    fn test_reproject_feature_collection() {
+        let collection = FeatureCollection::from_json_value(&json!({
+            "type": "FeatureCollection",
+            "bbox": [0, 0, 1, 1],
+            "features": [{
+                "type": "Feature",
+                "geometry": {"type": "LineString", "coordinates": [[0, 0], [1, 1]]},
+                "properties": {"name": "diagonal"}
+            }]
+        }))
+        .unwrap();
+        let reprojected =
+            reproject_feature_collection(&collection, Crs::Wgs84, Crs::WebMercator).unwrap();
+        let bbox = reprojected.bbox.clone().unwrap();
+        assert_close(&bbox, &[0.0, 0.0, 111_319.490_793, 111_325.142_866], 1e-3);
+        assert_eq!(
+            reprojected.features[0].properties,
+            collection.features[0].properties
+        );
+        let back =
+            reproject_feature_collection(&reprojected, Crs::WebMercator, Crs::Wgs84).unwrap();
+        let GeometryValue::LineString(line) = &back.features[0].geometry.as_ref().unwrap().value
+        else {
+            panic!("expected a line string");
+        };
+        assert_close(&line[1], &[1.0, 1.0], 1e-9);
+    }
+}
@@ -0,0 +1,237 @@
+use rstar::{PointDistance, RTree, RTreeObject, AABB};
+
+use super::{
+    measure::{bbox, contains_point, for_each_position, segment_distance},
+    Bbox, FeatureCollection, Geometry, GeometryValue, Position,
+};
+
+/// An R-tree over the geometries of a feature collection, answering spatial queries with feature indices.
+///
+/// Features without a geometry, or whose geometry has no positions, are left out.
+/// Queries work in the plane of the coordinates, so distances are in degrees for WGS84 data.
+///
+/// ## Example
+///
+/// ```rust
+/// use utils::geojson::{index::SpatialIndex, FeatureCollection};
+/// use serde_json::json;
+///
+/// let collection = FeatureCollection::from_json_value(&json!({
+///     "type": "FeatureCollection",
+///     "features": [
+///         {"type": "Feature", "geometry": {"type": "Polygon", "coordinates": [[[0, 0], [2, 0], [2, 2], [0, 2], [0, 0]]]}, "properties": null},
+///         {"type": "Feature", "geometry": {"type": "Point", "coordinates": [5, 5]}, "properties": null}
+///     ]
+/// }))
+/// .unwrap();
+/// let index = SpatialIndex::new(&collection);
+/// assert_eq!(index.containing_point(&vec![1.0, 1.0]), vec![0]);
+/// assert_eq!(index.nearest(&vec![4.0, 4.0], 1), vec![1]);
+/// ```
+pub struct SpatialIndex {
+    tree: RTree<IndexedGeometry>,
+}
+
+struct IndexedGeometry {
+    feature: usize,
+    envelope: AABB<[f64; 2]>,
+    geometry: Geometry,
+}
+
+impl SpatialIndex {
+    /// Builds the index, bulk loading every feature's geometry at once.
+    pub fn new(collection: &FeatureCollection) -> Self {
+        let items = collection
+            .features
+            .iter()
+            .enumerate()
+            .filter_map(|(feature, f)| {
+                let geometry = f.geometry.as_ref()?;
+                let bbox = bbox(geometry)?;
+                Some(IndexedGeometry {
+                    feature,
+                    envelope: AABB::from_corners([bbox[0], bbox[1]], [bbox[2], bbox[3]]),
+                    geometry: geometry.clone(),
+                })
+            })
+            .collect();
+        Self {
+            tree: RTree::bulk_load(items),
+        }
+    }
+
+    /// The number of indexed geometries.
+    pub fn len(&self) -> usize {
+        self.tree.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.size() == 0
+    }
+
+    /// The indices of the features whose bounding box intersects `bbox`, given as `[west, south, east, north]`,
+    /// in ascending order.
+    ///
+    /// A `bbox` whose west edge is east of its east edge crosses the antimeridian.
+    ///
+    /// This is a bounding box test only: a feature is returned if its box touches `bbox`, even if its geometry doesn't.
+    pub fn intersecting_bbox(&self, bbox: &Bbox) -> Vec<usize> {
+        if bbox.len() < 4 {
+            return vec![];
+        }
+        let (west, south, east, north) = (bbox[0], bbox[1], bbox[2], bbox[3]);
+        // A box crossing the antimeridian has its west edge east of its east edge
+        let envelopes = if west > east {
+            vec![
+                AABB::from_corners([west, south], [180.0, north]),
+                AABB::from_corners([-180.0, south], [east, north]),
+            ]
+        } else {
+            vec![AABB::from_corners([west, south], [east, north])]
+        };
+        let mut features: Vec<usize> = envelopes
+            .iter()
+            .flat_map(|envelope| self.tree.locate_in_envelope_intersecting(envelope))
+            .map(|item| item.feature)
+            .collect();
+        features.sort_unstable();
+        features.dedup();
+        features
+    }
+
+    /// The indices of the features whose polygons contain `point`, in ascending order,
+    /// see `measure::contains_point`.
+    pub fn containing_point(&self, point: &Position) -> Vec<usize> {
+        if point.len() < 2 {
+            return vec![];
+        }
+        let envelope = AABB::from_point([point[0], point[1]]);
+        let mut features: Vec<usize> = self
+            .tree
+            .locate_in_envelope_intersecting(&envelope)
+            .filter(|item| contains_point(&item.geometry, point))
+            .map(|item| item.feature)
+            .collect();
+        features.sort_unstable();
+        features
+    }
+
+    /// The indices of the `k` features closest to `point`, nearest first.
+    ///
+    /// The distance is to the geometry itself, not its bounding box, and is 0 for polygons containing the point.
+    pub fn nearest(&self, point: &Position, k: usize) -> Vec<usize> {
+        if point.len() < 2 {
+            return vec![];
+        }
+        self.tree
+            .nearest_neighbor_iter(&[point[0], point[1]])
+            .take(k)
+            .map(|item| item.feature)
+            .collect()
+    }
+}
+
+impl RTreeObject for IndexedGeometry {
+    type Envelope = AABB<[f64; 2]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        self.envelope
+    }
+}
+
+impl PointDistance for IndexedGeometry {
+    fn distance_2(&self, point: &[f64; 2]) -> f64 {
+        let point = vec![point[0], point[1]];
+        if contains_point(&self.geometry, &point) {
+            return 0.0;
+        }
+        let distance = geometry_distance(&self.geometry, &point);
+        distance * distance
+    }
+}
+
+/// The planar distance from a point to the closest position or segment of a geometry.
+fn geometry_distance(geometry: &Geometry, point: &Position) -> f64 {
+    let line_distance = |line: &Vec<Position>| match line.len() {
+        1 => segment_distance(point, &line[0], &line[0]),
+        _ => line
+            .windows(2)
+            .map(|pair| segment_distance(point, &pair[0], &pair[1]))
+            .fold(f64::INFINITY, f64::min),
+    };
+    match &geometry.value {
+        GeometryValue::LineString(line) => line_distance(line),
+        GeometryValue::MultiLineString(lines) | GeometryValue::Polygon(lines) => lines
+            .iter()
+            .map(line_distance)
+            .fold(f64::INFINITY, f64::min),
+        GeometryValue::MultiPolygon(polygons) => polygons
+            .iter()
+            .flatten()
+            .map(line_distance)
+            .fold(f64::INFINITY, f64::min),
+        GeometryValue::GeometryCollection(geometries) => geometries
+            .iter()
+            .map(|g| geometry_distance(g, point))
+            .fold(f64::INFINITY, f64::min),
+        GeometryValue::Point(_) | GeometryValue::MultiPoint(_) => {
+            let mut distance = f64::INFINITY;
+            for_each_position(geometry, &mut |p| {
+                distance = distance.min(segment_distance(point, p, p))
+            });
+            distance
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use traceback_error::serde_json::json;
+
+    use super::*;
+
+    fn collection() -> FeatureCollection {
+        let square = |x: i32, y: i32| {
+            json!({
+                "type": "Feature",
+                "geometry": {"type": "Polygon", "coordinates": [[[x, y], [x + 2, y], [x + 2, y + 2], [x, y + 2], [x, y]]]},
+                "properties": null
+            })
+        };
+        FeatureCollection::from_json_value(&json!({
+            "type": "FeatureCollection",
+            "features": [
+                square(0, 0),
+                square(1, 1),
+                {"type": "Feature", "geometry": null, "properties": null},
+                {"type": "Feature", "geometry": {"type": "LineString", "coordinates": [[10, 0], [10, 10]]}, "properties": null},
+                square(20, 20),
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_queries() {
+        let index = SpatialIndex::new(&collection());
+        assert_eq!(index.len(), 4);
+        assert_eq!(index.containing_point(&vec![1.5, 1.5]), vec![0, 1]);
+        assert_eq!(index.containing_point(&vec![0.5, 0.5]), vec![0]);
+        assert_eq!(
+            index.containing_point(&vec![10.0, 5.0]),
+            Vec::<usize>::new()
+        );
+        assert_eq!(
+            index.intersecting_bbox(&vec![2.5, -1.0, 10.0, 2.5]),
+            vec![1, 3]
+        );
+        assert_eq!(
+            index.intersecting_bbox(&vec![19.0, 0.0, 0.5, 30.0]),
+            vec![0, 4]
+        );
+        // The line is closer than the corner of the second square, though its bounding box center isn't
+        assert_eq!(index.nearest(&vec![8.0, 9.0], 2), vec![3, 1]);
+        assert_eq!(index.nearest(&vec![21.0, 21.0], 1), vec![4]);
+        assert_eq!(index.nearest(&vec![0.0, 0.0], 10).len(), 4);
+    }
+}
@@ -0,0 +1,590 @@
+use super::{Bbox, FeatureCollection, Geometry, GeometryValue, Position};
+
+/// The mean radius of the Earth in meters, as used by the spherical formulas in this module.
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// The WGS84 ellipsoid, used by `vincenty_distance`.
+const WGS84_A: f64 = 6_378_137.0;
+const WGS84_F: f64 = 1.0 / 298.257_223_563;
+const WGS84_B: f64 = WGS84_A * (1.0 - WGS84_F);
+
+/// How `length` measures the distance between two positions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LengthMethod {
+    /// Great-circle distance on a sphere. Fast, and within about 0.5% of the ellipsoidal distance.
+    #[default]
+    Haversine,
+    /// Distance on the WGS84 ellipsoid, accurate to well under a millimeter.
+    /// Falls back to haversine for the nearly antipodal points where the formula doesn't converge.
+    Vincenty,
+}
+
+/// Calls `f` for every position of a geometry, including those of nested geometries.
+pub fn for_each_position<'a>(geometry: &'a Geometry, f: &mut impl FnMut(&'a Position)) {
+    match &geometry.value {
+        GeometryValue::Point(position) => f(position),
+        GeometryValue::MultiPoint(positions) | GeometryValue::LineString(positions) => {
+            positions.iter().for_each(f)
+        }
+        GeometryValue::MultiLineString(lines) | GeometryValue::Polygon(lines) => {
+            lines.iter().flatten().for_each(f)
+        }
+        GeometryValue::MultiPolygon(polygons) => polygons.iter().flatten().flatten().for_each(f),
+        GeometryValue::GeometryCollection(geometries) => {
+            for geometry in geometries {
+                for_each_position(geometry, f);
+            }
+        }
+    }
+}
+
+/// Builds a copy of a geometry with `f` applied to every position, stopping at the first error.
+///
+/// Bounding boxes and foreign members are copied as they are.
+pub fn try_map_positions<E>(
+    geometry: &Geometry,
+    f: &mut impl FnMut(&Position) -> Result<Position, E>,
+) -> Result<Geometry, E> {
+    let value = match &geometry.value {
+        GeometryValue::Point(position) => GeometryValue::Point(f(position)?),
+        GeometryValue::MultiPoint(positions) => {
+            GeometryValue::MultiPoint(positions.iter().map(&mut *f).collect::<Result<_, _>>()?)
+        }
+        GeometryValue::LineString(positions) => {
+            GeometryValue::LineString(positions.iter().map(&mut *f).collect::<Result<_, _>>()?)
+        }
+        GeometryValue::MultiLineString(lines) => {
+            GeometryValue::MultiLineString(map_lines(lines, f)?)
+        }
+        GeometryValue::Polygon(rings) => GeometryValue::Polygon(map_lines(rings, f)?),
+        GeometryValue::MultiPolygon(polygons) => GeometryValue::MultiPolygon(
+            polygons
+                .iter()
+                .map(|rings| map_lines(rings, f))
+                .collect::<Result<_, _>>()?,
+        ),
+        GeometryValue::GeometryCollection(geometries) => GeometryValue::GeometryCollection(
+            geometries
+                .iter()
+                .map(|g| try_map_positions(g, f))
+                .collect::<Result<_, _>>()?,
+        ),
+    };
+    Ok(Geometry {
+        value,
+        bbox: geometry.bbox.clone(),
+        foreign_members: geometry.foreign_members.clone(),
+    })
+}
+
+fn map_lines<E>(
+    lines: &[Vec<Position>],
+    f: &mut impl FnMut(&Position) -> Result<Position, E>,
+) -> Result<Vec<Vec<Position>>, E> {
+    lines
+        .iter()
+        .map(|line| line.iter().map(&mut *f).collect())
+        .collect()
+}
+
+/// The 2D bounding box of a geometry as `[west, south, east, north]`, or `None` if it has no positions.
+///
+/// This is the plain min/max of the coordinates, so a geometry crossing the antimeridian gets a box spanning the globe.
+pub fn bbox(geometry: &Geometry) -> Option<Bbox> {
+    let mut bbox: Option<Bbox> = None;
+    for_each_position(geometry, &mut |p| extend_bbox(&mut bbox, p));
+    bbox
+}
+
+/// The 2D bounding box of every geometry in a collection, or `None` if none of them has positions.
+pub fn collection_bbox(collection: &FeatureCollection) -> Option<Bbox> {
+    let mut bbox: Option<Bbox> = None;
+    for geometry in collection
+        .features
+        .iter()
+        .filter_map(|f| f.geometry.as_ref())
+    {
+        for_each_position(geometry, &mut |p| extend_bbox(&mut bbox, p));
+    }
+    bbox
+}
+
+fn extend_bbox(bbox: &mut Option<Bbox>, p: &Position) {
+    let (x, y) = (p[0], p[1]);
+    match bbox {
+        Some(b) => {
+            b[0] = b[0].min(x);
+            b[1] = b[1].min(y);
+            b[2] = b[2].max(x);
+            b[3] = b[3].max(y);
+        }
+        None => *bbox = Some(vec![x, y, x, y]),
+    }
+}
+
+/// The centroid of a geometry, computed in the plane of its coordinates.
+///
+/// Like most GIS tools, only the parts with the highest dimension count:
+/// polygons are weighted by area, lines by length, and points are averaged.
+/// Returns `None` for a geometry with no positions.
+///
+/// ## Example
+///
+/// ```rust
+/// use utils::geojson::{measure::centroid, Geometry, GeometryValue};
+///
+/// let square = Geometry::new(GeometryValue::Polygon(vec![vec![
+///     vec![0.0, 0.0],
+///     vec![2.0, 0.0],
+///     vec![2.0, 2.0],
+///     vec![0.0, 2.0],
+///     vec![0.0, 0.0],
+/// ]]));
+/// assert_eq!(centroid(&square), Some(vec![1.0, 1.0]));
+/// ```
+pub fn centroid(geometry: &Geometry) -> Option<Position> {
+    let mut sums = CentroidSums::default();
+    sums.add(geometry);
+    sums.centroid()
+}
+
+/// Weighted coordinate sums for each dimension of geometry: `[weight, x, y]`.
+#[derive(Default)]
+struct CentroidSums {
+    points: [f64; 3],
+    lines: [f64; 3],
+    areas: [f64; 3],
+}
+
+impl CentroidSums {
+    fn add(&mut self, geometry: &Geometry) {
+        match &geometry.value {
+            GeometryValue::Point(p) => self.add_point(p),
+            GeometryValue::MultiPoint(ps) => ps.iter().for_each(|p| self.add_point(p)),
+            GeometryValue::LineString(line) => self.add_line(line),
+            GeometryValue::MultiLineString(lines) => lines.iter().for_each(|l| self.add_line(l)),
+            GeometryValue::Polygon(rings) => self.add_polygon(rings),
+            GeometryValue::MultiPolygon(polygons) => {
+                polygons.iter().for_each(|rings| self.add_polygon(rings))
+            }
+            GeometryValue::GeometryCollection(geometries) => {
+                geometries.iter().for_each(|g| self.add(g))
+            }
+        }
+    }
+
+    fn add_point(&mut self, p: &Position) {
+        self.points[0] += 1.0;
+        self.points[1] += p[0];
+        self.points[2] += p[1];
+    }
+
+    fn add_line(&mut self, line: &[Position]) {
+        for pair in line.windows(2) {
+            let (a, b) = (&pair[0], &pair[1]);
+            let length = (b[0] - a[0]).hypot(b[1] - a[1]);
+            self.lines[0] += length;
+            self.lines[1] += length * (a[0] + b[0]) / 2.0;
+            self.lines[2] += length * (a[1] + b[1]) / 2.0;
+        }
+        // A line of identical positions has no length, but still has a location
+        if let Some(first) = line.first() {
+            self.add_point(first);
+        }
+    }
+
+    fn add_polygon(&mut self, rings: &[Vec<Position>]) {
+        for (i, ring) in rings.iter().enumerate() {
+            let (area, x, y) = ring_moments(ring);
+            // Count the exterior ring positively and holes negatively, whichever way the input winds them
+            let sign = match i {
+                0 => area.signum(),
+                _ => -area.signum(),
+            };
+            self.areas[0] += area * sign;
+            self.areas[1] += x * sign;
+            self.areas[2] += y * sign;
+        }
+        for ring in rings {
+            self.add_line(ring);
+        }
+    }
+
+    fn centroid(&self) -> Option<Position> {
+        [self.areas, self.lines, self.points]
+            .into_iter()
+            .find(|sums| sums[0].abs() > f64::EPSILON)
+            .map(|[weight, x, y]| vec![x / weight, y / weight])
+    }
+}
+
+/// The signed area of a ring and its first moments, in the plane of its coordinates.
+fn ring_moments(ring: &[Position]) -> (f64, f64, f64) {
+    let (mut area, mut x, mut y) = (0.0, 0.0, 0.0);
+    for pair in ring.windows(2) {
+        let (a, b) = (&pair[0], &pair[1]);
+        let cross = a[0] * b[1] - b[0] * a[1];
+        area += cross;
+        x += (a[0] + b[0]) * cross;
+        y += (a[1] + b[1]) * cross;
+    }
+    (area / 2.0, x / 6.0, y / 6.0)
+}
+
+/// The great-circle distance between two positions in meters.
+pub fn haversine_distance(a: &Position, b: &Position) -> f64 {
+    let (lat1, lat2) = (a[1].to_radians(), b[1].to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (b[0] - a[0]).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * h.sqrt().min(1.0).asin()
+}
+
+/// The distance between two positions on the WGS84 ellipsoid in meters,
+/// or `None` if the iteration doesn't converge, which happens for nearly antipodal points.
+pub fn vincenty_distance(a: &Position, b: &Position) -> Option<f64> {
+    let l = (b[0] - a[0]).to_radians();
+    let u1 = ((1.0 - WGS84_F) * a[1].to_radians().tan()).atan();
+    let u2 = ((1.0 - WGS84_F) * b[1].to_radians().tan()).atan();
+    let (sin_u1, cos_u1) = u1.sin_cos();
+    let (sin_u2, cos_u2) = u2.sin_cos();
+    let mut lambda = l;
+    for _ in 0..200 {
+        let (sin_lambda, cos_lambda) = lambda.sin_cos();
+        let sin_sigma = ((cos_u2 * sin_lambda).powi(2)
+            + (cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda).powi(2))
+        .sqrt();
+        if sin_sigma == 0.0 {
+            // The same point
+            return Some(0.0);
+        }
+        let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+        let sigma = sin_sigma.atan2(cos_sigma);
+        let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+        let cos_sq_alpha = 1.0 - sin_alpha.powi(2);
+        let cos_2sigma_m = match cos_sq_alpha {
+            // Both points on the equator
+            0.0 => 0.0,
+            _ => cos_sigma - 2.0 * sin_u1 * sin_u2 / cos_sq_alpha,
+        };
+        let c = WGS84_F / 16.0 * cos_sq_alpha * (4.0 + WGS84_F * (4.0 - 3.0 * cos_sq_alpha));
+        let previous = lambda;
+        lambda = l
+            + (1.0 - c)
+                * WGS84_F
+                * sin_alpha
+                * (sigma
+                    + c * sin_sigma
+                        * (cos_2sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))));
+        if (lambda - previous).abs() < 1e-12 {
+            let u_sq = cos_sq_alpha * (WGS84_A.powi(2) - WGS84_B.powi(2)) / WGS84_B.powi(2);
+            let big_a =
+                1.0 + u_sq / 16384.0 * (4096.0 + u_sq * (-768.0 + u_sq * (320.0 - 175.0 * u_sq)));
+            let big_b = u_sq / 1024.0 * (256.0 + u_sq * (-128.0 + u_sq * (74.0 - 47.0 * u_sq)));
+            let delta_sigma = big_b
+                * sin_sigma
+                * (cos_2sigma_m
+                    + big_b / 4.0
+                        * (cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))
+                            - big_b / 6.0
+                                * cos_2sigma_m
+                                * (-3.0 + 4.0 * sin_sigma.powi(2))
+                                * (-3.0 + 4.0 * cos_2sigma_m.powi(2))));
+            return Some(WGS84_B * big_a * (sigma - delta_sigma));
+        }
+    }
+    None
+}
+
+/// The geodesic length of a geometry in meters.
+///
+/// Lines count their length, polygons the length of all their rings, and points nothing.
+pub fn length(geometry: &Geometry, method: LengthMethod) -> f64 {
+    let distance = |a: &Position, b: &Position| match method {
+        LengthMethod::Haversine => haversine_distance(a, b),
+        LengthMethod::Vincenty => {
+            vincenty_distance(a, b).unwrap_or_else(|| haversine_distance(a, b))
+        }
+    };
+    let line_length = |line: &[Position]| -> f64 {
+        line.windows(2)
+            .map(|pair| distance(&pair[0], &pair[1]))
+            .sum()
+    };
+    match &geometry.value {
+        GeometryValue::Point(_) | GeometryValue::MultiPoint(_) => 0.0,
+        GeometryValue::LineString(line) => line_length(line),
+        GeometryValue::MultiLineString(lines) | GeometryValue::Polygon(lines) => {
+            lines.iter().map(|l| line_length(l)).sum()
+        }
+        GeometryValue::MultiPolygon(polygons) => {
+            polygons.iter().flatten().map(|l| line_length(l)).sum()
+        }
+        GeometryValue::GeometryCollection(geometries) => {
+            geometries.iter().map(|g| length(g, method)).sum()
+        }
+    }
+}
+
+/// The area of a geometry on a sphere in square meters, with holes subtracted.
+///
+/// Only polygons have an area. The winding order of the rings doesn't matter.
+pub fn area(geometry: &Geometry) -> f64 {
+    match &geometry.value {
+        GeometryValue::Polygon(rings) => polygon_area(rings),
+        GeometryValue::MultiPolygon(polygons) => polygons.iter().map(|p| polygon_area(p)).sum(),
+        GeometryValue::GeometryCollection(geometries) => geometries.iter().map(area).sum(),
+        _ => 0.0,
+    }
+}
+
+fn polygon_area(rings: &[Vec<Position>]) -> f64 {
+    let mut rings = rings.iter().map(|ring| ring_area(ring).abs());
+    let exterior = rings.next().unwrap_or(0.0);
+    (exterior - rings.sum::<f64>()).max(0.0)
+}
+
+/// The signed spherical area of a ring, after Chamberlain and Duquette,
+/// "Some Algorithms for Polygons on a Sphere" (2007).
+fn ring_area(ring: &[Position]) -> f64 {
+    let sum: f64 = ring
+        .windows(2)
+        .map(|pair| {
+            let (a, b) = (&pair[0], &pair[1]);
+            (b[0] - a[0]).to_radians() * (2.0 + a[1].to_radians().sin() + b[1].to_radians().sin())
+        })
+        .sum();
+    sum * EARTH_RADIUS * EARTH_RADIUS / 2.0
+}
+
+/// Whether a point lies inside a polygonal geometry, treating holes as outside.
+///
+/// Uses ray casting in the plane of the coordinates. Points exactly on an edge may land on either side.
+/// Geometries without an area never contain anything.
+pub fn contains_point(geometry: &Geometry, point: &Position) -> bool {
+    match &geometry.value {
+        GeometryValue::Polygon(rings) => polygon_contains(rings, point),
+        GeometryValue::MultiPolygon(polygons) => {
+            polygons.iter().any(|rings| polygon_contains(rings, point))
+        }
+        GeometryValue::GeometryCollection(geometries) => {
+            geometries.iter().any(|g| contains_point(g, point))
+        }
+        _ => false,
+    }
+}
+
+fn polygon_contains(rings: &[Vec<Position>], point: &Position) -> bool {
+    match rings.split_first() {
+        Some((exterior, holes)) => {
+            ring_contains(exterior, point) && !holes.iter().any(|h| ring_contains(h, point))
+        }
+        None => false,
+    }
+}
+
+fn ring_contains(ring: &[Position], point: &Position) -> bool {
+    let (x, y) = (point[0], point[1]);
+    let mut inside = false;
+    for pair in ring.windows(2) {
+        let (a, b) = (&pair[0], &pair[1]);
+        if (a[1] > y) != (b[1] > y) && x < (b[0] - a[0]) * (y - a[1]) / (b[1] - a[1]) + a[0] {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Simplifies the lines and rings of a geometry with the Douglas-Peucker algorithm.
+///
+/// `tolerance` is in the units of the coordinates, so degrees for WGS84.
+/// Lines keep at least their end points and rings at least 4 positions;
+/// a ring that would collapse further is kept as it was. Points are left alone.
+pub fn simplify(geometry: &Geometry, tolerance: f64) -> Geometry {
+    let simplify_ring = |ring: &Vec<Position>| {
+        let simplified = douglas_peucker(ring, tolerance);
+        match simplified.len() < 4 {
+            true => ring.clone(),
+            false => simplified,
+        }
+    };
+    let value = match &geometry.value {
+        GeometryValue::LineString(line) => {
+            GeometryValue::LineString(douglas_peucker(line, tolerance))
+        }
+        GeometryValue::MultiLineString(lines) => GeometryValue::MultiLineString(
+            lines
+                .iter()
+                .map(|l| douglas_peucker(l, tolerance))
+                .collect(),
+        ),
+        GeometryValue::Polygon(rings) => {
+            GeometryValue::Polygon(rings.iter().map(simplify_ring).collect())
+        }
+        GeometryValue::MultiPolygon(polygons) => GeometryValue::MultiPolygon(
+            polygons
+                .iter()
+                .map(|rings| rings.iter().map(simplify_ring).collect())
+                .collect(),
+        ),
+        GeometryValue::GeometryCollection(geometries) => GeometryValue::GeometryCollection(
+            geometries.iter().map(|g| simplify(g, tolerance)).collect(),
+        ),
+        other => other.clone(),
+    };
+    Geometry {
+        value,
+        bbox: geometry.bbox.clone(),
+        foreign_members: geometry.foreign_members.clone(),
+    }
+}
+
+/// Simplifies a line, keeping the positions that deviate more than `tolerance` from the simplified line.
+pub fn douglas_peucker(line: &[Position], tolerance: f64) -> Vec<Position> {
+    if line.len() < 3 {
+        return line.to_vec();
+    }
+    let mut keep = vec![false; line.len()];
+    keep[0] = true;
+    keep[line.len() - 1] = true;
+    // An explicit stack instead of recursion, so long lines can't overflow the call stack
+    let mut stack = vec![(0, line.len() - 1)];
+    while let Some((start, end)) = stack.pop() {
+        let mut farthest = (0.0, start);
+        for i in start + 1..end {
+            let distance = segment_distance(&line[i], &line[start], &line[end]);
+            if distance > farthest.0 {
+                farthest = (distance, i);
+            }
+        }
+        if farthest.0 > tolerance {
+            keep[farthest.1] = true;
+            stack.push((start, farthest.1));
+            stack.push((farthest.1, end));
+        }
+    }
+    line.iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|(p, _)| p.clone())
+        .collect()
+}
+
+/// The planar distance from `p` to the segment from `a` to `b`.
+pub(crate) fn segment_distance(p: &Position, a: &Position, b: &Position) -> f64 {
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    let length_sq = dx * dx + dy * dy;
+    let t = match length_sq {
+        0.0 => 0.0,
+        _ => (((p[0] - a[0]) * dx + (p[1] - a[1]) * dy) / length_sq).clamp(0.0, 1.0),
+    };
+    (p[0] - (a[0] + t * dx)).hypot(p[1] - (a[1] + t * dy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exterior() -> Vec<Position> {
+        vec![
+            vec![0.0, 0.0],
+            vec![4.0, 0.0],
+            vec![4.0, 4.0],
+            vec![0.0, 4.0],
+            vec![0.0, 0.0],
+        ]
+    }
+
+    fn hole() -> Vec<Position> {
+        vec![
+            vec![1.0, 1.0],
+            vec![1.0, 2.0],
+            vec![2.0, 2.0],
+            vec![2.0, 1.0],
+            vec![1.0, 1.0],
+        ]
+    }
+
+    fn polygon(rings: Vec<Vec<Position>>) -> Geometry {
+        Geometry::new(GeometryValue::Polygon(rings))
+    }
+
+    fn square_with_hole() -> Geometry {
+        polygon(vec![exterior(), hole()])
+    }
+
+    #[test]
+    fn test_bbox_and_centroid() {
+        let polygon = square_with_hole();
+        assert_eq!(bbox(&polygon), Some(vec![0.0, 0.0, 4.0, 4.0]));
+        // 16 * (2, 2) - 1 * (1.5, 1.5), divided by 15
+        let c = centroid(&polygon).unwrap();
+        assert!((c[0] - 30.5 / 15.0).abs() < 1e-12);
+        assert!((c[1] - 30.5 / 15.0).abs() < 1e-12);
+        let line = Geometry::new(GeometryValue::LineString(vec![
+            vec![0.0, 0.0],
+            vec![2.0, 0.0],
+        ]));
+        assert_eq!(centroid(&line), Some(vec![1.0, 0.0]));
+    }
+
+    #[test]
+    fn test_distances() {
+        let oslo = vec![10.7522, 59.9139];
+        let bergen = vec![5.3221, 60.3913];
+        let haversine = haversine_distance(&oslo, &bergen);
+        let vincenty = vincenty_distance(&oslo, &bergen).unwrap();
+        assert!((haversine - 305_000.0).abs() < 2_000.0, "{haversine}");
+        assert!((haversine - vincenty).abs() / vincenty < 0.005);
+        let line = Geometry::new(GeometryValue::LineString(vec![oslo, bergen]));
+        assert_eq!(length(&line, LengthMethod::Vincenty), vincenty);
+    }
+
+    #[test]
+    fn test_area() {
+        // One degree square at the equator is about 12 364 km²
+        let cell = polygon(vec![vec![
+            vec![0.0, 0.0],
+            vec![1.0, 0.0],
+            vec![1.0, 1.0],
+            vec![0.0, 1.0],
+            vec![0.0, 0.0],
+        ]]);
+        let km2 = area(&cell) / 1e6;
+        assert!((km2 - 12_364.0).abs() < 10.0, "{km2}");
+        let with_hole = area(&square_with_hole());
+        let expected = area(&polygon(vec![exterior()])) - area(&polygon(vec![hole()]));
+        assert!((with_hole - expected).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_contains_point() {
+        let polygon = square_with_hole();
+        assert!(contains_point(&polygon, &vec![3.0, 3.0]));
+        assert!(!contains_point(&polygon, &vec![1.5, 1.5]));
+        assert!(!contains_point(&polygon, &vec![5.0, 1.0]));
+    }
+
+    #[test]
+    fn test_simplify() {
+        let line = vec![
+            vec![0.0, 0.0],
+            vec![1.0, 0.01],
+            vec![2.0, -0.01],
+            vec![3.0, 5.0],
+            vec![4.0, 6.0],
+            vec![5.0, 7.0],
+        ];
+        assert_eq!(
+            douglas_peucker(&line, 0.1),
+            vec![
+                vec![0.0, 0.0],
+                vec![2.0, -0.01],
+                vec![3.0, 5.0],
+                vec![5.0, 7.0]
+            ]
+        );
+        // The hole would collapse, so it is kept as it was
+        assert_eq!(simplify(&square_with_hole(), 10.0), square_with_hole());
+    }
+}
@@ -0,0 +1,342 @@
+use std::convert::Infallible;
+
+use traceback_error::{serde_json::json, traceback, TracebackError};
+
+use super::{
+    measure::try_map_positions, Bbox, Feature, FeatureCollection, GeoJson, Geometry, GeometryValue,
+    Position,
+};
+
+/// The most decimal places `round_coordinate` rounds to. Larger values are clamped to it.
+pub const MAX_DECIMALS: u32 = 15;
+
+/// Rounds a coordinate to `decimals` places, half away from zero. `-0` becomes `0`.
+///
+/// A coordinate too large to scale to `decimals` places exactly already has no more precision
+/// than that, and is returned unchanged.
+pub fn round_coordinate(coordinate: f64, decimals: u32) -> f64 {
+    let factor = 10f64.powi(decimals.min(MAX_DECIMALS) as i32);
+    let scaled = coordinate * factor;
+    if scaled.abs() >= 2f64.powi(f64::MANTISSA_DIGITS as i32) {
+        return coordinate;
+    }
+    let rounded = scaled.round() / factor;
+    match rounded == 0.0 {
+        true => 0.0,
+        false => rounded,
+    }
+}
+
+pub fn round_position(position: &Position, decimals: u32) -> Position {
+    position
+        .iter()
+        .map(|c| round_coordinate(*c, decimals))
+        .collect()
+}
+
+/// Rounds the coordinates of a geometry to `decimals` places and cleans up what the rounding collapsed.
+///
+/// Consecutive positions that became equal are merged. Rings left with fewer than 4 positions
+/// and lines left with fewer than 2 are dropped from multi-geometries, and holes are dropped from polygons.
+/// A geometry that collapses as a whole, like a polygon whose exterior ring does, is an error naming
+/// the coordinate path, as are coordinates that aren't finite numbers.
+///
+/// ## Example
+///
+/// ```rust
+/// use utils::geojson::{precision::reduce_precision, wkt::{parse_wkt, to_wkt}};
+///
+/// let line = parse_wkt("LINESTRING (10.751234 59.912345, 10.751239 59.912341, 10.76 59.92)").unwrap();
+/// let reduced = reduce_precision(&line, 4).unwrap();
+/// assert_eq!(to_wkt(&reduced), "LINESTRING (10.7512 59.9123, 10.76 59.92)");
+/// ```
+pub fn reduce_precision(geometry: &Geometry, decimals: u32) -> Result<Geometry, TracebackError> {
+    match reduce_geometry(geometry, decimals, "$") {
+        Ok(geometry) => Ok(geometry),
+        Err(e) => Err(traceback!(err e).with_extra_data(json!({ "decimals": decimals }))),
+    }
+}
+
+/// Reduces the precision of every feature's geometry, see `reduce_precision`.
+///
+/// Errors name the index of the feature whose geometry collapsed.
+pub fn reduce_feature_collection_precision(
+    collection: &FeatureCollection,
+    decimals: u32,
+) -> Result<FeatureCollection, TracebackError> {
+    let mut features = Vec::with_capacity(collection.features.len());
+    for (i, feature) in collection.features.iter().enumerate() {
+        let geometry = match &feature.geometry {
+            Some(geometry) => {
+                match reduce_geometry(geometry, decimals, &format!("$.features[{i}].geometry")) {
+                    Ok(geometry) => Some(geometry),
+                    Err(e) => {
+                        return Err(traceback!(
+                            err e,
+                            format!("Failed to reduce the precision of feature {i}")
+                        )
+                        .with_extra_data(json!({ "feature": i, "decimals": decimals })))
+                    }
+                }
+            }
+            None => None,
+        };
+        features.push(Feature {
+            geometry,
+            bbox: round_bbox(&feature.bbox, decimals),
+            ..feature.clone()
+        });
+    }
+    Ok(FeatureCollection {
+        features,
+        bbox: round_bbox(&collection.bbox, decimals),
+        foreign_members: collection.foreign_members.clone(),
+    })
+}
+
+/// Rounds every coordinate and bounding box of a GeoJSON object, keeping its structure as it is.
+///
+/// This is what `GeoJson::to_string_with_options` writes; use `reduce_precision` to also clean up the geometries.
+pub fn round_geojson(geojson: &GeoJson, decimals: u32) -> GeoJson {
+    let round_feature = |feature: &Feature| Feature {
+        geometry: feature
+            .geometry
+            .as_ref()
+            .map(|g| round_geometry(g, decimals)),
+        bbox: round_bbox(&feature.bbox, decimals),
+        ..feature.clone()
+    };
+    match geojson {
+        GeoJson::Geometry(geometry) => GeoJson::Geometry(round_geometry(geometry, decimals)),
+        GeoJson::Feature(feature) => GeoJson::Feature(round_feature(feature)),
+        GeoJson::FeatureCollection(collection) => GeoJson::FeatureCollection(FeatureCollection {
+            features: collection.features.iter().map(round_feature).collect(),
+            bbox: round_bbox(&collection.bbox, decimals),
+            foreign_members: collection.foreign_members.clone(),
+        }),
+    }
+}
+
+fn round_geometry(geometry: &Geometry, decimals: u32) -> Geometry {
+    let rounded =
+        try_map_positions::<Infallible>(geometry, &mut |p| Ok(round_position(p, decimals)));
+    let mut rounded = match rounded {
+        Ok(rounded) => rounded,
+        Err(never) => match never {},
+    };
+    round_nested_bboxes(&mut rounded, decimals);
+    rounded
+}
+
+fn round_nested_bboxes(geometry: &mut Geometry, decimals: u32) {
+    geometry.bbox = round_bbox(&geometry.bbox, decimals);
+    if let GeometryValue::GeometryCollection(geometries) = &mut geometry.value {
+        for geometry in geometries {
+            round_nested_bboxes(geometry, decimals);
+        }
+    }
+}
+
+fn round_bbox(bbox: &Option<Bbox>, decimals: u32) -> Option<Bbox> {
+    bbox.as_ref().map(|bbox| round_position(bbox, decimals))
+}
+
+fn reduce_geometry(
+    geometry: &Geometry,
+    decimals: u32,
+    path: &str,
+) -> Result<Geometry, TracebackError> {
+    let coordinates = format!("{path}.coordinates");
+    let value = match &geometry.value {
+        GeometryValue::Point(position) => match round_checked(position, decimals, &coordinates) {
+            Ok(position) => GeometryValue::Point(position),
+            Err(e) => return Err(traceback!(err e)),
+        },
+        GeometryValue::MultiPoint(positions) => {
+            let mut points = Vec::with_capacity(positions.len());
+            for (i, position) in positions.iter().enumerate() {
+                match round_checked(position, decimals, &format!("{coordinates}[{i}]")) {
+                    Ok(position) => points.push(position),
+                    Err(e) => return Err(traceback!(err e)),
+                }
+            }
+            GeometryValue::MultiPoint(points)
+        }
+        GeometryValue::LineString(line) => match reduce_line(line, decimals, &coordinates, 2) {
+            Ok(Some(line)) => GeometryValue::LineString(line),
+            Ok(None) => return Err(collapsed("Line", &coordinates)),
+            Err(e) => return Err(traceback!(err e)),
+        },
+        GeometryValue::MultiLineString(lines) => {
+            let mut reduced = Vec::with_capacity(lines.len());
+            for (i, line) in lines.iter().enumerate() {
+                match reduce_line(line, decimals, &format!("{coordinates}[{i}]"), 2) {
+                    Ok(Some(line)) => reduced.push(line),
+                    Ok(None) => {}
+                    Err(e) => return Err(traceback!(err e)),
+                }
+            }
+            if reduced.is_empty() && !lines.is_empty() {
+                return Err(collapsed("Every line", &coordinates));
+            }
+            GeometryValue::MultiLineString(reduced)
+        }
+        GeometryValue::Polygon(rings) => match reduce_polygon(rings, decimals, &coordinates) {
+            Ok(Some(rings)) => GeometryValue::Polygon(rings),
+            Ok(None) => return Err(collapsed("Exterior ring", &format!("{coordinates}[0]"))),
+            Err(e) => return Err(traceback!(err e)),
+        },
+        GeometryValue::MultiPolygon(polygons) => {
+            let mut reduced = Vec::with_capacity(polygons.len());
+            for (i, rings) in polygons.iter().enumerate() {
+                match reduce_polygon(rings, decimals, &format!("{coordinates}[{i}]")) {
+                    Ok(Some(rings)) => reduced.push(rings),
+                    Ok(None) => {}
+                    Err(e) => return Err(traceback!(err e)),
+                }
+            }
+            if reduced.is_empty() && !polygons.is_empty() {
+                return Err(collapsed("Every polygon", &coordinates));
+            }
+            GeometryValue::MultiPolygon(reduced)
+        }
+        GeometryValue::GeometryCollection(geometries) => {
+            let mut reduced = Vec::with_capacity(geometries.len());
+            for (i, geometry) in geometries.iter().enumerate() {
+                match reduce_geometry(geometry, decimals, &format!("{path}.geometries[{i}]")) {
+                    Ok(geometry) => reduced.push(geometry),
+                    Err(e) => return Err(traceback!(err e)),
+                }
+            }
+            GeometryValue::GeometryCollection(reduced)
+        }
+    };
+    Ok(Geometry {
+        value,
+        bbox: round_bbox(&geometry.bbox, decimals),
+        foreign_members: geometry.foreign_members.clone(),
+    })
+}
+
+fn collapsed(what: &str, path: &str) -> TracebackError {
+    traceback!(format!("{what} collapsed when rounded at {path}"))
+        .with_extra_data(json!({ "path": path }))
+}
+
+fn round_checked(
+    position: &Position,
+    decimals: u32,
+    path: &str,
+) -> Result<Position, TracebackError> {
+    match position.iter().all(|c| c.is_finite()) {
+        true => Ok(round_position(position, decimals)),
+        false => Err(
+            traceback!(format!("Coordinate is not a finite number at {path}"))
+                .with_extra_data(json!({ "path": path, "position": format!("{position:?}") })),
+        ),
+    }
+}
+
+/// Rounds a line and merges consecutive duplicates, or returns `None` if fewer than `min_len` positions remain.
+fn reduce_line(
+    line: &[Position],
+    decimals: u32,
+    path: &str,
+    min_len: usize,
+) -> Result<Option<Vec<Position>>, TracebackError> {
+    let mut reduced: Vec<Position> = Vec::with_capacity(line.len());
+    for (i, position) in line.iter().enumerate() {
+        let position = match round_checked(position, decimals, &format!("{path}[{i}]")) {
+            Ok(position) => position,
+            Err(e) => return Err(traceback!(err e)),
+        };
+        if reduced.last() != Some(&position) {
+            reduced.push(position);
+        }
+    }
+    match reduced.len() >= min_len {
+        true => Ok(Some(reduced)),
+        false => Ok(None),
+    }
+}
+
+/// Reduces the rings of a polygon, dropping degenerate holes, or returns `None` if the exterior ring is degenerate.
+fn reduce_polygon(
+    rings: &[Vec<Position>],
+    decimals: u32,
+    path: &str,
+) -> Result<Option<Vec<Vec<Position>>>, TracebackError> {
+    let mut reduced = Vec::with_capacity(rings.len());
+    for (i, ring) in rings.iter().enumerate() {
+        match reduce_line(ring, decimals, &format!("{path}[{i}]"), 4) {
+            Ok(Some(ring)) => reduced.push(ring),
+            Ok(None) if i == 0 => return Ok(None),
+            Ok(None) => {}
+            Err(e) => return Err(traceback!(err e)),
+        }
+    }
+    Ok(Some(reduced))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geojson::wkt::{parse_wkt, to_wkt};
+
+    #[test]
+    fn test_reduce_precision() {
+        let polygon = parse_wkt(
+            "MULTIPOLYGON (((0.00001 0.00001, 0.99999 0, 1 1, 1 1.00001, 0 1, 0 0)), \
+             ((5 5, 5.000001 5, 5 5.000001, 5 5)), \
+             ((2 2, 3 2, 3 3, 2 2), (2.5 2.4, 2.5 2.400001, 2.500001 2.4, 2.5 2.4)))",
+        )
+        .unwrap();
+        assert_eq!(
+            to_wkt(&reduce_precision(&polygon, 3).unwrap()),
+            "MULTIPOLYGON (((0 0, 1 0, 1 1, 0 1, 0 0)), ((2 2, 3 2, 3 3, 2 2)))"
+        );
+        assert_eq!(round_coordinate(-0.0004, 3).to_string(), "0");
+        // More digits than an f64 holds, so scaling it by 10^15 leaves no fraction to round
+        let coordinate: f64 = "59.912345678901234".parse().unwrap();
+        assert_eq!(round_coordinate(coordinate, 15), coordinate);
+        assert_eq!(round_coordinate(59.912345678, 5), 59.91235);
+
+        for (wkt, path) in [
+            (
+                "POLYGON ((5 5, 5.000001 5, 5 5.000001, 5 5))",
+                "$.coordinates[0]",
+            ),
+            ("LINESTRING (1 1, 1.0000001 1)", "$.coordinates"),
+            (
+                "GEOMETRYCOLLECTION (POINT (1 1), MULTILINESTRING ((1 1, 1.0000001 1)))",
+                "$.geometries[1].coordinates",
+            ),
+        ] {
+            let mut err = reduce_precision(&parse_wkt(wkt).unwrap(), 3).unwrap_err();
+            err.is_handled = true;
+            assert!(err.message.ends_with(path), "{}", err.message);
+        }
+    }
+
+    #[test]
+    fn test_feature_collection() {
+        let collection: GeoJson = r#"{"type": "FeatureCollection", "bbox": [0.123456, 0, 1, 1], "features": [
+            {"type": "Feature", "geometry": {"type": "Point", "coordinates": [0.123456, 0.654321]}, "properties": null},
+            {"type": "Feature", "geometry": {"type": "LineString", "coordinates": [[0, 0], [0.0001, 0]]}, "properties": null}
+        ]}"#
+        .parse()
+        .unwrap();
+        let GeoJson::FeatureCollection(collection) = collection else {
+            unreachable!()
+        };
+        let mut err = reduce_feature_collection_precision(&collection, 2).unwrap_err();
+        err.is_handled = true;
+        assert!(err.message.contains("feature 1"), "{}", err.message);
+        let reduced = reduce_feature_collection_precision(&collection, 4).unwrap();
+        assert_eq!(reduced.bbox, Some(vec![0.1235, 0.0, 1.0, 1.0]));
+        assert_eq!(
+            reduced.features[0].geometry.as_ref().unwrap().value,
+            GeometryValue::Point(vec![0.1235, 0.6543])
+        );
+    }
+}
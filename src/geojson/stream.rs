@@ -0,0 +1,710 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    f64::consts::PI,
+    fs::create_dir_all,
+    io::{BufRead, BufReader, Read, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use traceback_error::{
+    serde_json::{self, json, Value},
+    traceback, TracebackError,
+};
+
+use super::{crs::WEB_MERCATOR_MAX_LATITUDE, measure::bbox, Bbox, Feature};
+use crate::compression::{open_file, strip_compression_extension, CompressedWriter};
+
+/// The byte that starts every text in a GeoJSON text sequence, see RFC 8142.
+pub const RECORD_SEPARATOR: u8 = 0x1E;
+
+/// Reads the features of a FeatureCollection one at a time, without loading the whole document.
+///
+/// Only the `features` array is parsed into values, a feature at a time. Members before it are skipped,
+/// and the document after the array is never read. Errors carry the byte offset where reading failed,
+/// and the iterator ends after the first error.
+///
+/// ## Example
+///
+/// ```rust
+/// use utils::geojson::stream::FeatureReader;
+///
+/// let geojson = r#"{"type": "FeatureCollection", "features": [
+///     {"type": "Feature", "geometry": {"type": "Point", "coordinates": [1, 2]}, "properties": {"n": 1}},
+///     {"type": "Feature", "geometry": null, "properties": {"n": 2}}
+/// ]}"#;
+/// let features: Vec<_> = FeatureReader::new(geojson.as_bytes())
+///     .collect::<Result<_, _>>()
+///     .unwrap();
+/// assert_eq!(features.len(), 2);
+/// ```
+pub struct FeatureReader<R: Read> {
+    reader: BufReader<R>,
+    offset: u64,
+    state: ReaderState,
+    index: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReaderState {
+    Start,
+    InFeatures,
+    Done,
+}
+
+impl FeatureReader<Box<dyn Read>> {
+    /// Opens a FeatureCollection file, which may be gzip or zstd compressed.
+    pub fn open(path: &str) -> Result<Self, TracebackError> {
+        match open_file(path) {
+            Ok(reader) => Ok(FeatureReader::new(reader)),
+            Err(e) => Err(traceback!(err e, "Failed to open GeoJSON file")),
+        }
+    }
+}
+
+impl<R: Read> FeatureReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            offset: 0,
+            state: ReaderState::Start,
+            index: 0,
+        }
+    }
+
+    fn error(&self, message: &str) -> TracebackError {
+        traceback!(format!("Invalid GeoJSON stream: {message}"))
+            .with_extra_data(json!({ "offset": self.offset }))
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>, TracebackError> {
+        match self.reader.fill_buf() {
+            Ok(buf) => Ok(buf.first().copied()),
+            Err(e) => Err(traceback!("Failed to read GeoJSON")
+                .with_extra_data(json!({ "error": e.to_string(), "offset": self.offset }))),
+        }
+    }
+
+    fn bump(&mut self) {
+        self.reader.consume(1);
+        self.offset += 1;
+    }
+
+    /// Skips whitespace and returns the next byte without consuming it, or `None` at the end.
+    fn skip_whitespace(&mut self) -> Result<Option<u8>, TracebackError> {
+        loop {
+            match self.peek() {
+                Ok(Some(b' ' | b'\t' | b'\n' | b'\r')) => self.bump(),
+                Ok(byte) => return Ok(byte),
+                Err(e) => return Err(traceback!(err e)),
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<(), TracebackError> {
+        match self.skip_whitespace() {
+            Ok(Some(byte)) if byte == expected => {
+                self.bump();
+                Ok(())
+            }
+            Ok(Some(byte)) => Err(self.error(&format!(
+                "Expected {:?}, found {:?}",
+                expected as char, byte as char
+            ))),
+            Ok(None) => Err(self.error(&format!("Expected {:?}, found the end", expected as char))),
+            Err(e) => Err(traceback!(err e)),
+        }
+    }
+
+    /// Copies a string, quotes included, from the opening quote the reader is at.
+    fn read_string(&mut self, out: &mut Vec<u8>) -> Result<(), TracebackError> {
+        self.bump();
+        out.push(b'"');
+        let mut escaped = false;
+        loop {
+            let byte = match self.peek() {
+                Ok(Some(byte)) => byte,
+                Ok(None) => return Err(self.error("Unterminated string")),
+                Err(e) => return Err(traceback!(err e)),
+            };
+            self.bump();
+            out.push(byte);
+            match (escaped, byte) {
+                (false, b'"') => return Ok(()),
+                (false, b'\\') => escaped = true,
+                _ => escaped = false,
+            }
+        }
+    }
+
+    /// Copies the raw bytes of the next JSON value. Only strings and nesting are checked here,
+    /// the bytes are validated when they're parsed.
+    fn read_value(&mut self, out: &mut Vec<u8>) -> Result<(), TracebackError> {
+        let mut depth = 0usize;
+        loop {
+            let byte = match depth {
+                0 => self.skip_whitespace(),
+                _ => self.peek(),
+            };
+            let byte = match byte {
+                Ok(Some(byte)) => byte,
+                Ok(None) => return Err(self.error("Unexpected end of data")),
+                Err(e) => return Err(traceback!(err e)),
+            };
+            match byte {
+                b'"' => {
+                    if let Err(e) = self.read_string(out) {
+                        return Err(traceback!(err e));
+                    }
+                }
+                b'{' | b'[' => {
+                    self.bump();
+                    out.push(byte);
+                    depth += 1;
+                }
+                b'}' | b']' if depth > 0 => {
+                    self.bump();
+                    out.push(byte);
+                    depth -= 1;
+                }
+                // The end of a scalar at the top level
+                b',' | b'}' | b']' | b' ' | b'\t' | b'\n' | b'\r' if depth == 0 => return Ok(()),
+                _ => {
+                    self.bump();
+                    out.push(byte);
+                }
+            }
+            if depth == 0 && matches!(byte, b'"' | b'}' | b']') {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reads the members of the top-level object up to the opening bracket of `features`.
+    fn seek_features(&mut self) -> Result<(), TracebackError> {
+        if let Err(e) = self.expect(b'{') {
+            return Err(traceback!(err e));
+        }
+        let mut raw = vec![];
+        loop {
+            raw.clear();
+            match self.skip_whitespace() {
+                Ok(Some(b'"')) => {}
+                Ok(_) => return Err(self.error("FeatureCollection has no features")),
+                Err(e) => return Err(traceback!(err e)),
+            }
+            if let Err(e) = self.read_string(&mut raw) {
+                return Err(traceback!(err e));
+            }
+            let key: String = match serde_json::from_slice(&raw) {
+                Ok(key) => key,
+                Err(e) => return Err(self.error(&format!("Invalid member name: {e}"))),
+            };
+            if let Err(e) = self.expect(b':') {
+                return Err(traceback!(err e));
+            }
+            if key == "features" {
+                return match self.expect(b'[') {
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(traceback!(err e, "Expected the features to be an array")),
+                };
+            }
+            raw.clear();
+            if let Err(e) = self.read_value(&mut raw) {
+                return Err(traceback!(err e));
+            }
+            if key == "type" {
+                match serde_json::from_slice::<Value>(&raw) {
+                    Ok(Value::String(kind)) if kind == "FeatureCollection" => {}
+                    _ => {
+                        return Err(self.error(&format!(
+                            "Expected a FeatureCollection, found type {}",
+                            String::from_utf8_lossy(&raw)
+                        )))
+                    }
+                }
+            }
+            if let Err(e) = self.expect(b',') {
+                return Err(traceback!(err e, "FeatureCollection has no features"));
+            }
+        }
+    }
+
+    fn next_feature(&mut self) -> Result<Option<Feature>, TracebackError> {
+        if self.state == ReaderState::Start {
+            if let Err(e) = self.seek_features() {
+                return Err(traceback!(err e));
+            }
+            self.state = ReaderState::InFeatures;
+            match self.skip_whitespace() {
+                Ok(Some(b']')) => return Ok(None),
+                Ok(_) => {}
+                Err(e) => return Err(traceback!(err e)),
+            }
+        }
+        let mut raw = vec![];
+        if let Err(e) = self.read_value(&mut raw) {
+            return Err(traceback!(err e));
+        }
+        let i = self.index;
+        let feature = match serde_json::from_slice::<Value>(&raw) {
+            Ok(value) => match Feature::from_json_value(&value) {
+                Ok(feature) => feature,
+                Err(e) => {
+                    return Err(traceback!(err e, format!("Failed to read feature {i}"))
+                        .with_extra_data(json!({ "feature": i, "offset": self.offset })))
+                }
+            },
+            Err(e) => return Err(self.error(&format!("Feature {i} is not valid JSON: {e}"))),
+        };
+        self.index += 1;
+        match self.skip_whitespace() {
+            Ok(Some(b',')) => self.bump(),
+            Ok(Some(b']')) => {
+                self.bump();
+                self.state = ReaderState::Done;
+            }
+            Ok(_) => return Err(self.error("Expected \",\" or \"]\" after a feature")),
+            Err(e) => return Err(traceback!(err e)),
+        }
+        Ok(Some(feature))
+    }
+}
+
+impl<R: Read> Iterator for FeatureReader<R> {
+    type Item = Result<Feature, TracebackError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.state == ReaderState::Done {
+            return None;
+        }
+        match self.next_feature() {
+            Ok(Some(feature)) => Some(Ok(feature)),
+            Ok(None) => {
+                self.state = ReaderState::Done;
+                None
+            }
+            Err(e) => {
+                self.state = ReaderState::Done;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Writes features as a GeoJSON text sequence (RFC 8142): each feature on its own line,
+/// preceded by the record separator byte.
+pub struct GeoJsonSeqWriter<W: Write> {
+    writer: W,
+    count: usize,
+}
+
+impl GeoJsonSeqWriter<CompressedWriter> {
+    /// Creates a GeoJSONSeq file, compressed if its name ends in `.gz` or `.zst`.
+    pub fn create(path: &str) -> Result<Self, TracebackError> {
+        match CompressedWriter::create(path) {
+            Ok(writer) => Ok(GeoJsonSeqWriter::new(writer)),
+            Err(e) => Err(traceback!(err e, "Failed to create GeoJSONSeq file")),
+        }
+    }
+
+    /// Flushes the file and finishes its compression. Returns the number of features written.
+    pub fn finish(self) -> Result<usize, TracebackError> {
+        match self.writer.finish() {
+            Ok(_) => Ok(self.count),
+            Err(e) => Err(traceback!(err e)),
+        }
+    }
+}
+
+impl<W: Write> GeoJsonSeqWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, count: 0 }
+    }
+
+    pub fn write_feature(&mut self, feature: &Feature) -> Result<(), TracebackError> {
+        let mut text = vec![RECORD_SEPARATOR];
+        if let Err(e) = serde_json::to_writer(&mut text, &feature.to_json_value()) {
+            return Err(traceback!(format!("Failed to serialize feature: {e}")));
+        }
+        text.push(b'\n');
+        match self.writer.write_all(&text) {
+            Ok(_) => {
+                self.count += 1;
+                Ok(())
+            }
+            Err(e) => Err(traceback!("Failed to write GeoJSONSeq")
+                .with_extra_data(json!({ "error": e.to_string(), "feature": self.count }))),
+        }
+    }
+
+    /// The number of features written so far.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Streams the features of a FeatureCollection file into a GeoJSONSeq file, returning the number of features.
+///
+/// Either file may be gzip or zstd compressed, see `compression`.
+pub fn feature_collection_file_to_geojson_seq(
+    input: &str,
+    output: &str,
+) -> Result<usize, TracebackError> {
+    let reader = match FeatureReader::open(input) {
+        Ok(reader) => reader,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    let mut writer = match GeoJsonSeqWriter::create(output) {
+        Ok(writer) => writer,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    for feature in reader {
+        let feature = match feature {
+            Ok(feature) => feature,
+            Err(e) => return Err(traceback!(err e).with_extra_data(json!({ "path": input }))),
+        };
+        if let Err(e) = writer.write_feature(&feature) {
+            return Err(traceback!(err e).with_extra_data(json!({ "path": output })));
+        }
+    }
+    match writer.finish() {
+        Ok(count) => Ok(count),
+        Err(e) => Err(traceback!(err e)),
+    }
+}
+
+/// A web map tile in the XYZ scheme used by OpenStreetMap and most tile servers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Tile {
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl Tile {
+    /// The deepest zoom level supported.
+    pub const MAX_ZOOM: u8 = 30;
+
+    /// The tile containing a WGS84 position at a zoom level.
+    /// Latitudes beyond the reach of Web Mercator fall in the top or bottom row.
+    pub fn for_position(lon: f64, lat: f64, zoom: u8) -> Tile {
+        let zoom = zoom.min(Self::MAX_ZOOM);
+        let n = (1u64 << zoom) as f64;
+        let lat = lat
+            .clamp(-WEB_MERCATOR_MAX_LATITUDE, WEB_MERCATOR_MAX_LATITUDE)
+            .to_radians();
+        let x = ((lon + 180.0) / 360.0 * n).floor();
+        let y = ((1.0 - lat.tan().asinh() / PI) / 2.0 * n).floor();
+        Tile {
+            z: zoom,
+            x: x.clamp(0.0, n - 1.0) as u32,
+            y: y.clamp(0.0, n - 1.0) as u32,
+        }
+    }
+
+    /// The WGS84 bounding box of the tile as `[west, south, east, north]`.
+    pub fn bbox(&self) -> Bbox {
+        let n = (1u64 << self.z) as f64;
+        let lon = |x: f64| x / n * 360.0 - 180.0;
+        let lat = |y: f64| (PI * (1.0 - 2.0 * y / n)).sinh().atan().to_degrees();
+        let (x, y) = (self.x as f64, self.y as f64);
+        vec![lon(x), lat(y + 1.0), lon(x + 1.0), lat(y)]
+    }
+}
+
+/// How `split_feature_collection_file` groups features into files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureSplit {
+    /// Up to this many features per file, in the order they are read.
+    Chunks(usize),
+    /// One file per tile at this zoom level, by the center of each feature's bounding box.
+    Tiles { zoom: u8 },
+}
+
+/// A file written by `split_feature_collection_file`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitFile {
+    pub path: String,
+    /// The tile the features of the file fall in, when splitting by tiles.
+    pub tile: Option<Tile>,
+    pub feature_count: usize,
+}
+
+/// Splits a FeatureCollection file into smaller FeatureCollection files, streaming the input a feature at a time.
+///
+/// Files are written to `output_dir` as `<input file stem>_<chunk index>.geojson`,
+/// or `<input file stem>_<z>_<x>_<y>.geojson` for tiles, creating the directory if needed.
+/// When splitting by tiles, features are not clipped, and those without a geometry go to `<input file stem>_untiled.geojson`.
+/// Every tile file stays open until the input has been read, so pick a zoom level giving a manageable number of tiles.
+/// If the input is named like a compressed file, the outputs are compressed the same way.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use utils::geojson::stream::{split_feature_collection_file, FeatureSplit};
+///
+/// let files = split_feature_collection_file("parcels.geojson", "parcels_tiles", FeatureSplit::Tiles { zoom: 10 }).unwrap();
+/// for file in &files {
+///     println!("{}: {} features", file.path, file.feature_count);
+/// }
+/// ```
+pub fn split_feature_collection_file(
+    input: &str,
+    output_dir: &str,
+    split: FeatureSplit,
+) -> Result<Vec<SplitFile>, TracebackError> {
+    if let FeatureSplit::Tiles { zoom } = split {
+        if zoom > Tile::MAX_ZOOM {
+            return Err(traceback!(format!(
+                "Zoom level {zoom} is deeper than {}",
+                Tile::MAX_ZOOM
+            )));
+        }
+    }
+    let reader = match FeatureReader::open(input) {
+        Ok(reader) => reader,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    if let Err(e) = create_dir_all(output_dir) {
+        return Err(traceback!("Failed to create output directory")
+            .with_extra_data(json!({ "error": e.to_string(), "path": output_dir })));
+    }
+    // Outputs are compressed the same way as the input file
+    let (uncompressed, compression) = strip_compression_extension(input);
+    let stem = Path::new(uncompressed)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| "chunk".to_string());
+    let path_for = |name: &str| {
+        Path::new(output_dir)
+            .join(format!("{stem}_{name}.geojson{}", compression.extension()))
+            .to_string_lossy()
+            .to_string()
+    };
+
+    let mut files = vec![];
+    let mut current: Option<CollectionFile> = None;
+    let mut tiles: HashMap<Option<Tile>, CollectionFile> = HashMap::new();
+    for (i, feature) in reader.enumerate() {
+        let feature = match feature {
+            Ok(feature) => feature,
+            Err(e) => return Err(traceback!(err e).with_extra_data(json!({ "path": input }))),
+        };
+        let file = match split {
+            FeatureSplit::Chunks(size) => {
+                if current
+                    .as_ref()
+                    .is_some_and(|file| file.split.feature_count >= size.max(1))
+                {
+                    match current.take().map(CollectionFile::finish) {
+                        Some(Ok(file)) => files.push(file),
+                        Some(Err(e)) => return Err(traceback!(err e)),
+                        None => {}
+                    }
+                }
+                if current.is_none() {
+                    let path = path_for(&files.len().to_string());
+                    match CollectionFile::create(path, None) {
+                        Ok(file) => current = Some(file),
+                        Err(e) => return Err(traceback!(err e)),
+                    }
+                }
+                current.as_mut()
+            }
+            FeatureSplit::Tiles { zoom } => {
+                let tile =
+                    feature.geometry.as_ref().and_then(bbox).map(|b| {
+                        Tile::for_position((b[0] + b[2]) / 2.0, (b[1] + b[3]) / 2.0, zoom)
+                    });
+                match tiles.entry(tile) {
+                    Entry::Occupied(entry) => Some(entry.into_mut()),
+                    Entry::Vacant(entry) => {
+                        let name = match tile {
+                            Some(Tile { z, x, y }) => format!("{z}_{x}_{y}"),
+                            None => "untiled".to_string(),
+                        };
+                        match CollectionFile::create(path_for(&name), tile) {
+                            Ok(file) => Some(entry.insert(file)),
+                            Err(e) => return Err(traceback!(err e)),
+                        }
+                    }
+                }
+            }
+        };
+        if let Some(file) = file {
+            if let Err(e) = file.write(&feature) {
+                return Err(traceback!(err e).with_extra_data(json!({ "feature": i })));
+            }
+        }
+    }
+    let mut open: Vec<CollectionFile> = current.into_iter().chain(tiles.into_values()).collect();
+    // Tiles in order, with the untiled features first
+    open.sort_by_key(|file| file.split.tile);
+    for file in open {
+        match file.finish() {
+            Ok(file) => files.push(file),
+            Err(e) => return Err(traceback!(err e)),
+        }
+    }
+    Ok(files)
+}
+
+/// A FeatureCollection file being written a feature at a time.
+struct CollectionFile {
+    writer: CompressedWriter,
+    split: SplitFile,
+}
+
+impl CollectionFile {
+    fn create(path: String, tile: Option<Tile>) -> Result<Self, TracebackError> {
+        let mut writer = match CompressedWriter::create(&path) {
+            Ok(writer) => writer,
+            Err(e) => return Err(traceback!(err e)),
+        };
+        if let Err(e) = writer.write_all(b"{\"type\":\"FeatureCollection\",\"features\":[\n") {
+            return Err(traceback!("Failed to write GeoJSON file")
+                .with_extra_data(json!({ "error": e.to_string(), "path": path })));
+        }
+        Ok(Self {
+            writer,
+            split: SplitFile {
+                path,
+                tile,
+                feature_count: 0,
+            },
+        })
+    }
+
+    fn write(&mut self, feature: &Feature) -> Result<(), TracebackError> {
+        let mut text = match self.split.feature_count {
+            0 => vec![],
+            _ => b",\n".to_vec(),
+        };
+        if let Err(e) = serde_json::to_writer(&mut text, &feature.to_json_value()) {
+            return Err(traceback!(format!("Failed to serialize feature: {e}")));
+        }
+        match self.writer.write_all(&text) {
+            Ok(_) => {
+                self.split.feature_count += 1;
+                Ok(())
+            }
+            Err(e) => Err(traceback!("Failed to write GeoJSON file")
+                .with_extra_data(json!({ "error": e.to_string(), "path": self.split.path }))),
+        }
+    }
+
+    fn finish(mut self) -> Result<SplitFile, TracebackError> {
+        if let Err(e) = self.writer.write_all(b"\n]}\n") {
+            return Err(traceback!("Failed to write GeoJSON file")
+                .with_extra_data(json!({ "error": e.to_string(), "path": self.split.path })));
+        }
+        match self.writer.finish() {
+            Ok(_) => Ok(self.split),
+            Err(e) => Err(traceback!(err e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geojson::{FeatureCollection, GeometryValue};
+
+    const COLLECTION: &str = r#"{
+        "type": "FeatureCollection",
+        "name": "places, \"quoted\" [and] {bracketed}",
+        "bbox": [-10, -10, 20, 70],
+        "features": [
+            {"type": "Feature", "geometry": {"type": "Point", "coordinates": [10.75, 59.91]}, "properties": {"name": "Oslo ]}"}},
+            {"type": "Feature", "geometry": {"type": "Point", "coordinates": [-9.14, 38.72]}, "properties": {"name": "Lisbon"}},
+            {"type": "Feature", "geometry": null, "properties": {"name": "Nowhere"}}
+        ],
+        "trailing": true
+    }"#;
+
+    #[test]
+    fn test_feature_reader() {
+        let features: Vec<Feature> = FeatureReader::new(COLLECTION.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let expected =
+            FeatureCollection::from_json_value(&serde_json::from_str(COLLECTION).unwrap()).unwrap();
+        assert_eq!(features, expected.features);
+
+        let empty = r#"{"type": "FeatureCollection", "features": [ ]}"#;
+        assert_eq!(FeatureReader::new(empty.as_bytes()).count(), 0);
+
+        let broken = r#"{"type": "FeatureCollection", "features": [{"type": "Feature", "geometry": null, "properties": null}, {"type": "Point"}]}"#;
+        let mut results: Vec<_> = FeatureReader::new(broken.as_bytes()).collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        let mut err = results.pop().unwrap().unwrap_err();
+        err.is_handled = true;
+        assert!(err.message.contains("feature 1"), "{}", err.message);
+
+        let mut err = FeatureReader::new(&br#"{"type": "Feature"}"#[..])
+            .next()
+            .unwrap()
+            .unwrap_err();
+        err.is_handled = true;
+    }
+
+    #[test]
+    fn test_geojson_seq_and_split() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("places.geojson.gz");
+        let input = input.to_str().unwrap();
+        crate::compression::write_file(input, COLLECTION.as_bytes()).unwrap();
+
+        let seq = dir.path().join("places.geojsons");
+        let seq = seq.to_str().unwrap();
+        assert_eq!(
+            feature_collection_file_to_geojson_seq(input, seq).unwrap(),
+            3
+        );
+        let text = std::fs::read_to_string(seq).unwrap();
+        assert_eq!(text.matches('\u{1e}').count(), 3);
+        assert!(text.lines().all(|line| line.starts_with('\u{1e}')));
+
+        let out = dir.path().join("chunks");
+        let out = out.to_str().unwrap();
+        let files = split_feature_collection_file(input, out, FeatureSplit::Chunks(2)).unwrap();
+        let counts: Vec<usize> = files.iter().map(|f| f.feature_count).collect();
+        assert_eq!(counts, vec![2, 1]);
+        assert!(files[0].path.ends_with("places_0.geojson.gz"));
+        let chunk: Vec<Feature> = FeatureReader::open(&files[1].path)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(chunk[0].property("name"), Some(&json!("Nowhere")));
+
+        let out = dir.path().join("tiles");
+        let out = out.to_str().unwrap();
+        let files =
+            split_feature_collection_file(input, out, FeatureSplit::Tiles { zoom: 4 }).unwrap();
+        let tiles: Vec<Option<Tile>> = files.iter().map(|f| f.tile).collect();
+        assert_eq!(
+            tiles,
+            vec![
+                None,
+                Some(Tile { z: 4, x: 7, y: 6 }),
+                Some(Tile { z: 4, x: 8, y: 4 })
+            ]
+        );
+        let oslo = Tile::for_position(10.75, 59.91, 4).bbox();
+        assert!(oslo[0] <= 10.75 && 10.75 <= oslo[2] && oslo[1] <= 59.91 && 59.91 <= oslo[3]);
+        let lisbon: Vec<Feature> = FeatureReader::open(&files[1].path)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            lisbon[0].geometry.as_ref().unwrap().value,
+            GeometryValue::Point(vec![-9.14, 38.72])
+        );
+    }
+}
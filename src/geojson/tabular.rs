@@ -0,0 +1,396 @@
+use traceback_error::{
+    serde_json::{json, Map, Value},
+    traceback, TracebackError,
+};
+
+use super::{
+    coordinate_to_json,
+    wkt::{parse_wkt, to_wkt},
+    Feature, FeatureCollection, FeatureId, Geometry, GeometryValue,
+};
+use crate::csv2json::{csv_file_to_json, finish_csv_writer, new_csv_writer};
+
+/// Where the geometry of each row is stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GeometryColumns {
+    /// A point per row, as decimal degrees in two columns.
+    LatLon { lat: String, lon: String },
+    /// Any geometry, as Well-Known Text in one column.
+    Wkt(String),
+}
+
+impl Default for GeometryColumns {
+    fn default() -> Self {
+        GeometryColumns::LatLon {
+            lat: "lat".to_string(),
+            lon: "lon".to_string(),
+        }
+    }
+}
+
+/// Options for converting between table rows and GeoJSON features.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TableGeometryOptions {
+    pub geometry: GeometryColumns,
+    /// A column holding the feature `id`, which is then not repeated among the properties.
+    pub id_column: Option<String>,
+}
+
+/// Converts rows, such as the output of `csv2json::csv_to_json`, into a `FeatureCollection`.
+///
+/// Each row becomes a feature whose geometry is read from the columns named by `options.geometry`,
+/// and whose properties are all the other columns. A row with empty geometry columns becomes a feature
+/// without a geometry. Values that can't be read as coordinates or WKT are errors naming the row.
+///
+/// ## Example
+///
+/// ```rust
+/// use serde_json::json;
+/// use utils::geojson::{tabular::{rows_to_feature_collection, TableGeometryOptions}, GeometryValue};
+///
+/// let rows = json!([{"name": "Oslo", "lat": "59.91", "lon": "10.75"}]);
+/// let collection = rows_to_feature_collection(&rows, &TableGeometryOptions::default()).unwrap();
+/// let feature = &collection.features[0];
+/// assert_eq!(
+///     feature.geometry.as_ref().unwrap().value,
+///     GeometryValue::Point(vec![10.75, 59.91])
+/// );
+/// assert_eq!(feature.property("name"), Some(&json!("Oslo")));
+/// ```
+pub fn rows_to_feature_collection(
+    rows: &Value,
+    options: &TableGeometryOptions,
+) -> Result<FeatureCollection, TracebackError> {
+    let rows = match rows.as_array() {
+        Some(rows) => rows,
+        None => {
+            return Err(traceback!("Expected an array of rows")
+                .with_extra_data(json!({ "rows": rows.to_string() })))
+        }
+    };
+    let mut features = Vec::with_capacity(rows.len());
+    for (i, row) in rows.iter().enumerate() {
+        let row = match row.as_object() {
+            Some(row) => row,
+            None => {
+                return Err(traceback!(format!("Row {i} is not an object"))
+                    .with_extra_data(json!({ "row": i })))
+            }
+        };
+        match row_to_feature(row, options) {
+            Ok(feature) => features.push(feature),
+            Err(e) => {
+                return Err(
+                    traceback!(err e, format!("Failed to convert row {i} to a feature"))
+                        .with_extra_data(json!({ "row": i })),
+                )
+            }
+        }
+    }
+    Ok(FeatureCollection {
+        features,
+        ..Default::default()
+    })
+}
+
+/// Reads a CSV file with `csv2json::csv_file_to_json` and converts its rows to a `FeatureCollection`,
+/// see `rows_to_feature_collection`.
+pub fn csv_file_to_feature_collection(
+    path: &str,
+    options: &TableGeometryOptions,
+) -> Result<FeatureCollection, TracebackError> {
+    let rows = match csv_file_to_json(path) {
+        Ok(rows) => rows,
+        Err(e) => return Err(traceback!(err e, "Failed to read CSV file")),
+    };
+    match rows_to_feature_collection(&rows, options) {
+        Ok(collection) => Ok(collection),
+        Err(e) => Err(traceback!(err e).with_extra_data(json!({ "path": path }))),
+    }
+}
+
+fn row_to_feature(
+    row: &Map<String, Value>,
+    options: &TableGeometryOptions,
+) -> Result<Feature, TracebackError> {
+    let geometry_columns: Vec<&str> = match &options.geometry {
+        GeometryColumns::LatLon { lat, lon } => vec![lat, lon],
+        GeometryColumns::Wkt(column) => vec![column],
+    };
+    let geometry = match &options.geometry {
+        GeometryColumns::LatLon { lat, lon } => match (cell_text(row, lat), cell_text(row, lon)) {
+            (None, None) => None,
+            (Some(lat_text), Some(lon_text)) => {
+                match (lat_text.parse::<f64>(), lon_text.parse::<f64>()) {
+                    (Ok(lat), Ok(lon)) => Some(Geometry::new(GeometryValue::Point(vec![lon, lat]))),
+                    _ => {
+                        return Err(traceback!(format!(
+                            "Coordinates in columns {lat} and {lon} are not numbers"
+                        ))
+                        .with_extra_data(json!({ lat: lat_text, lon: lon_text })))
+                    }
+                }
+            }
+            (lat_text, lon_text) => {
+                return Err(traceback!(format!(
+                    "Only one of the coordinate columns {lat} and {lon} is set"
+                ))
+                .with_extra_data(json!({ lat: lat_text, lon: lon_text })))
+            }
+        },
+        GeometryColumns::Wkt(column) => match cell_text(row, column) {
+            None => None,
+            Some(wkt) => match parse_wkt(&wkt) {
+                Ok(geometry) => Some(geometry),
+                Err(e) => {
+                    return Err(traceback!(err e, format!("Invalid WKT in column {column}"))
+                        .with_extra_data(json!({ "column": column, "wkt": wkt })))
+                }
+            },
+        },
+    };
+    let id = match &options.id_column {
+        Some(column) => match row.get(column) {
+            Some(Value::Number(n)) => Some(FeatureId::Number(n.clone())),
+            Some(Value::String(s)) if !s.is_empty() => Some(FeatureId::String(s.clone())),
+            _ => None,
+        },
+        None => None,
+    };
+    let properties = row
+        .iter()
+        .filter(|(key, _)| {
+            !geometry_columns.contains(&key.as_str()) && Some(*key) != options.id_column.as_ref()
+        })
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    Ok(Feature {
+        id,
+        geometry,
+        properties: Some(properties),
+        ..Default::default()
+    })
+}
+
+/// The trimmed text of a cell, or `None` if it is missing, null or blank.
+fn cell_text(row: &Map<String, Value>, column: &str) -> Option<String> {
+    let text = match row.get(column)? {
+        Value::Null => return None,
+        Value::String(s) => s.trim().to_string(),
+        other => other.to_string(),
+    };
+    match text.is_empty() {
+        true => None,
+        false => Some(text),
+    }
+}
+
+/// Converts a `FeatureCollection` into rows, the reverse of `rows_to_feature_collection`.
+///
+/// Properties become columns, with objects and arrays written as JSON text, and the geometry is written to
+/// the columns named by `options.geometry`. With `GeometryColumns::LatLon`, every geometry must be a point.
+pub fn feature_collection_to_rows(
+    collection: &FeatureCollection,
+    options: &TableGeometryOptions,
+) -> Result<Value, TracebackError> {
+    match features_to_rows(collection, options) {
+        Ok(rows) => Ok(Value::Array(rows.into_iter().map(Value::Object).collect())),
+        Err(e) => Err(traceback!(err e)),
+    }
+}
+
+fn features_to_rows(
+    collection: &FeatureCollection,
+    options: &TableGeometryOptions,
+) -> Result<Vec<Map<String, Value>>, TracebackError> {
+    let mut rows = Vec::with_capacity(collection.features.len());
+    for (i, feature) in collection.features.iter().enumerate() {
+        match feature_to_row(feature, options) {
+            Ok(row) => rows.push(row),
+            Err(e) => {
+                return Err(
+                    traceback!(err e, format!("Failed to convert feature {i} to a row"))
+                        .with_extra_data(json!({ "feature": i })),
+                )
+            }
+        }
+    }
+    Ok(rows)
+}
+
+fn feature_to_row(
+    feature: &Feature,
+    options: &TableGeometryOptions,
+) -> Result<Map<String, Value>, TracebackError> {
+    let mut row = Map::new();
+    if let Some(column) = &options.id_column {
+        let id = match &feature.id {
+            Some(FeatureId::String(id)) => Value::String(id.clone()),
+            Some(FeatureId::Number(id)) => Value::Number(id.clone()),
+            None => Value::Null,
+        };
+        row.insert(column.clone(), id);
+    }
+    for (key, value) in feature.properties.iter().flatten() {
+        let value = match value {
+            Value::Array(_) | Value::Object(_) => Value::String(value.to_string()),
+            other => other.clone(),
+        };
+        row.insert(key.clone(), value);
+    }
+    match (&options.geometry, &feature.geometry) {
+        (GeometryColumns::LatLon { lat, lon }, None) => {
+            row.insert(lat.clone(), Value::Null);
+            row.insert(lon.clone(), Value::Null);
+        }
+        (GeometryColumns::LatLon { lat, lon }, Some(geometry)) => match &geometry.value {
+            GeometryValue::Point(p) => {
+                row.insert(lat.clone(), coordinate_to_json(p[1]));
+                row.insert(lon.clone(), coordinate_to_json(p[0]));
+            }
+            other => {
+                return Err(traceback!(format!(
+                    "Only points fit in lat/lon columns, not a {}",
+                    other.type_name()
+                )))
+            }
+        },
+        (GeometryColumns::Wkt(column), geometry) => {
+            let wkt = match geometry {
+                Some(geometry) => Value::String(to_wkt(geometry)),
+                None => Value::Null,
+            };
+            row.insert(column.clone(), wkt);
+        }
+    }
+    Ok(row)
+}
+
+/// Writes a `FeatureCollection` as CSV, see `feature_collection_to_rows`.
+///
+/// The id column comes first, then the properties in the order they first appear, then the geometry columns.
+pub fn feature_collection_to_csv(
+    collection: &FeatureCollection,
+    options: &TableGeometryOptions,
+) -> Result<String, TracebackError> {
+    let rows = match features_to_rows(collection, options) {
+        Ok(rows) => rows,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    let mut columns: Vec<String> = options.id_column.iter().cloned().collect();
+    for feature in &collection.features {
+        for key in feature.properties.iter().flat_map(|p| p.keys()) {
+            if !columns.contains(key) {
+                columns.push(key.clone());
+            }
+        }
+    }
+    match &options.geometry {
+        GeometryColumns::LatLon { lat, lon } => {
+            columns.retain(|c| c != lat && c != lon);
+            columns.push(lat.clone());
+            columns.push(lon.clone());
+        }
+        GeometryColumns::Wkt(column) => {
+            columns.retain(|c| c != column);
+            columns.push(column.clone());
+        }
+    }
+    let mut wtr = new_csv_writer();
+    if let Err(e) = wtr.write_record(&columns) {
+        return Err(traceback!("Failed to write CSV headers")
+            .with_extra_data(json!({ "error": e.to_string() })));
+    }
+    for row in &rows {
+        let record = columns.iter().map(|column| match row.get(column) {
+            None | Some(Value::Null) => String::new(),
+            Some(Value::String(s)) => s.clone(),
+            Some(other) => other.to_string(),
+        });
+        if let Err(e) = wtr.write_record(record) {
+            return Err(traceback!("Failed to write CSV record")
+                .with_extra_data(json!({ "error": e.to_string() })));
+        }
+    }
+    finish_csv_writer(wtr)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::csv2json::csv_to_json;
+
+    use super::*;
+
+    #[test]
+    fn test_lat_lon_round_trip() {
+        let csv = "id,name,lat,lon\n1,Oslo,59.91,10.75\n2,Nowhere,,\n";
+        let rows = csv_to_json(csv::Reader::from_reader(csv.as_bytes())).unwrap();
+        let options = TableGeometryOptions {
+            id_column: Some("id".to_string()),
+            ..Default::default()
+        };
+        let collection = rows_to_feature_collection(&rows, &options).unwrap();
+        assert_eq!(
+            collection.features[0].id,
+            Some(FeatureId::String("1".to_string()))
+        );
+        assert_eq!(collection.features[1].geometry, None);
+        assert_eq!(collection.features[1].property("id"), None);
+        assert_eq!(
+            feature_collection_to_csv(&collection, &options).unwrap(),
+            "id,name,lat,lon\n1,Oslo,59.91,10.75\n2,Nowhere,,\n"
+        );
+
+        let rows = json!([
+            {"name": "Oslo", "lat": "59.91", "lon": "10.75"},
+            {"name": "Bergen", "lat": "60.39", "lon": ""},
+            {"name": "Trondheim", "lat": "63.43", "lon": "east"}
+        ]);
+        for (row, message) in [
+            (1, "Only one of the coordinate columns lat and lon is set"),
+            (2, "Coordinates in columns lat and lon are not numbers"),
+        ] {
+            let rows = json!([rows[0], rows[row]]);
+            let mut err = rows_to_feature_collection(&rows, &options).unwrap_err();
+            err.is_handled = true;
+            assert_eq!(err.message, "Failed to convert row 1 to a feature");
+            assert_eq!(err.extra_data[0]["row"], 1);
+            assert_eq!(err.parent.as_ref().unwrap().message, message);
+        }
+    }
+
+    #[test]
+    fn test_wkt_column() {
+        let rows = json!([
+            {"name": "road", "geom": "LINESTRING (0 0, 1 1)"},
+            {"name": "bad", "geom": "LINESTRING (0 0, 1"}
+        ]);
+        let options = TableGeometryOptions {
+            geometry: GeometryColumns::Wkt("geom".to_string()),
+            id_column: None,
+        };
+        let mut err = rows_to_feature_collection(&rows, &options).unwrap_err();
+        err.is_handled = true;
+        assert_eq!(err.message, "Failed to convert row 1 to a feature");
+        assert_eq!(err.extra_data[0]["row"], 1);
+        let parent = err.parent.as_ref().unwrap();
+        assert_eq!(parent.message, "Invalid WKT in column geom");
+        assert_eq!(parent.extra_data[0]["column"], "geom");
+
+        let collection = rows_to_feature_collection(&json!([rows[0]]), &options).unwrap();
+        assert_eq!(
+            feature_collection_to_rows(&collection, &options).unwrap(),
+            json!([rows[0]])
+        );
+        let mut err =
+            feature_collection_to_rows(&collection, &TableGeometryOptions::default()).unwrap_err();
+        err.is_handled = true;
+        let err = err.parent.as_ref().unwrap();
+        assert_eq!(err.message, "Failed to convert feature 0 to a row");
+        assert_eq!(err.extra_data[0]["feature"], 0);
+        assert_eq!(
+            err.parent.as_ref().unwrap().message,
+            "Only points fit in lat/lon columns, not a LineString"
+        );
+    }
+}
@@ -0,0 +1,838 @@
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+
+use traceback_error::{
+    serde_json::{json, Map, Value},
+    traceback, TracebackError,
+};
+
+use super::{
+    coordinate_to_json, measure::collection_bbox, Feature, FeatureCollection, FeatureId, Geometry,
+    GeometryValue, Position,
+};
+
+/// Options for `feature_collection_to_topojson`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopoJsonOptions {
+    /// The name of the object holding the features in the topology's `objects`.
+    pub object_name: String,
+    /// The number of distinct values per axis that coordinates are snapped to, which also turns on delta encoding.
+    /// `None` keeps the coordinates exact.
+    pub quantization: Option<u32>,
+}
+
+impl Default for TopoJsonOptions {
+    fn default() -> Self {
+        Self {
+            object_name: "collection".to_string(),
+            quantization: Some(10_000),
+        }
+    }
+}
+
+/// Converts a feature collection into a TopoJSON topology.
+///
+/// Lines and rings are cut where they meet others, and each stretch shared by several geometries,
+/// like the border between two neighboring regions, is stored once as an arc.
+/// With quantization, coordinates become integers on a grid over the bounding box of the collection,
+/// and arcs store each position as the difference from the previous one.
+/// Only longitude and latitude are kept.
+///
+/// ## Example
+///
+/// ```rust
+/// use utils::geojson::{topojson::{feature_collection_to_topojson, TopoJsonOptions}, FeatureCollection};
+/// use serde_json::json;
+///
+/// let square = |x: i32| json!({
+///     "type": "Feature",
+///     "geometry": {"type": "Polygon", "coordinates": [[[x, 0], [x + 1, 0], [x + 1, 1], [x, 1], [x, 0]]]},
+///     "properties": {"x": x}
+/// });
+/// let collection = FeatureCollection::from_json_value(&json!({
+///     "type": "FeatureCollection",
+///     "features": [square(0), square(1)]
+/// }))
+/// .unwrap();
+/// let topology = feature_collection_to_topojson(&collection, &TopoJsonOptions::default()).unwrap();
+/// // The shared edge is stored once
+/// assert_eq!(topology["arcs"].as_array().unwrap().len(), 3);
+/// ```
+pub fn feature_collection_to_topojson(
+    collection: &FeatureCollection,
+    options: &TopoJsonOptions,
+) -> Result<Value, TracebackError> {
+    let quantizer = match options.quantization {
+        Some(q) if q < 2 => {
+            return Err(traceback!(format!(
+                "Quantization must be at least 2, found {q}"
+            )))
+        }
+        Some(q) => collection_bbox(collection).map(|bbox| Quantizer::new(&bbox, q)),
+        None => None,
+    };
+    let mut encoder = Encoder {
+        quantizer,
+        paths: vec![],
+    };
+    let shapes: Vec<Option<Shape>> = collection
+        .features
+        .iter()
+        .map(|f| f.geometry.as_ref().map(|g| encoder.shape(g)))
+        .collect();
+    let (arcs, path_arcs) = encoder.arcs();
+
+    let geometries: Vec<Value> = collection
+        .features
+        .iter()
+        .zip(&shapes)
+        .map(|(feature, shape)| {
+            let mut obj = match shape {
+                Some(shape) => encoder.shape_to_json(shape, &path_arcs),
+                None => {
+                    let mut obj = Map::new();
+                    obj.insert("type".to_string(), Value::Null);
+                    obj
+                }
+            };
+            if let Some(id) = &feature.id {
+                obj.insert("id".to_string(), feature_id_to_json(id));
+            }
+            if let Some(properties) = &feature.properties {
+                obj.insert("properties".to_string(), Value::Object(properties.clone()));
+            }
+            Value::Object(obj)
+        })
+        .collect();
+
+    let mut topology = Map::new();
+    topology.insert("type".to_string(), json!("Topology"));
+    if let Some(bbox) = collection_bbox(collection) {
+        topology.insert(
+            "bbox".to_string(),
+            Value::Array(bbox.iter().map(|c| coordinate_to_json(*c)).collect()),
+        );
+    }
+    if let Some(quantizer) = &encoder.quantizer {
+        topology.insert(
+            "transform".to_string(),
+            json!({ "scale": quantizer.scale, "translate": quantizer.translate }),
+        );
+    }
+    let mut objects = Map::new();
+    objects.insert(
+        options.object_name.clone(),
+        json!({ "type": "GeometryCollection", "geometries": geometries }),
+    );
+    topology.insert("objects".to_string(), Value::Object(objects));
+    topology.insert(
+        "arcs".to_string(),
+        Value::Array(arcs.iter().map(|arc| encoder.arc_to_json(arc)).collect()),
+    );
+    Ok(Value::Object(topology))
+}
+
+/// Converts an object of a TopoJSON topology back into a feature collection.
+///
+/// A `GeometryCollection` object becomes one feature per geometry, and any other object becomes a single feature.
+/// Errors name the path of the offending member, like `$.objects.regions.geometries[2].arcs[0][1]`.
+pub fn topojson_to_feature_collection(
+    topology: &Value,
+    object_name: &str,
+) -> Result<FeatureCollection, TracebackError> {
+    if topology.get("type").and_then(Value::as_str) != Some("Topology") {
+        return Err(invalid("Expected a Topology", "$.type"));
+    }
+    let transform = match topology.get("transform") {
+        None | Some(Value::Null) => None,
+        Some(transform) => match parse_transform(transform) {
+            Ok(transform) => Some(transform),
+            Err(e) => return Err(traceback!(err e)),
+        },
+    };
+    let arcs = match decode_arcs(topology.get("arcs"), &transform) {
+        Ok(arcs) => arcs,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    let path = format!("$.objects.{object_name}");
+    let object = match topology.get("objects").and_then(|o| o.get(object_name)) {
+        Some(object) => object,
+        None => {
+            return Err(invalid(&format!("No object named {object_name:?}"), &path)
+                .with_extra_data(json!({ "object": object_name })))
+        }
+    };
+    let decoder = Decoder {
+        arcs: &arcs,
+        transform: &transform,
+    };
+    let objects: Vec<(Value, String)> = match (
+        object.get("type").and_then(Value::as_str),
+        object.get("geometries").and_then(Value::as_array),
+    ) {
+        (Some("GeometryCollection"), Some(geometries)) => geometries
+            .iter()
+            .enumerate()
+            .map(|(i, g)| (g.clone(), format!("{path}.geometries[{i}]")))
+            .collect(),
+        _ => vec![(object.clone(), path)],
+    };
+    let mut features = Vec::with_capacity(objects.len());
+    for (object, path) in &objects {
+        match decoder.feature(object, path) {
+            Ok(feature) => features.push(feature),
+            Err(e) => return Err(traceback!(err e)),
+        }
+    }
+    Ok(FeatureCollection {
+        features,
+        ..Default::default()
+    })
+}
+
+/// A position as the key arcs are matched by: grid coordinates when quantizing, or the bits of the floats.
+type Key = [i64; 2];
+
+struct Quantizer {
+    scale: [f64; 2],
+    translate: [f64; 2],
+}
+
+impl Quantizer {
+    fn new(bbox: &[f64], quantization: u32) -> Self {
+        let steps = (quantization - 1) as f64;
+        let scale = |min: f64, max: f64| match max > min {
+            true => (max - min) / steps,
+            false => 1.0,
+        };
+        Self {
+            scale: [scale(bbox[0], bbox[2]), scale(bbox[1], bbox[3])],
+            translate: [bbox[0], bbox[1]],
+        }
+    }
+}
+
+/// A line or ring, as keys.
+struct Path {
+    points: Vec<Key>,
+    ring: bool,
+}
+
+/// A geometry with its lines and rings replaced by indices into `Encoder::paths`.
+enum Shape {
+    Point(Key),
+    MultiPoint(Vec<Key>),
+    LineString(usize),
+    MultiLineString(Vec<usize>),
+    Polygon(Vec<usize>),
+    MultiPolygon(Vec<Vec<usize>>),
+    GeometryCollection(Vec<Shape>),
+}
+
+struct Encoder {
+    quantizer: Option<Quantizer>,
+    paths: Vec<Path>,
+}
+
+impl Encoder {
+    fn key(&self, position: &Position) -> Key {
+        match &self.quantizer {
+            Some(q) => [
+                ((position[0] - q.translate[0]) / q.scale[0]).round() as i64,
+                ((position[1] - q.translate[1]) / q.scale[1]).round() as i64,
+            ],
+            None => [position[0].to_bits() as i64, position[1].to_bits() as i64],
+        }
+    }
+
+    fn key_to_json(&self, key: Key) -> Value {
+        match self.quantizer {
+            Some(_) => json!(key),
+            None => Value::Array(
+                key.iter()
+                    .map(|k| coordinate_to_json(f64::from_bits(*k as u64)))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn path(&mut self, positions: &[Position], ring: bool) -> usize {
+        let mut points: Vec<Key> = positions.iter().map(|p| self.key(p)).collect();
+        // Quantizing can snap neighbors to the same position
+        points.dedup();
+        if points.len() == 1 {
+            points.push(points[0]);
+        }
+        self.paths.push(Path { points, ring });
+        self.paths.len() - 1
+    }
+
+    fn shape(&mut self, geometry: &Geometry) -> Shape {
+        match &geometry.value {
+            GeometryValue::Point(p) => Shape::Point(self.key(p)),
+            GeometryValue::MultiPoint(ps) => {
+                Shape::MultiPoint(ps.iter().map(|p| self.key(p)).collect())
+            }
+            GeometryValue::LineString(line) => Shape::LineString(self.path(line, false)),
+            GeometryValue::MultiLineString(lines) => {
+                Shape::MultiLineString(lines.iter().map(|l| self.path(l, false)).collect())
+            }
+            GeometryValue::Polygon(rings) => {
+                Shape::Polygon(rings.iter().map(|r| self.path(r, true)).collect())
+            }
+            GeometryValue::MultiPolygon(polygons) => Shape::MultiPolygon(
+                polygons
+                    .iter()
+                    .map(|rings| rings.iter().map(|r| self.path(r, true)).collect())
+                    .collect(),
+            ),
+            GeometryValue::GeometryCollection(geometries) => {
+                Shape::GeometryCollection(geometries.iter().map(|g| self.shape(g)).collect())
+            }
+        }
+    }
+
+    /// Positions where paths meet or part: line ends, and positions whose neighbors differ between visits.
+    fn junctions(&self) -> HashSet<Key> {
+        let mut junctions = HashSet::new();
+        let mut neighbors: HashMap<Key, (Key, Key)> = HashMap::new();
+        let mut visit = |point: Key, a: Key, b: Key, junctions: &mut HashSet<Key>| {
+            let pair = match a <= b {
+                true => (a, b),
+                false => (b, a),
+            };
+            match neighbors.entry(point) {
+                Entry::Vacant(entry) => {
+                    entry.insert(pair);
+                }
+                Entry::Occupied(entry) => {
+                    if *entry.get() != pair {
+                        junctions.insert(point);
+                    }
+                }
+            }
+        };
+        // Empty lines and rings have no arcs, so they can't meet anything
+        for path in self.paths.iter().filter(|path| path.points.len() >= 2) {
+            let points = &path.points;
+            if path.ring {
+                let open = &points[..points.len() - 1];
+                let m = open.len();
+                for i in 0..m {
+                    visit(
+                        open[i],
+                        open[(i + m - 1) % m],
+                        open[(i + 1) % m],
+                        &mut junctions,
+                    );
+                }
+            } else {
+                junctions.insert(points[0]);
+                junctions.insert(points[points.len() - 1]);
+                for window in points.windows(3) {
+                    visit(window[1], window[0], window[2], &mut junctions);
+                }
+            }
+        }
+        junctions
+    }
+
+    /// Cuts every path at its junctions and stores each distinct stretch once,
+    /// returning the arcs and, for each path, its arc references. A reference `!i` is arc `i` reversed.
+    fn arcs(&self) -> (Vec<Vec<Key>>, Vec<Vec<i64>>) {
+        let junctions = self.junctions();
+        let mut arcs: Vec<Vec<Key>> = vec![];
+        let mut index: HashMap<Vec<Key>, usize> = HashMap::new();
+        let mut path_arcs = Vec::with_capacity(self.paths.len());
+        for path in &self.paths {
+            // An empty line or ring is an empty list of arcs
+            if path.points.len() < 2 {
+                path_arcs.push(vec![]);
+                continue;
+            }
+            let points = match path.ring {
+                true => rotate_ring(&path.points, &junctions),
+                false => path.points.clone(),
+            };
+            let mut references = vec![];
+            let mut start = 0;
+            for i in 1..points.len() {
+                if i == points.len() - 1 || junctions.contains(&points[i]) {
+                    let arc = points[start..=i].to_vec();
+                    let reversed: Vec<Key> = arc.iter().rev().copied().collect();
+                    let reference = match (index.get(&arc), index.get(&reversed)) {
+                        (Some(&i), _) => i as i64,
+                        (None, Some(&i)) => !(i as i64),
+                        (None, None) => {
+                            index.insert(arc.clone(), arcs.len());
+                            arcs.push(arc);
+                            arcs.len() as i64 - 1
+                        }
+                    };
+                    references.push(reference);
+                    start = i;
+                }
+            }
+            path_arcs.push(references);
+        }
+        (arcs, path_arcs)
+    }
+
+    fn arc_to_json(&self, arc: &[Key]) -> Value {
+        match self.quantizer {
+            // The first position is absolute, and the rest are differences from the one before
+            Some(_) => {
+                let mut previous = [0, 0];
+                Value::Array(
+                    arc.iter()
+                        .map(|key| {
+                            let delta = [key[0] - previous[0], key[1] - previous[1]];
+                            previous = *key;
+                            json!(delta)
+                        })
+                        .collect(),
+                )
+            }
+            None => Value::Array(arc.iter().map(|key| self.key_to_json(*key)).collect()),
+        }
+    }
+
+    fn shape_to_json(&self, shape: &Shape, path_arcs: &[Vec<i64>]) -> Map<String, Value> {
+        let rings =
+            |paths: &Vec<usize>| json!(paths.iter().map(|p| &path_arcs[*p]).collect::<Vec<_>>());
+        let (kind, key, value) = match shape {
+            Shape::Point(key) => ("Point", "coordinates", self.key_to_json(*key)),
+            Shape::MultiPoint(keys) => (
+                "MultiPoint",
+                "coordinates",
+                Value::Array(keys.iter().map(|k| self.key_to_json(*k)).collect()),
+            ),
+            Shape::LineString(path) => ("LineString", "arcs", json!(path_arcs[*path])),
+            Shape::MultiLineString(paths) => ("MultiLineString", "arcs", rings(paths)),
+            Shape::Polygon(paths) => ("Polygon", "arcs", rings(paths)),
+            Shape::MultiPolygon(polygons) => (
+                "MultiPolygon",
+                "arcs",
+                Value::Array(polygons.iter().map(rings).collect()),
+            ),
+            Shape::GeometryCollection(shapes) => (
+                "GeometryCollection",
+                "geometries",
+                Value::Array(
+                    shapes
+                        .iter()
+                        .map(|s| Value::Object(self.shape_to_json(s, path_arcs)))
+                        .collect(),
+                ),
+            ),
+        };
+        let mut obj = Map::new();
+        obj.insert("type".to_string(), json!(kind));
+        obj.insert(key.to_string(), value);
+        obj
+    }
+}
+
+/// Rotates a closed ring to start at a junction, or at its smallest position if it has none,
+/// so that equal rings starting at different positions give equal arcs.
+fn rotate_ring(points: &[Key], junctions: &HashSet<Key>) -> Vec<Key> {
+    if points.len() < 2 {
+        return points.to_vec();
+    }
+    let open = &points[..points.len() - 1];
+    let start = open
+        .iter()
+        .position(|p| junctions.contains(p))
+        .or_else(|| (0..open.len()).min_by_key(|i| open[*i]))
+        .unwrap_or(0);
+    let mut rotated: Vec<Key> = open[start..]
+        .iter()
+        .chain(&open[..start])
+        .copied()
+        .collect();
+    if let Some(first) = rotated.first().copied() {
+        rotated.push(first);
+    }
+    rotated
+}
+
+fn feature_id_to_json(id: &FeatureId) -> Value {
+    match id {
+        FeatureId::String(s) => json!(s),
+        FeatureId::Number(n) => Value::Number(n.clone()),
+    }
+}
+
+/// The error for a member of a topology that isn't what TopoJSON requires at `path`.
+fn invalid(message: &str, path: &str) -> TracebackError {
+    traceback!(format!("Invalid TopoJSON: {message} at {path}"))
+        .with_extra_data(json!({ "path": path }))
+}
+
+struct Transform {
+    scale: [f64; 2],
+    translate: [f64; 2],
+}
+
+impl Transform {
+    fn apply(&self, x: f64, y: f64) -> Position {
+        vec![
+            x * self.scale[0] + self.translate[0],
+            y * self.scale[1] + self.translate[1],
+        ]
+    }
+}
+
+fn parse_transform(transform: &Value) -> Result<Transform, TracebackError> {
+    let pair = |key: &str| -> Result<[f64; 2], TracebackError> {
+        let path = format!("$.transform.{key}");
+        match transform
+            .get(key)
+            .and_then(Value::as_array)
+            .map(|a| a.as_slice())
+        {
+            Some([x, y]) => match (x.as_f64(), y.as_f64()) {
+                (Some(x), Some(y)) => Ok([x, y]),
+                _ => Err(invalid("Expected two numbers", &path)),
+            },
+            _ => Err(invalid("Expected two numbers", &path)),
+        }
+    };
+    match (pair("scale"), pair("translate")) {
+        (Ok(scale), Ok(translate)) => Ok(Transform { scale, translate }),
+        (Err(e), _) | (_, Err(e)) => Err(traceback!(err e)),
+    }
+}
+
+fn decode_position(
+    value: &Value,
+    transform: &Option<Transform>,
+    path: &str,
+) -> Result<Position, TracebackError> {
+    let numbers: Option<Vec<f64>> = value
+        .as_array()
+        .map(|a| a.iter().map(Value::as_f64).collect())
+        .unwrap_or(None);
+    match (numbers, transform) {
+        (Some(n), Some(t)) if n.len() >= 2 => Ok(t.apply(n[0], n[1])),
+        (Some(n), None) if n.len() >= 2 => Ok(n),
+        _ => Err(invalid("Expected a position of at least 2 numbers", path)),
+    }
+}
+
+fn decode_arcs(
+    arcs: Option<&Value>,
+    transform: &Option<Transform>,
+) -> Result<Vec<Vec<Position>>, TracebackError> {
+    let arcs = match arcs.and_then(Value::as_array) {
+        Some(arcs) => arcs,
+        None => return Err(invalid("Expected an array of arcs", "$.arcs")),
+    };
+    let mut decoded = Vec::with_capacity(arcs.len());
+    for (i, arc) in arcs.iter().enumerate() {
+        let positions = match arc.as_array() {
+            Some(positions) => positions,
+            None => {
+                return Err(invalid(
+                    "Expected an array of positions",
+                    &format!("$.arcs[{i}]"),
+                ))
+            }
+        };
+        let mut line = Vec::with_capacity(positions.len());
+        // Quantized arcs are delta encoded, so positions are decoded as a running sum
+        let mut sum = [0.0, 0.0];
+        for (j, position) in positions.iter().enumerate() {
+            let path = format!("$.arcs[{i}][{j}]");
+            match transform {
+                Some(t) => match decode_position(position, &None, &path) {
+                    Ok(delta) => {
+                        sum = [sum[0] + delta[0], sum[1] + delta[1]];
+                        line.push(t.apply(sum[0], sum[1]));
+                    }
+                    Err(e) => return Err(traceback!(err e)),
+                },
+                None => match decode_position(position, &None, &path) {
+                    Ok(position) => line.push(position),
+                    Err(e) => return Err(traceback!(err e)),
+                },
+            }
+        }
+        decoded.push(line);
+    }
+    Ok(decoded)
+}
+
+struct Decoder<'a> {
+    arcs: &'a [Vec<Position>],
+    transform: &'a Option<Transform>,
+}
+
+impl Decoder<'_> {
+    fn feature(&self, object: &Value, path: &str) -> Result<Feature, TracebackError> {
+        let geometry = match object.get("type") {
+            None | Some(Value::Null) => None,
+            Some(_) => match self.geometry(object, path) {
+                Ok(geometry) => Some(geometry),
+                Err(e) => return Err(traceback!(err e)),
+            },
+        };
+        let id = match object.get("id") {
+            Some(Value::String(s)) => Some(FeatureId::String(s.clone())),
+            Some(Value::Number(n)) => Some(FeatureId::Number(n.clone())),
+            _ => None,
+        };
+        let properties = object.get("properties").and_then(Value::as_object).cloned();
+        Ok(Feature {
+            id,
+            geometry,
+            properties,
+            ..Default::default()
+        })
+    }
+
+    fn geometry(&self, object: &Value, path: &str) -> Result<Geometry, TracebackError> {
+        let arcs_path = format!("{path}.arcs");
+        let coordinates_path = format!("{path}.coordinates");
+        let value = match object.get("type").and_then(Value::as_str) {
+            Some("Point") => {
+                decode_position(&object["coordinates"], self.transform, &coordinates_path)
+                    .map(GeometryValue::Point)
+            }
+            Some("MultiPoint") => self
+                .list(object.get("coordinates"), &coordinates_path, |v, p| {
+                    decode_position(v, self.transform, p)
+                })
+                .map(GeometryValue::MultiPoint),
+            Some("LineString") => self
+                .line(object.get("arcs"), &arcs_path)
+                .map(GeometryValue::LineString),
+            Some("MultiLineString") => self
+                .list(object.get("arcs"), &arcs_path, |v, p| self.line(Some(v), p))
+                .map(GeometryValue::MultiLineString),
+            Some("Polygon") => self
+                .list(object.get("arcs"), &arcs_path, |v, p| self.line(Some(v), p))
+                .map(GeometryValue::Polygon),
+            Some("MultiPolygon") => self
+                .list(object.get("arcs"), &arcs_path, |v, p| {
+                    self.list(Some(v), p, |v, p| self.line(Some(v), p))
+                })
+                .map(GeometryValue::MultiPolygon),
+            Some("GeometryCollection") => self
+                .list(
+                    object.get("geometries"),
+                    &format!("{path}.geometries"),
+                    |v, p| self.geometry(v, p),
+                )
+                .map(GeometryValue::GeometryCollection),
+            _ => return Err(invalid("Unknown geometry type", &format!("{path}.type"))),
+        };
+        match value {
+            Ok(value) => Ok(Geometry::new(value)),
+            Err(e) => Err(traceback!(err e)),
+        }
+    }
+
+    fn list<T>(
+        &self,
+        value: Option<&Value>,
+        path: &str,
+        parse: impl Fn(&Value, &str) -> Result<T, TracebackError>,
+    ) -> Result<Vec<T>, TracebackError> {
+        let items = match value.and_then(Value::as_array) {
+            Some(items) => items,
+            None => return Err(invalid("Expected an array", path)),
+        };
+        let mut parsed = Vec::with_capacity(items.len());
+        for (i, item) in items.iter().enumerate() {
+            match parse(item, &format!("{path}[{i}]")) {
+                Ok(item) => parsed.push(item),
+                Err(e) => return Err(traceback!(err e)),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Stitches arcs into a line, dropping the position each arc shares with the one before.
+    fn line(
+        &self,
+        references: Option<&Value>,
+        path: &str,
+    ) -> Result<Vec<Position>, TracebackError> {
+        let references = match self.list(references, path, |v, p| {
+            v.as_i64()
+                .ok_or_else(|| invalid("Expected an arc index", p))
+        }) {
+            Ok(references) => references,
+            Err(e) => return Err(traceback!(err e)),
+        };
+        let mut line: Vec<Position> = vec![];
+        for (i, reference) in references.into_iter().enumerate() {
+            let (index, reversed) = match reference < 0 {
+                true => (!reference, true),
+                false => (reference, false),
+            };
+            let arc = match self.arcs.get(index as usize) {
+                Some(arc) => arc,
+                None => {
+                    return Err(invalid(
+                        &format!("Arc {reference} doesn't exist"),
+                        &format!("{path}[{i}]"),
+                    ))
+                }
+            };
+            let positions: Box<dyn Iterator<Item = &Position>> = match reversed {
+                true => Box::new(arc.iter().rev()),
+                false => Box::new(arc.iter()),
+            };
+            let skip = match line.is_empty() {
+                true => 0,
+                false => 1,
+            };
+            line.extend(positions.skip(skip).cloned());
+        }
+        Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geojson::{measure::area, wkt::parse_wkt};
+
+    fn regions() -> FeatureCollection {
+        FeatureCollection::from_json_value(&json!({
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "id": "west",
+                    "geometry": {"type": "Polygon", "coordinates": [
+                        [[0, 0], [1, 0], [1, 1], [0, 1], [0, 0]],
+                        [[0.25, 0.25], [0.25, 0.75], [0.75, 0.75], [0.75, 0.25], [0.25, 0.25]]
+                    ]},
+                    "properties": {"name": "West"}
+                },
+                {
+                    "type": "Feature",
+                    "id": 2,
+                    "geometry": {"type": "Polygon", "coordinates": [[[1, 0], [2, 0], [2, 1], [1, 1], [1, 0]]]},
+                    "properties": {"name": "East"}
+                },
+                {
+                    "type": "Feature",
+                    "geometry": {"type": "Polygon", "coordinates": [
+                        [[0.75, 0.25], [0.75, 0.75], [0.25, 0.75], [0.25, 0.25], [0.75, 0.25]]
+                    ]},
+                    "properties": {"name": "Enclave"}
+                },
+                {"type": "Feature", "geometry": {"type": "Point", "coordinates": [2, 1]}, "properties": null},
+                {"type": "Feature", "geometry": null, "properties": null}
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_shared_arcs() {
+        let collection = regions();
+        let options = TopoJsonOptions {
+            object_name: "regions".to_string(),
+            quantization: None,
+        };
+        let topology = feature_collection_to_topojson(&collection, &options).unwrap();
+        // The shared border, the rest of each square, and the hole shared with the enclave
+        assert_eq!(topology["arcs"].as_array().unwrap().len(), 4);
+        let geometries = &topology["objects"]["regions"]["geometries"];
+        assert_eq!(geometries[0]["arcs"][1], json!([2]));
+        assert_eq!(geometries[2]["arcs"][0], json!([!2]));
+        assert_eq!(geometries[4], json!({"type": null}));
+
+        let decoded = topojson_to_feature_collection(&topology, "regions").unwrap();
+        assert_eq!(decoded.features.len(), 5);
+        for (feature, original) in decoded.features.iter().zip(&collection.features) {
+            assert_eq!(feature.id, original.id);
+            assert_eq!(feature.properties, original.properties);
+            if let Some(geometry) = &original.geometry {
+                assert_eq!(area(feature.geometry.as_ref().unwrap()), area(geometry));
+            }
+        }
+        assert_eq!(
+            decoded.features[3].geometry,
+            collection.features[3].geometry
+        );
+    }
+
+    #[test]
+    fn test_quantized() {
+        let collection = regions();
+        let topology =
+            feature_collection_to_topojson(&collection, &TopoJsonOptions::default()).unwrap();
+        assert_eq!(topology["transform"]["translate"], json!([0.0, 0.0]));
+        // The first position of an arc is absolute and the rest are deltas
+        let arc = topology["arcs"][0].as_array().unwrap();
+        assert_eq!(arc[..2], [json!([5000, 0]), json!([0, 9999])]);
+        assert_eq!(
+            topology["objects"]["collection"]["geometries"][3]["coordinates"],
+            json!([9999, 9999])
+        );
+
+        let decoded = topojson_to_feature_collection(&topology, "collection").unwrap();
+        let GeometryValue::Polygon(rings) = &decoded.features[1].geometry.as_ref().unwrap().value
+        else {
+            panic!("expected a polygon");
+        };
+        let close =
+            |a: &Position, b: [f64; 2]| (a[0] - b[0]).abs() < 1e-3 && (a[1] - b[1]).abs() < 1e-3;
+        assert!(close(&rings[0][0], [1.0, 0.0]) && close(&rings[0][2], [2.0, 1.0]));
+
+        let mut broken = topology.clone();
+        broken["objects"]["collection"]["geometries"][1]["arcs"][0][0] = json!(99);
+        let mut err = topojson_to_feature_collection(&broken, "collection").unwrap_err();
+        err.is_handled = true;
+        assert!(
+            err.message
+                .ends_with("$.objects.collection.geometries[1].arcs[0][0]"),
+            "{}",
+            err.message
+        );
+        let mut err = topojson_to_feature_collection(&topology, "missing").unwrap_err();
+        err.is_handled = true;
+    }
+
+    #[test]
+    fn test_empty_lines_and_rings() {
+        let collection = FeatureCollection::from_json_value(&json!({
+            "type": "FeatureCollection",
+            "features": [
+                {"type": "Feature", "geometry": {"type": "LineString", "coordinates": []}, "properties": null},
+                {"type": "Feature", "geometry": {"type": "Polygon", "coordinates": [[]]}, "properties": null},
+                {"type": "Feature", "geometry": {"type": "MultiLineString", "coordinates": [[[0, 0], [1, 1]], []]}, "properties": null}
+            ]
+        }))
+        .unwrap();
+        for quantization in [None, Some(10_000)] {
+            let options = TopoJsonOptions {
+                quantization,
+                ..Default::default()
+            };
+            let topology = feature_collection_to_topojson(&collection, &options).unwrap();
+            let geometries = &topology["objects"]["collection"]["geometries"];
+            assert_eq!(geometries[0]["arcs"], json!([]));
+            assert_eq!(geometries[1]["arcs"], json!([[]]));
+            assert_eq!(geometries[2]["arcs"], json!([[0], []]));
+            let decoded = topojson_to_feature_collection(&topology, "collection").unwrap();
+            assert_eq!(
+                decoded.features[0].geometry.as_ref().unwrap().value,
+                GeometryValue::LineString(vec![])
+            );
+            assert_eq!(
+                decoded.features[1].geometry.as_ref().unwrap().value,
+                GeometryValue::Polygon(vec![vec![]])
+            );
+        }
+        assert_eq!(
+            parse_wkt("LINESTRING EMPTY").unwrap().value,
+            GeometryValue::LineString(vec![])
+        );
+    }
+}
@@ -0,0 +1,376 @@
+use serde::{Deserialize, Serialize};
+use traceback_error::serde_json::Value;
+
+use super::{FeatureCollection, GeoJson, Geometry, GeometryValue, Position};
+
+/// How serious a problem `validate_geojson` found is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// The data breaks a MUST of RFC 7946.
+    Error,
+    /// The data breaks a SHOULD of RFC 7946, and some consumers will misread it.
+    Warning,
+}
+
+/// The kind of problem `validate_geojson` found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// A position with fewer than 2 coordinates.
+    PositionDimension,
+    /// A position with more than 3 coordinates, which consumers may not interpret the same way.
+    ExtraCoordinates,
+    LongitudeOutOfRange,
+    LatitudeOutOfRange,
+    /// A linear ring with fewer than 4 positions.
+    TooFewRingPositions,
+    /// A linear ring whose last position isn't its first.
+    UnclosedRing,
+    /// An exterior ring that is clockwise, or a hole that is counterclockwise.
+    WindingOrder,
+    /// Two consecutive positions more than 180° of longitude apart,
+    /// meaning the geometry crosses the antimeridian instead of being cut at it.
+    CrossesAntimeridian,
+}
+
+/// A problem found by `validate_geojson`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GeoJsonIssue {
+    pub kind: IssueKind,
+    pub severity: Severity,
+    /// The index of the feature in its collection, if the problem is in a feature collection.
+    pub feature: Option<usize>,
+    /// The JSON path of the offending coordinates, like `$.features[3].geometry.coordinates[0][2]`.
+    pub path: String,
+    pub message: String,
+}
+
+/// Checks a GeoJSON document against the geometry rules of RFC 7946.
+///
+/// Every problem is reported, not only the first, each with the index of its feature and its coordinate path.
+/// Positions must have at least 2 coordinates within longitude and latitude ranges,
+/// linear rings must be closed and have at least 4 positions,
+/// and, as warnings, positions shouldn't have more than 3 coordinates,
+/// rings should follow the right-hand rule and lines shouldn't jump across the antimeridian.
+///
+/// ## Example
+///
+/// ```rust
+/// use utils::geojson::{
+///     validate::{validate_geojson, IssueKind},
+///     GeoJson,
+/// };
+///
+/// let geojson: GeoJson = r#"{"type": "Polygon", "coordinates": [[[0, 0], [1, 0], [1, 1]]]}"#
+///     .parse()
+///     .unwrap();
+/// let issues = validate_geojson(&geojson);
+/// assert_eq!(issues[0].kind, IssueKind::TooFewRingPositions);
+/// assert_eq!(issues[0].path, "$.coordinates[0]");
+/// ```
+pub fn validate_geojson(geojson: &GeoJson) -> Vec<GeoJsonIssue> {
+    match geojson {
+        GeoJson::Geometry(geometry) => validate_geometry(geometry),
+        GeoJson::Feature(feature) => {
+            let mut checker = Checker::new(None);
+            if let Some(geometry) = &feature.geometry {
+                checker.geometry(geometry, "$.geometry");
+            }
+            checker.issues
+        }
+        GeoJson::FeatureCollection(collection) => validate_feature_collection(collection),
+    }
+}
+
+/// Validates every feature of a collection, see `validate_geojson`.
+pub fn validate_feature_collection(collection: &FeatureCollection) -> Vec<GeoJsonIssue> {
+    let mut issues = vec![];
+    for (i, feature) in collection.features.iter().enumerate() {
+        if let Some(geometry) = &feature.geometry {
+            let mut checker = Checker::new(Some(i));
+            checker.geometry(geometry, &format!("$.features[{i}].geometry"));
+            issues.append(&mut checker.issues);
+        }
+    }
+    issues
+}
+
+/// Validates a single geometry, see `validate_geojson`.
+pub fn validate_geometry(geometry: &Geometry) -> Vec<GeoJsonIssue> {
+    let mut checker = Checker::new(None);
+    checker.geometry(geometry, "$");
+    checker.issues
+}
+
+/// Converts issues to a JSON array, for reports.
+pub fn issues_to_json(issues: &[GeoJsonIssue]) -> Value {
+    traceback_error::serde_json::to_value(issues).unwrap_or(Value::Null)
+}
+
+/// Twice the signed area of a ring in the plane of its coordinates, positive when counterclockwise.
+pub(crate) fn ring_signed_area(ring: &[Position]) -> f64 {
+    let coordinate = |p: &Position, i: usize| p.get(i).copied().unwrap_or(0.0);
+    ring.windows(2)
+        .map(|pair| {
+            coordinate(&pair[0], 0) * coordinate(&pair[1], 1)
+                - coordinate(&pair[1], 0) * coordinate(&pair[0], 1)
+        })
+        .sum()
+}
+
+struct Checker {
+    feature: Option<usize>,
+    issues: Vec<GeoJsonIssue>,
+}
+
+impl Checker {
+    fn new(feature: Option<usize>) -> Self {
+        Self {
+            feature,
+            issues: vec![],
+        }
+    }
+
+    fn report(&mut self, kind: IssueKind, path: String, message: String) {
+        let severity = match kind {
+            IssueKind::ExtraCoordinates
+            | IssueKind::WindingOrder
+            | IssueKind::CrossesAntimeridian => Severity::Warning,
+            _ => Severity::Error,
+        };
+        self.issues.push(GeoJsonIssue {
+            kind,
+            severity,
+            feature: self.feature,
+            path,
+            message,
+        });
+    }
+
+    fn geometry(&mut self, geometry: &Geometry, path: &str) {
+        let coordinates = format!("{path}.coordinates");
+        match &geometry.value {
+            GeometryValue::Point(position) => self.position(position, &coordinates),
+            GeometryValue::MultiPoint(positions) => self.positions(positions, &coordinates),
+            GeometryValue::LineString(line) => self.line(line, &coordinates),
+            GeometryValue::MultiLineString(lines) => {
+                for (i, line) in lines.iter().enumerate() {
+                    self.line(line, &format!("{coordinates}[{i}]"));
+                }
+            }
+            GeometryValue::Polygon(rings) => self.polygon(rings, &coordinates),
+            GeometryValue::MultiPolygon(polygons) => {
+                for (i, rings) in polygons.iter().enumerate() {
+                    self.polygon(rings, &format!("{coordinates}[{i}]"));
+                }
+            }
+            GeometryValue::GeometryCollection(geometries) => {
+                for (i, geometry) in geometries.iter().enumerate() {
+                    self.geometry(geometry, &format!("{path}.geometries[{i}]"));
+                }
+            }
+        }
+    }
+
+    fn position(&mut self, position: &Position, path: &str) {
+        if position.len() < 2 {
+            self.report(
+                IssueKind::PositionDimension,
+                path.to_string(),
+                format!("Position has {} coordinates, not 2 or 3", position.len()),
+            );
+        } else if position.len() > 3 {
+            self.report(
+                IssueKind::ExtraCoordinates,
+                path.to_string(),
+                format!("Position has {} coordinates, not 2 or 3", position.len()),
+            );
+        }
+        if let Some(&lon) = position.first() {
+            if !(-180.0..=180.0).contains(&lon) {
+                self.report(
+                    IssueKind::LongitudeOutOfRange,
+                    path.to_string(),
+                    format!("Longitude {lon} is outside -180 to 180"),
+                );
+            }
+        }
+        if let Some(&lat) = position.get(1) {
+            if !(-90.0..=90.0).contains(&lat) {
+                self.report(
+                    IssueKind::LatitudeOutOfRange,
+                    path.to_string(),
+                    format!("Latitude {lat} is outside -90 to 90"),
+                );
+            }
+        }
+    }
+
+    fn positions(&mut self, positions: &[Position], path: &str) {
+        for (i, position) in positions.iter().enumerate() {
+            self.position(position, &format!("{path}[{i}]"));
+        }
+    }
+
+    /// Checks the positions of a line, and that it doesn't jump across the antimeridian.
+    fn line(&mut self, line: &[Position], path: &str) {
+        self.positions(line, path);
+        for (i, pair) in line.windows(2).enumerate() {
+            let (Some(a), Some(b)) = (pair[0].first(), pair[1].first()) else {
+                continue;
+            };
+            if (b - a).abs() > 180.0 {
+                self.report(
+                    IssueKind::CrossesAntimeridian,
+                    format!("{path}[{}]", i + 1),
+                    format!(
+                        "Segment from longitude {a} to {b} crosses the antimeridian, it should be cut in two"
+                    ),
+                );
+            }
+        }
+    }
+
+    fn polygon(&mut self, rings: &[Vec<Position>], path: &str) {
+        for (i, ring) in rings.iter().enumerate() {
+            let ring_path = format!("{path}[{i}]");
+            self.line(ring, &ring_path);
+            if ring.len() < 4 {
+                self.report(
+                    IssueKind::TooFewRingPositions,
+                    ring_path,
+                    format!("Linear ring has {} positions, not at least 4", ring.len()),
+                );
+                continue;
+            }
+            if ring.first() != ring.last() {
+                self.report(
+                    IssueKind::UnclosedRing,
+                    ring_path,
+                    "Linear ring doesn't end at the position it starts at".to_string(),
+                );
+                continue;
+            }
+            let area = ring_signed_area(ring);
+            let exterior = i == 0;
+            if (exterior && area < 0.0) || (!exterior && area > 0.0) {
+                let message = match exterior {
+                    true => "Exterior ring is clockwise, it should be counterclockwise",
+                    false => "Hole is counterclockwise, it should be clockwise",
+                };
+                self.report(IssueKind::WindingOrder, ring_path, message.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use traceback_error::serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_valid_collection() {
+        let collection = FeatureCollection::from_json_value(&json!({
+            "type": "FeatureCollection",
+            "features": [{
+                "type": "Feature",
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [
+                        [[0, 0], [10, 0], [10, 10], [0, 10], [0, 0]],
+                        [[2, 2], [2, 8], [8, 8], [8, 2], [2, 2]]
+                    ]
+                },
+                "properties": null
+            }]
+        }))
+        .unwrap();
+        assert_eq!(validate_feature_collection(&collection), vec![]);
+    }
+
+    #[test]
+    fn test_reports_every_issue() {
+        let collection = FeatureCollection::from_json_value(&json!({
+            "type": "FeatureCollection",
+            "features": [
+                {"type": "Feature", "geometry": {"type": "Point", "coordinates": [0, 0]}, "properties": null},
+                {
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "GeometryCollection",
+                        "geometries": [
+                            {"type": "Point", "coordinates": [190, 95, 1, 2]},
+                            {"type": "LineString", "coordinates": [[179, 0], [-179, 1]]},
+                            {"type": "Polygon", "coordinates": [
+                                [[0, 0], [0, 10], [10, 10], [10, 0], [0, 0]],
+                                [[2, 2], [8, 2], [8, 8], [2, 2.5]]
+                            ]}
+                        ]
+                    },
+                    "properties": null
+                }
+            ]
+        }))
+        .unwrap();
+        let issues = validate_feature_collection(&collection);
+        let found: Vec<(IssueKind, &str)> = issues
+            .iter()
+            .map(|issue| (issue.kind, issue.path.as_str()))
+            .collect();
+        let base = "$.features[1].geometry.geometries";
+        assert_eq!(
+            found,
+            vec![
+                (
+                    IssueKind::ExtraCoordinates,
+                    &*format!("{base}[0].coordinates")
+                ),
+                (
+                    IssueKind::LongitudeOutOfRange,
+                    &*format!("{base}[0].coordinates")
+                ),
+                (
+                    IssueKind::LatitudeOutOfRange,
+                    &*format!("{base}[0].coordinates")
+                ),
+                (
+                    IssueKind::CrossesAntimeridian,
+                    &*format!("{base}[1].coordinates[1]")
+                ),
+                (
+                    IssueKind::WindingOrder,
+                    &*format!("{base}[2].coordinates[0]")
+                ),
+                (
+                    IssueKind::UnclosedRing,
+                    &*format!("{base}[2].coordinates[1]")
+                ),
+            ]
+        );
+        assert!(issues.iter().all(|issue| issue.feature == Some(1)));
+        let severities: Vec<Severity> = issues.iter().map(|issue| issue.severity).collect();
+        assert_eq!(
+            severities,
+            vec![
+                Severity::Warning,
+                Severity::Error,
+                Severity::Error,
+                Severity::Warning,
+                Severity::Warning,
+                Severity::Error,
+            ]
+        );
+        // The parser rejects short positions, so only a geometry built in code can have one
+        let short = validate_geometry(&Geometry {
+            value: GeometryValue::Point(vec![1.0]),
+            bbox: None,
+            foreign_members: None,
+        });
+        assert_eq!(short.len(), 1);
+        assert_eq!(short[0].kind, IssueKind::PositionDimension);
+        assert_eq!(short[0].severity, Severity::Error);
+    }
+}
@@ -0,0 +1,535 @@
+use traceback_error::{serde_json::json, traceback, TracebackError};
+
+use super::{
+    wkt::{has_z, SridGeometry},
+    Geometry, GeometryValue, Position,
+};
+
+/// The EWKB flag for a Z coordinate in the geometry type.
+const EWKB_Z: u32 = 0x8000_0000;
+/// The EWKB flag for an M value in the geometry type.
+const EWKB_M: u32 = 0x4000_0000;
+/// The EWKB flag for an SRID following the geometry type.
+const EWKB_SRID: u32 = 0x2000_0000;
+
+/// Parses Well-Known Binary, in either byte order, into a geometry.
+///
+/// PostGIS Extended WKB is accepted too, as are the ISO type codes for Z and M geometries (1001, 2001, 3001...),
+/// see `parse_ewkb` to also get the SRID. M values are dropped.
+pub fn parse_wkb(wkb: &[u8]) -> Result<Geometry, TracebackError> {
+    match parse_ewkb(wkb) {
+        Ok(parsed) => Ok(parsed.geometry),
+        Err(e) => Err(traceback!(err e)),
+    }
+}
+
+/// Parses PostGIS Extended WKB, or plain WKB, keeping the SRID if there is one.
+pub fn parse_ewkb(wkb: &[u8]) -> Result<SridGeometry, TracebackError> {
+    let mut reader = WkbReader { wkb, offset: 0 };
+    let parsed = match reader.geometry() {
+        Ok(parsed) => parsed,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    match reader.offset == wkb.len() {
+        true => Ok(parsed),
+        false => Err(reader.error(&format!(
+            "{} bytes left after the geometry",
+            wkb.len() - reader.offset
+        ))),
+    }
+}
+
+/// Parses hex-encoded WKB or EWKB, as PostGIS prints geometry columns, keeping the SRID if there is one.
+///
+/// ## Example
+///
+/// ```rust
+/// use utils::geojson::{wkb::parse_ewkb_hex, GeometryValue};
+///
+/// let parsed = parse_ewkb_hex("0101000020E6100000000000000000F03F0000000000000040").unwrap();
+/// assert_eq!(parsed.srid, Some(4326));
+/// assert_eq!(parsed.geometry.value, GeometryValue::Point(vec![1.0, 2.0]));
+/// ```
+pub fn parse_ewkb_hex(hex: &str) -> Result<SridGeometry, TracebackError> {
+    let bytes = match decode_hex(hex.trim()) {
+        Some(bytes) => bytes,
+        None => {
+            return Err(
+                traceback!("Invalid WKB: not a hex string").with_extra_data(json!({ "hex": hex }))
+            )
+        }
+    };
+    match parse_ewkb(&bytes) {
+        Ok(parsed) => Ok(parsed),
+        Err(e) => Err(traceback!(err e).with_extra_data(json!({ "hex": hex }))),
+    }
+}
+
+/// Parses hex-encoded WKB or EWKB, dropping the SRID, see `parse_ewkb_hex`.
+pub fn parse_wkb_hex(hex: &str) -> Result<Geometry, TracebackError> {
+    match parse_ewkb_hex(hex) {
+        Ok(parsed) => Ok(parsed.geometry),
+        Err(e) => Err(traceback!(err e)),
+    }
+}
+
+/// Writes a geometry as little-endian ISO WKB.
+///
+/// Positions with a third coordinate are written as Z geometries, with the ISO type codes (1001, 1002...).
+pub fn to_wkb(geometry: &Geometry) -> Vec<u8> {
+    let mut wkb = vec![];
+    write_geometry(&mut wkb, geometry, Flavor::Iso, None);
+    wkb
+}
+
+/// Writes a geometry as little-endian PostGIS Extended WKB, with the SRID if there is one.
+pub fn to_ewkb(geometry: &Geometry, srid: Option<u32>) -> Vec<u8> {
+    let mut wkb = vec![];
+    write_geometry(&mut wkb, geometry, Flavor::Extended, srid);
+    wkb
+}
+
+/// Writes a geometry as uppercase hex-encoded ISO WKB, see `to_wkb`.
+pub fn to_wkb_hex(geometry: &Geometry) -> String {
+    encode_hex(&to_wkb(geometry))
+}
+
+/// Writes a geometry as uppercase hex-encoded EWKB, the way PostGIS prints geometry columns, see `to_ewkb`.
+pub fn to_ewkb_hex(geometry: &Geometry, srid: Option<u32>) -> String {
+    encode_hex(&to_ewkb(geometry, srid))
+}
+
+struct WkbReader<'a> {
+    wkb: &'a [u8],
+    offset: usize,
+}
+
+impl WkbReader<'_> {
+    fn error(&self, message: &str) -> TracebackError {
+        traceback!(format!("Invalid WKB: {message}")).with_extra_data(json!({
+            "offset": self.offset,
+            "length": self.wkb.len(),
+        }))
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], TracebackError> {
+        match self.wkb.get(self.offset..self.offset + N) {
+            Some(bytes) => {
+                self.offset += N;
+                Ok(bytes.try_into().expect("slice has length N"))
+            }
+            None => Err(self.error("Unexpected end of data")),
+        }
+    }
+
+    fn u32(&mut self, little_endian: bool) -> Result<u32, TracebackError> {
+        match self.bytes::<4>() {
+            Ok(bytes) if little_endian => Ok(u32::from_le_bytes(bytes)),
+            Ok(bytes) => Ok(u32::from_be_bytes(bytes)),
+            Err(e) => Err(traceback!(err e)),
+        }
+    }
+
+    fn f64(&mut self, little_endian: bool) -> Result<f64, TracebackError> {
+        match self.bytes::<8>() {
+            Ok(bytes) if little_endian => Ok(f64::from_le_bytes(bytes)),
+            Ok(bytes) => Ok(f64::from_be_bytes(bytes)),
+            Err(e) => Err(traceback!(err e)),
+        }
+    }
+
+    /// Reads a count, checking it against the bytes left so corrupt data can't make us allocate wildly.
+    fn count(
+        &mut self,
+        little_endian: bool,
+        min_item_size: usize,
+    ) -> Result<usize, TracebackError> {
+        let count = match self.u32(little_endian) {
+            Ok(count) => count as usize,
+            Err(e) => return Err(traceback!(err e)),
+        };
+        match count.saturating_mul(min_item_size) <= self.wkb.len() - self.offset {
+            true => Ok(count),
+            false => Err(self.error(&format!("Count {count} is larger than the data left"))),
+        }
+    }
+
+    fn geometry(&mut self) -> Result<SridGeometry, TracebackError> {
+        let little_endian = match self.bytes::<1>() {
+            Ok([0]) => false,
+            Ok([1]) => true,
+            Ok([order]) => return Err(self.error(&format!("Unknown byte order {order}"))),
+            Err(e) => return Err(traceback!(err e)),
+        };
+        let type_code = match self.u32(little_endian) {
+            Ok(type_code) => type_code,
+            Err(e) => return Err(traceback!(err e)),
+        };
+        let srid = match type_code & EWKB_SRID != 0 {
+            true => match self.u32(little_endian) {
+                Ok(srid) => Some(srid),
+                Err(e) => return Err(traceback!(err e)),
+            },
+            false => None,
+        };
+        let iso_code = type_code & 0x0FFF_FFFF;
+        let (z, m) = match iso_code / 1000 {
+            0 => (type_code & EWKB_Z != 0, type_code & EWKB_M != 0),
+            1 => (true, false),
+            2 => (false, true),
+            3 => (true, true),
+            _ => return Err(self.error(&format!("Unknown geometry type {type_code}"))),
+        };
+        let dimensions = Dimensions { z, m };
+        let value = match iso_code % 1000 {
+            1 => match self.position(little_endian, dimensions) {
+                Ok(position) if position.iter().all(|c| c.is_nan()) => {
+                    return Err(self.error("POINT EMPTY can't be represented in GeoJSON"))
+                }
+                Ok(position) => GeometryValue::Point(position),
+                Err(e) => return Err(traceback!(err e)),
+            },
+            2 => match self.positions(little_endian, dimensions) {
+                Ok(line) => GeometryValue::LineString(line),
+                Err(e) => return Err(traceback!(err e)),
+            },
+            3 => match self.rings(little_endian, dimensions) {
+                Ok(rings) => GeometryValue::Polygon(rings),
+                Err(e) => return Err(traceback!(err e)),
+            },
+            4 => match self.members(little_endian, "MultiPoint", |member| match member {
+                GeometryValue::Point(coordinates) => Some(coordinates),
+                _ => None,
+            }) {
+                Ok(points) => GeometryValue::MultiPoint(points),
+                Err(e) => return Err(traceback!(err e)),
+            },
+            5 => match self.members(little_endian, "MultiLineString", |member| match member {
+                GeometryValue::LineString(coordinates) => Some(coordinates),
+                _ => None,
+            }) {
+                Ok(lines) => GeometryValue::MultiLineString(lines),
+                Err(e) => return Err(traceback!(err e)),
+            },
+            6 => match self.members(little_endian, "MultiPolygon", |member| match member {
+                GeometryValue::Polygon(coordinates) => Some(coordinates),
+                _ => None,
+            }) {
+                Ok(polygons) => GeometryValue::MultiPolygon(polygons),
+                Err(e) => return Err(traceback!(err e)),
+            },
+            7 => {
+                let count = match self.count(little_endian, 5) {
+                    Ok(count) => count,
+                    Err(e) => return Err(traceback!(err e)),
+                };
+                let mut geometries = Vec::with_capacity(count);
+                for _ in 0..count {
+                    match self.geometry() {
+                        Ok(member) => geometries.push(member.geometry),
+                        Err(e) => return Err(traceback!(err e)),
+                    }
+                }
+                GeometryValue::GeometryCollection(geometries)
+            }
+            _ => return Err(self.error(&format!("Unknown geometry type {type_code}"))),
+        };
+        Ok(SridGeometry {
+            srid,
+            geometry: Geometry::new(value),
+        })
+    }
+
+    /// Reads the members of a multi-geometry, unwrapping each to its coordinates.
+    fn members<T>(
+        &mut self,
+        little_endian: bool,
+        kind: &str,
+        unwrap: impl Fn(GeometryValue) -> Option<T>,
+    ) -> Result<Vec<T>, TracebackError> {
+        let count = match self.count(little_endian, 5) {
+            Ok(count) => count,
+            Err(e) => return Err(traceback!(err e)),
+        };
+        let mut members = Vec::with_capacity(count);
+        for _ in 0..count {
+            let member = match self.geometry() {
+                Ok(member) => member.geometry.value,
+                Err(e) => return Err(traceback!(err e)),
+            };
+            let found = member.type_name();
+            match unwrap(member) {
+                Some(coordinates) => members.push(coordinates),
+                None => return Err(self.error(&format!("{kind} contains a {found}"))),
+            }
+        }
+        Ok(members)
+    }
+
+    fn position(
+        &mut self,
+        little_endian: bool,
+        dimensions: Dimensions,
+    ) -> Result<Position, TracebackError> {
+        let count = 2 + dimensions.z as usize + dimensions.m as usize;
+        let mut position = Vec::with_capacity(count);
+        for _ in 0..count {
+            match self.f64(little_endian) {
+                Ok(coordinate) => position.push(coordinate),
+                Err(e) => return Err(traceback!(err e)),
+            }
+        }
+        // Drop the M value, which is last
+        if dimensions.m {
+            position.pop();
+        }
+        Ok(position)
+    }
+
+    fn positions(
+        &mut self,
+        little_endian: bool,
+        dimensions: Dimensions,
+    ) -> Result<Vec<Position>, TracebackError> {
+        let count = match self.count(little_endian, 16) {
+            Ok(count) => count,
+            Err(e) => return Err(traceback!(err e)),
+        };
+        let mut positions = Vec::with_capacity(count);
+        for _ in 0..count {
+            match self.position(little_endian, dimensions) {
+                Ok(position) => positions.push(position),
+                Err(e) => return Err(traceback!(err e)),
+            }
+        }
+        Ok(positions)
+    }
+
+    fn rings(
+        &mut self,
+        little_endian: bool,
+        dimensions: Dimensions,
+    ) -> Result<Vec<Vec<Position>>, TracebackError> {
+        let count = match self.count(little_endian, 4) {
+            Ok(count) => count,
+            Err(e) => return Err(traceback!(err e)),
+        };
+        let mut rings = Vec::with_capacity(count);
+        for _ in 0..count {
+            match self.positions(little_endian, dimensions) {
+                Ok(ring) => rings.push(ring),
+                Err(e) => return Err(traceback!(err e)),
+            }
+        }
+        Ok(rings)
+    }
+}
+
+/// Whether WKB coordinates carry Z and M values.
+#[derive(Debug, Clone, Copy)]
+struct Dimensions {
+    z: bool,
+    m: bool,
+}
+
+/// Which extension of WKB to write Z coordinates and SRIDs with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flavor {
+    /// ISO type codes, like 1001 for a Z point, and no SRID.
+    Iso,
+    /// PostGIS type flags, like `0x80000001` for a Z point, with an SRID on the outermost geometry.
+    Extended,
+}
+
+fn write_geometry(wkb: &mut Vec<u8>, geometry: &Geometry, flavor: Flavor, srid: Option<u32>) {
+    let has_z = has_z(geometry);
+    let code: u32 = match &geometry.value {
+        GeometryValue::Point(_) => 1,
+        GeometryValue::LineString(_) => 2,
+        GeometryValue::Polygon(_) => 3,
+        GeometryValue::MultiPoint(_) => 4,
+        GeometryValue::MultiLineString(_) => 5,
+        GeometryValue::MultiPolygon(_) => 6,
+        GeometryValue::GeometryCollection(_) => 7,
+    };
+    let type_code = match (flavor, has_z) {
+        (Flavor::Iso, true) => code + 1000,
+        (Flavor::Extended, true) => code | EWKB_Z,
+        (_, false) => code,
+    };
+    // Little endian
+    wkb.push(1);
+    match srid {
+        Some(srid) if flavor == Flavor::Extended => {
+            wkb.extend_from_slice(&(type_code | EWKB_SRID).to_le_bytes());
+            wkb.extend_from_slice(&srid.to_le_bytes());
+        }
+        _ => wkb.extend_from_slice(&type_code.to_le_bytes()),
+    }
+    // Members of multi-geometries are whole geometries, written without the SRID
+    let write_member = |wkb: &mut Vec<u8>, value: GeometryValue| {
+        write_geometry(wkb, &Geometry::new(value), flavor, None)
+    };
+    match &geometry.value {
+        GeometryValue::Point(p) => write_position(wkb, p, has_z),
+        GeometryValue::LineString(line) => write_positions(wkb, line, has_z),
+        GeometryValue::Polygon(rings) => write_rings(wkb, rings, has_z),
+        GeometryValue::MultiPoint(points) => {
+            write_count(wkb, points.len());
+            for p in points {
+                write_member(wkb, GeometryValue::Point(with_z(p, has_z)));
+            }
+        }
+        GeometryValue::MultiLineString(lines) => {
+            write_count(wkb, lines.len());
+            for line in lines {
+                let line = line.iter().map(|p| with_z(p, has_z)).collect();
+                write_member(wkb, GeometryValue::LineString(line));
+            }
+        }
+        GeometryValue::MultiPolygon(polygons) => {
+            write_count(wkb, polygons.len());
+            for rings in polygons {
+                let rings = rings
+                    .iter()
+                    .map(|ring| ring.iter().map(|p| with_z(p, has_z)).collect())
+                    .collect();
+                write_member(wkb, GeometryValue::Polygon(rings));
+            }
+        }
+        GeometryValue::GeometryCollection(geometries) => {
+            write_count(wkb, geometries.len());
+            for geometry in geometries {
+                write_geometry(wkb, geometry, flavor, None);
+            }
+        }
+    }
+}
+
+/// Pads a position to 3 coordinates when its geometry has Z, so multi-geometry members agree on it.
+fn with_z(position: &Position, has_z: bool) -> Position {
+    let mut position = position.clone();
+    if has_z && position.len() < 3 {
+        position.resize(3, 0.0);
+    }
+    position
+}
+
+fn write_count(wkb: &mut Vec<u8>, count: usize) {
+    wkb.extend_from_slice(&(count as u32).to_le_bytes());
+}
+
+fn write_position(wkb: &mut Vec<u8>, position: &Position, has_z: bool) {
+    let dimensions = 2 + has_z as usize;
+    for i in 0..dimensions {
+        let coordinate = position.get(i).copied().unwrap_or(0.0);
+        wkb.extend_from_slice(&coordinate.to_le_bytes());
+    }
+}
+
+fn write_positions(wkb: &mut Vec<u8>, positions: &[Position], has_z: bool) {
+    write_count(wkb, positions.len());
+    for p in positions {
+        write_position(wkb, p, has_z);
+    }
+}
+
+fn write_rings(wkb: &mut Vec<u8>, rings: &[Vec<Position>], has_z: bool) {
+    write_count(wkb, rings.len());
+    for ring in rings {
+        write_positions(wkb, ring, has_z);
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        hex.push(DIGITS[(byte >> 4) as usize] as char);
+        hex.push(DIGITS[(byte & 0xF) as usize] as char);
+    }
+    hex
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    let digits = hex.as_bytes();
+    let value = |digit: u8| (digit as char).to_digit(16).map(|d| d as u8);
+    digits
+        .chunks(2)
+        .map(|pair| Some((value(pair[0])? << 4) | value(pair[1])?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geojson::wkt::{parse_ewkt, parse_wkt, to_ewkt};
+
+    /// The error that started a chain of `traceback!(err e)`, which has the extra data.
+    fn root_cause(err: &TracebackError) -> &TracebackError {
+        match &err.parent {
+            Some(parent) => root_cause(parent),
+            None => err,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for wkt in [
+            "POINT (1 2)",
+            "POINT Z (1 2 3)",
+            "LINESTRING (30 10, 10 30, 40 40)",
+            "POLYGON ((35 10, 45 45, 15 40, 10 20, 35 10), (20 30, 35 35, 30 20, 20 30))",
+            "MULTIPOINT Z ((10 40 1), (40 30 2))",
+            "MULTILINESTRING ((10 10, 20 20), (40 40, 30 30))",
+            "MULTIPOLYGON (((30 20, 45 40, 10 40, 30 20)), ((15 5, 40 10, 10 20, 5 10, 15 5)))",
+            "GEOMETRYCOLLECTION (POINT (40 10), LINESTRING Z (10 10 1, 20 20 2))",
+            "LINESTRING EMPTY",
+        ] {
+            let geometry = parse_wkt(wkt).unwrap();
+            assert_eq!(parse_wkb(&to_wkb(&geometry)).unwrap(), geometry);
+            assert_eq!(parse_wkb_hex(&to_wkb_hex(&geometry)).unwrap(), geometry);
+            let ewkb = to_ewkb_hex(&geometry, Some(4326));
+            let parsed = parse_ewkb_hex(&ewkb).unwrap();
+            assert_eq!(
+                to_ewkt(&parsed.geometry, parsed.srid),
+                format!("SRID=4326;{wkt}")
+            );
+        }
+    }
+
+    #[test]
+    fn test_postgis_output() {
+        // SELECT ST_AsEWKB('SRID=4326;POINT Z (1 2 3)'::geometry)
+        let ewkb = "01010000A0E6100000000000000000F03F00000000000000400000000000000840";
+        let parsed = parse_ewkb_hex(ewkb).unwrap();
+        assert_eq!(parsed, parse_ewkt("SRID=4326;POINT Z (1 2 3)").unwrap());
+        assert_eq!(to_ewkb_hex(&parsed.geometry, parsed.srid), ewkb);
+        // Big endian ISO WKB of LINESTRING M (1 2 9, 3 4 9)
+        let wkb = "00000007D2000000023FF000000000000040000000000000004022000000000000400800000000000040100000000000004022000000000000";
+        assert_eq!(
+            parse_wkb_hex(wkb).unwrap(),
+            parse_wkt("LINESTRING (1 2, 3 4)").unwrap()
+        );
+        let mut err = parse_wkb_hex("zz").unwrap_err();
+        err.is_handled = true;
+        assert_eq!(err.message, "Invalid WKB: not a hex string");
+        for (invalid, message, offset) in [
+            ("0101", "Invalid WKB: Unexpected end of data", 1),
+            ("0201000000", "Invalid WKB: Unknown byte order 2", 1),
+            (
+                "010100000000000000000000000000000000000000FF",
+                "Invalid WKB: 1 bytes left after the geometry",
+                21,
+            ),
+        ] {
+            let mut err = parse_wkb_hex(invalid).unwrap_err();
+            err.is_handled = true;
+            assert_eq!(err.message, message);
+            assert_eq!(root_cause(&err).extra_data[0]["offset"], offset);
+        }
+    }
+}
@@ -0,0 +1,464 @@
+use std::fmt::Write;
+
+use traceback_error::{serde_json::json, traceback, TracebackError};
+
+use super::{Geometry, GeometryValue, Position};
+
+/// Parses Well-Known Text into a geometry.
+///
+/// Z coordinates are kept as the third coordinate of each position, and M values are dropped,
+/// since GeoJSON positions have no place for them. `EMPTY` geometries become geometries without coordinates,
+/// except `POINT EMPTY`, which GeoJSON can't represent.
+///
+/// ## Example
+///
+/// ```rust
+/// use utils::geojson::{wkt::parse_wkt, GeometryValue};
+///
+/// let geometry = parse_wkt("POINT (10.75 59.91)").unwrap();
+/// assert_eq!(geometry.value, GeometryValue::Point(vec![10.75, 59.91]));
+/// ```
+pub fn parse_wkt(wkt: &str) -> Result<Geometry, TracebackError> {
+    let mut parser = WktParser::new(wkt);
+    let geometry = match parser.geometry() {
+        Ok(geometry) => geometry,
+        Err(e) => return Err(traceback!(err e)),
+    };
+    match parser.next_token() {
+        None => Ok(geometry),
+        Some(token) => Err(parser.error(&format!("Unexpected {token:?} after the geometry"))),
+    }
+}
+
+/// Writes a geometry as Well-Known Text.
+///
+/// Positions with a third coordinate are written as Z geometries.
+pub fn to_wkt(geometry: &Geometry) -> String {
+    let mut wkt = String::new();
+    write_geometry(&mut wkt, geometry);
+    wkt
+}
+
+/// A geometry with the spatial reference id that EWKT and EWKB can carry, such as 4326 for WGS 84.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SridGeometry {
+    pub srid: Option<u32>,
+    pub geometry: Geometry,
+}
+
+/// Parses PostGIS Extended WKT, which is WKT with an optional `SRID=<srid>;` prefix.
+///
+/// Plain WKT is accepted too, with no SRID.
+///
+/// ## Example
+///
+/// ```rust
+/// use utils::geojson::{wkt::parse_ewkt, GeometryValue};
+///
+/// let parsed = parse_ewkt("SRID=4326;POINT (10.75 59.91)").unwrap();
+/// assert_eq!(parsed.srid, Some(4326));
+/// assert_eq!(parsed.geometry.value, GeometryValue::Point(vec![10.75, 59.91]));
+/// ```
+pub fn parse_ewkt(ewkt: &str) -> Result<SridGeometry, TracebackError> {
+    let trimmed = ewkt.trim_start();
+    let has_srid = trimmed
+        .get(..5)
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case("SRID="));
+    let (srid, wkt) = match has_srid {
+        false => (None, trimmed),
+        true => {
+            let (srid, wkt) = match trimmed[5..].split_once(';') {
+                Some(split) => split,
+                None => {
+                    return Err(traceback!("Invalid EWKT: SRID is not followed by \";\"")
+                        .with_extra_data(json!({ "ewkt": ewkt })))
+                }
+            };
+            match srid.trim().parse::<u32>() {
+                Ok(srid) => (Some(srid), wkt),
+                Err(_) => {
+                    return Err(
+                        traceback!(format!("Invalid EWKT: SRID {srid:?} is not a number"))
+                            .with_extra_data(json!({ "ewkt": ewkt })),
+                    )
+                }
+            }
+        }
+    };
+    match parse_wkt(wkt) {
+        Ok(geometry) => Ok(SridGeometry { srid, geometry }),
+        Err(e) => Err(traceback!(err e)),
+    }
+}
+
+/// Writes a geometry as PostGIS Extended WKT, prefixed with `SRID=<srid>;` if there is one.
+pub fn to_ewkt(geometry: &Geometry, srid: Option<u32>) -> String {
+    match srid {
+        Some(srid) => format!("SRID={srid};{}", to_wkt(geometry)),
+        None => to_wkt(geometry),
+    }
+}
+
+/// Whether any position of a geometry has a Z coordinate.
+pub(crate) fn has_z(geometry: &Geometry) -> bool {
+    let mut has_z = false;
+    super::measure::for_each_position(geometry, &mut |p| has_z |= p.len() > 2);
+    has_z
+}
+
+/// Whether WKT coordinates carry Z and M values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Dimensions {
+    z: bool,
+    m: bool,
+}
+
+struct WktParser<'a> {
+    wkt: &'a str,
+    offset: usize,
+}
+
+impl<'a> WktParser<'a> {
+    fn new(wkt: &'a str) -> Self {
+        Self { wkt, offset: 0 }
+    }
+
+    fn error(&self, message: &str) -> TracebackError {
+        traceback!(format!("Invalid WKT: {message}")).with_extra_data(json!({
+            "wkt": self.wkt,
+            "offset": self.offset,
+        }))
+    }
+
+    /// Returns the next token without consuming it: a word, a number, or one of `(`, `)` and `,`.
+    fn peek_token(&self) -> Option<&'a str> {
+        let rest = &self.wkt[self.offset..];
+        let trimmed = rest.trim_start();
+        let start = rest.len() - trimmed.len();
+        let first = trimmed.chars().next()?;
+        let len = match first {
+            '(' | ')' | ',' => 1,
+            _ => trimmed
+                .find(|c: char| c.is_whitespace() || matches!(c, '(' | ')' | ','))
+                .unwrap_or(trimmed.len()),
+        };
+        Some(&rest[start..start + len])
+    }
+
+    fn next_token(&mut self) -> Option<&'a str> {
+        let token = self.peek_token()?;
+        let rest = &self.wkt[self.offset..];
+        self.offset += rest.len() - rest.trim_start().len() + token.len();
+        Some(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), TracebackError> {
+        match self.next_token() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(self.error(&format!("Expected {expected:?}, found {token:?}"))),
+            None => Err(self.error(&format!("Expected {expected:?}, found the end"))),
+        }
+    }
+
+    fn geometry(&mut self) -> Result<Geometry, TracebackError> {
+        let kind = match self.next_token() {
+            Some(kind) => kind.to_ascii_uppercase(),
+            None => return Err(self.error("Expected a geometry type")),
+        };
+        let dimensions = match self.peek_token().map(str::to_ascii_uppercase).as_deref() {
+            Some("Z") => Dimensions { z: true, m: false },
+            Some("M") => Dimensions { z: false, m: true },
+            Some("ZM") => Dimensions { z: true, m: true },
+            _ => Dimensions { z: false, m: false },
+        };
+        if dimensions.z || dimensions.m {
+            self.next_token();
+        }
+        let empty = self
+            .peek_token()
+            .is_some_and(|token| token.eq_ignore_ascii_case("EMPTY"));
+        if empty {
+            self.next_token();
+        }
+        let value = match (kind.as_str(), empty) {
+            ("POINT", true) => {
+                return Err(self.error("POINT EMPTY can't be represented in GeoJSON"))
+            }
+            ("POINT", false) => self
+                .parenthesized(|p| p.position(dimensions))
+                .map(GeometryValue::Point),
+            ("MULTIPOINT", true) => Ok(GeometryValue::MultiPoint(vec![])),
+            ("MULTIPOINT", false) => self
+                .list(|p| p.multipoint_member(dimensions))
+                .map(GeometryValue::MultiPoint),
+            ("LINESTRING", true) => Ok(GeometryValue::LineString(vec![])),
+            ("LINESTRING", false) => self.positions(dimensions).map(GeometryValue::LineString),
+            ("MULTILINESTRING", true) => Ok(GeometryValue::MultiLineString(vec![])),
+            ("MULTILINESTRING", false) => self
+                .list(|p| p.positions(dimensions))
+                .map(GeometryValue::MultiLineString),
+            ("POLYGON", true) => Ok(GeometryValue::Polygon(vec![])),
+            ("POLYGON", false) => self.rings(dimensions).map(GeometryValue::Polygon),
+            ("MULTIPOLYGON", true) => Ok(GeometryValue::MultiPolygon(vec![])),
+            ("MULTIPOLYGON", false) => self
+                .list(|p| p.rings(dimensions))
+                .map(GeometryValue::MultiPolygon),
+            ("GEOMETRYCOLLECTION", true) => Ok(GeometryValue::GeometryCollection(vec![])),
+            ("GEOMETRYCOLLECTION", false) => self
+                .list(|p| p.geometry())
+                .map(GeometryValue::GeometryCollection),
+            (other, _) => return Err(self.error(&format!("Unknown geometry type {other}"))),
+        };
+        match value {
+            Ok(value) => Ok(Geometry::new(value)),
+            Err(e) => Err(traceback!(err e)),
+        }
+    }
+
+    fn parenthesized<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, TracebackError>,
+    ) -> Result<T, TracebackError> {
+        if let Err(e) = self.expect("(") {
+            return Err(traceback!(err e));
+        }
+        let value = match parse(self) {
+            Ok(value) => value,
+            Err(e) => return Err(traceback!(err e)),
+        };
+        match self.expect(")") {
+            Ok(_) => Ok(value),
+            Err(e) => Err(traceback!(err e)),
+        }
+    }
+
+    /// Parses a parenthesized, comma-separated list.
+    fn list<T>(
+        &mut self,
+        mut parse: impl FnMut(&mut Self) -> Result<T, TracebackError>,
+    ) -> Result<Vec<T>, TracebackError> {
+        if let Err(e) = self.expect("(") {
+            return Err(traceback!(err e));
+        }
+        let mut items = vec![];
+        loop {
+            match parse(self) {
+                Ok(item) => items.push(item),
+                Err(e) => return Err(traceback!(err e)),
+            }
+            match self.next_token() {
+                Some(",") => continue,
+                Some(")") => return Ok(items),
+                Some(token) => {
+                    return Err(self.error(&format!("Expected \",\" or \")\", found {token:?}")))
+                }
+                None => return Err(self.error("Expected \",\" or \")\", found the end")),
+            }
+        }
+    }
+
+    fn position(&mut self, dimensions: Dimensions) -> Result<Position, TracebackError> {
+        let mut position = vec![];
+        while let Some(token) = self.peek_token() {
+            if matches!(token, "(" | ")" | ",") {
+                break;
+            }
+            match token.parse::<f64>() {
+                Ok(number) => position.push(number),
+                Err(_) => return Err(self.error(&format!("Expected a number, found {token:?}"))),
+            }
+            self.next_token();
+        }
+        let expected = 2 + dimensions.z as usize + dimensions.m as usize;
+        // Without a dimension tag, the number of coordinates decides, as in PostGIS
+        let untagged = !dimensions.z && !dimensions.m && (2..=4).contains(&position.len());
+        if position.len() != expected && !untagged {
+            return Err(self.error(&format!(
+                "Expected {expected} coordinates, found {}",
+                position.len()
+            )));
+        }
+        // Drop the M value, which is last
+        if dimensions.m || position.len() == 4 {
+            position.pop();
+        }
+        Ok(position)
+    }
+
+    fn positions(&mut self, dimensions: Dimensions) -> Result<Vec<Position>, TracebackError> {
+        self.list(|p| p.position(dimensions))
+    }
+
+    fn rings(&mut self, dimensions: Dimensions) -> Result<Vec<Vec<Position>>, TracebackError> {
+        self.list(|p| p.positions(dimensions))
+    }
+
+    /// MULTIPOINT members may or may not have their own parentheses.
+    fn multipoint_member(&mut self, dimensions: Dimensions) -> Result<Position, TracebackError> {
+        match self.peek_token() {
+            Some("(") => self.parenthesized(|p| p.position(dimensions)),
+            _ => self.position(dimensions),
+        }
+    }
+}
+
+fn write_geometry(wkt: &mut String, geometry: &Geometry) {
+    let kind = geometry.value.type_name().to_ascii_uppercase();
+    let has_z = has_z(geometry);
+    wkt.push_str(&kind);
+    if has_z && !matches!(geometry.value, GeometryValue::GeometryCollection(_)) {
+        wkt.push_str(" Z");
+    }
+    let is_empty = match &geometry.value {
+        GeometryValue::Point(_) => false,
+        GeometryValue::MultiPoint(items) | GeometryValue::LineString(items) => items.is_empty(),
+        GeometryValue::MultiLineString(items) | GeometryValue::Polygon(items) => items.is_empty(),
+        GeometryValue::MultiPolygon(items) => items.is_empty(),
+        GeometryValue::GeometryCollection(items) => items.is_empty(),
+    };
+    if is_empty {
+        wkt.push_str(" EMPTY");
+        return;
+    }
+    wkt.push(' ');
+    match &geometry.value {
+        GeometryValue::Point(p) => {
+            wkt.push('(');
+            write_position(wkt, p, has_z);
+            wkt.push(')');
+        }
+        GeometryValue::MultiPoint(ps) => write_list(wkt, ps, |wkt, p| {
+            wkt.push('(');
+            write_position(wkt, p, has_z);
+            wkt.push(')');
+        }),
+        GeometryValue::LineString(line) => write_positions(wkt, line, has_z),
+        GeometryValue::MultiLineString(rings) | GeometryValue::Polygon(rings) => {
+            write_list(wkt, rings, |wkt, ring| write_positions(wkt, ring, has_z))
+        }
+        GeometryValue::MultiPolygon(polygons) => write_list(wkt, polygons, |wkt, rings| {
+            write_list(wkt, rings, |wkt, ring| write_positions(wkt, ring, has_z))
+        }),
+        GeometryValue::GeometryCollection(geometries) => {
+            write_list(wkt, geometries, write_geometry)
+        }
+    }
+}
+
+fn write_list<T>(wkt: &mut String, items: &[T], mut write_item: impl FnMut(&mut String, &T)) {
+    wkt.push('(');
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            wkt.push_str(", ");
+        }
+        write_item(wkt, item);
+    }
+    wkt.push(')');
+}
+
+fn write_positions(wkt: &mut String, positions: &[Position], has_z: bool) {
+    write_list(wkt, positions, |wkt, p| write_position(wkt, p, has_z));
+}
+
+fn write_position(wkt: &mut String, position: &Position, has_z: bool) {
+    let _ = write!(wkt, "{} {}", position[0], position[1]);
+    if has_z {
+        let _ = write!(wkt, " {}", position.get(2).copied().unwrap_or(0.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root_cause(err: &TracebackError) -> &TracebackError {
+        match &err.parent {
+            Some(parent) => root_cause(parent),
+            None => err,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for wkt in [
+            "POINT (1 2)",
+            "POINT Z (1 2 3)",
+            "LINESTRING (30 10, 10 30, 40 40)",
+            "POLYGON ((35 10, 45 45, 15 40, 10 20, 35 10), (20 30, 35 35, 30 20, 20 30))",
+            "MULTIPOINT ((10 40), (40 30))",
+            "MULTILINESTRING ((10 10, 20 20), (40 40, 30 30))",
+            "MULTIPOLYGON (((30 20, 45 40, 10 40, 30 20)), ((15 5, 40 10, 10 20, 5 10, 15 5)))",
+            "GEOMETRYCOLLECTION (POINT (40 10), LINESTRING (10 10, 20 20))",
+            "LINESTRING EMPTY",
+        ] {
+            assert_eq!(to_wkt(&parse_wkt(wkt).unwrap()), wkt);
+        }
+    }
+
+    #[test]
+    fn test_variants() {
+        assert_eq!(
+            parse_wkt("multipoint (10 40, 40 30)").unwrap(),
+            parse_wkt("MULTIPOINT ((10 40), (40 30))").unwrap()
+        );
+        assert_eq!(
+            parse_wkt("POINT M (1 2 9)").unwrap().value,
+            GeometryValue::Point(vec![1.0, 2.0])
+        );
+        assert_eq!(
+            parse_wkt("POINT ZM (1 2 3 9)").unwrap().value,
+            GeometryValue::Point(vec![1.0, 2.0, 3.0])
+        );
+        for (invalid, message, offset) in [
+            (
+                "POINT (1)",
+                "Invalid WKT: Expected 2 coordinates, found 1",
+                8,
+            ),
+            (
+                "POINT (1 2",
+                "Invalid WKT: Expected \")\", found the end",
+                10,
+            ),
+            (
+                "LINESTRING (1 2, x 3)",
+                "Invalid WKT: Expected a number, found \"x\"",
+                16,
+            ),
+            (
+                "POINT EMPTY",
+                "Invalid WKT: POINT EMPTY can't be represented in GeoJSON",
+                11,
+            ),
+        ] {
+            let mut err = parse_wkt(invalid).unwrap_err();
+            err.is_handled = true;
+            assert_eq!(err.message, message);
+            assert_eq!(root_cause(&err).extra_data[0]["offset"], offset);
+        }
+    }
+
+    #[test]
+    fn test_ewkt() {
+        let wkt = "SRID=25833;LINESTRING Z (1 2 3, 4 5 6)";
+        let parsed = parse_ewkt(wkt).unwrap();
+        assert_eq!(parsed.srid, Some(25833));
+        assert_eq!(to_ewkt(&parsed.geometry, parsed.srid), wkt);
+        assert_eq!(parse_ewkt("POINT (1 2)").unwrap().srid, None);
+        for (invalid, message) in [
+            (
+                "SRID=x;POINT (1 2)",
+                "Invalid EWKT: SRID \"x\" is not a number",
+            ),
+            (
+                "SRID=4326 POINT (1 2)",
+                "Invalid EWKT: SRID is not followed by \";\"",
+            ),
+        ] {
+            let mut err = parse_ewkt(invalid).unwrap_err();
+            err.is_handled = true;
+            assert_eq!(err.message, message);
+            assert_eq!(err.extra_data[0]["ewkt"], invalid);
+        }
+    }
+}
@@ -1,10 +1,17 @@
 use std::{
     collections::HashMap,
     fmt::{Display, Formatter},
+    sync::OnceLock,
+    time::Duration,
 };
 
+use chrono::{DateTime, Utc};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER},
+    StatusCode,
+};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use traceback_error::{traceback, TracebackError};
 use url::Url;
 
@@ -82,6 +89,12 @@ impl Display for Method {
 ///
 /// In this example, the function `attempt_fetch_and_parse` is used to fetch JSON data from a URL, and the response is deserialized into a `Post` struct.
 /// The result is then printed.
+/// The request is sent with a client shared by every call, so connections are pooled and reused,
+/// and failed attempts are retried following `RetryPolicy::default()`.
+/// To configure timeouts, retries, default headers and the like, build an `HttpClient` instead.
+///
+/// Responses without a success status aren't parsed: the error has the status, headers and start of the body
+/// in its extra data. Use `attempt_fetch_and_parse_or_error` to parse the body of those responses too.
 pub async fn attempt_fetch_and_parse<T>(
     url: &Url,
     headers: &Option<HashMap<&str, &str>>,
@@ -91,77 +104,982 @@ pub async fn attempt_fetch_and_parse<T>(
 where
     T: serde::de::DeserializeOwned,
 {
-    let client = reqwest::Client::new();
-    let mut request_builder = match method {
-        Method::GET => client.get(url.clone()),
-        Method::POST => client.post(url.clone()),
-        Method::PUT => client.put(url.clone()),
-        Method::DELETE => client.delete(url.clone()),
-        Method::HEAD => client.head(url.clone()),
-        Method::PATCH => client.patch(url.clone()),
-    };
-    if let Some(h) = headers {
-        for (k, v) in h {
-            request_builder = request_builder.header(k.to_string(), v.to_string());
+    shared_client()
+        .fetch_and_parse(url.as_str(), headers, body, method)
+        .await
+}
+
+/// Like `attempt_fetch_and_parse`, but a response without a success status is returned as a `StatusError`,
+/// with its body parsed into `E` if possible.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use serde::Deserialize;
+/// use serde_json::Value;
+/// use url::Url;
+/// use utils::http::{attempt_fetch_and_parse_or_error, FetchError, Method};
+///
+/// #[derive(Debug, Deserialize)]
+/// struct ApiError {
+///     message: String,
+/// }
+///
+/// # async fn run() {
+/// let url = Url::parse("https://api.example.com/items/1").unwrap();
+/// match attempt_fetch_and_parse_or_error::<Value, ApiError>(&url, &None, None, Method::GET).await {
+///     Ok(item) => println!("{item}"),
+///     Err(FetchError::Status(e)) if e.status == 404 => println!("No such item"),
+///     Err(FetchError::Status(e)) => println!("{e}: {:?}", e.error_body),
+///     Err(FetchError::Traceback(e)) => println!("{e}"),
+/// }
+/// # }
+/// ```
+pub async fn attempt_fetch_and_parse_or_error<T, E>(
+    url: &Url,
+    headers: &Option<HashMap<&str, &str>>,
+    body: Option<&str>,
+    method: Method,
+) -> Result<T, FetchError<E>>
+where
+    T: serde::de::DeserializeOwned,
+    E: serde::de::DeserializeOwned,
+{
+    shared_client()
+        .fetch_and_parse_or_error(url.as_str(), headers, body, method)
+        .await
+}
+
+fn shared_client() -> &'static HttpClient {
+    static SHARED_CLIENT: OnceLock<HttpClient> = OnceLock::new();
+    SHARED_CLIENT.get_or_init(|| HttpClient {
+        client: reqwest::Client::new(),
+        base_url: None,
+        retry: RetryPolicy::default(),
+    })
+}
+
+/// The most of a response body kept in a `StatusError`, in bytes.
+pub const MAX_ERROR_BODY_LEN: usize = 4096;
+
+/// A response whose status wasn't a success, which was returned instead of being parsed.
+#[derive(Debug, Clone)]
+pub struct StatusError<E> {
+    pub status: u16,
+    /// The response headers, with the values of repeated headers joined by `, `.
+    pub headers: HashMap<String, String>,
+    /// The body as text, cut to at most `MAX_ERROR_BODY_LEN` bytes.
+    pub body: String,
+    /// Whether `body` was cut.
+    pub truncated: bool,
+    /// The whole body parsed into `E`, if it could be.
+    pub error_body: Option<E>,
+}
+
+impl<E> StatusError<E> {
+    fn new(status: StatusCode, headers: &HeaderMap, body: &str, error_body: Option<E>) -> Self {
+        let mut joined: HashMap<String, String> = HashMap::new();
+        for (name, value) in headers {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            joined
+                .entry(name.to_string())
+                .and_modify(|joined| {
+                    joined.push_str(", ");
+                    joined.push_str(&value);
+                })
+                .or_insert_with(|| value.to_string());
+        }
+        let mut end = body.len().min(MAX_ERROR_BODY_LEN);
+        while !body.is_char_boundary(end) {
+            end -= 1;
+        }
+        StatusError {
+            status: status.as_u16(),
+            headers: joined,
+            body: body[..end].to_string(),
+            truncated: end < body.len(),
+            error_body,
         }
     }
-    let request = match &body {
-        Some(b) => request_builder.body(b.to_string()).build(),
-        None => request_builder.build(),
-    };
-    let request = match request {
-        Ok(r) => r,
-        Err(e) => {
-            return Err(
-                traceback!(err e, "Error building request").with_extra_data(json!({
-                    "url": url,
-                    "headers": headers,
-                    "body": body,
-                    "method": method,
-                })),
-            );
+
+    /// The status, headers and body, as added to the extra data of a `TracebackError`.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "status": self.status,
+            "headers": self.headers,
+            "response": self.body,
+            "truncated": self.truncated,
+        })
+    }
+}
+
+impl<E> Display for StatusError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let reason = StatusCode::from_u16(self.status)
+            .ok()
+            .and_then(|status| status.canonical_reason());
+        match reason {
+            Some(reason) => write!(f, "Request failed with status {} {reason}", self.status),
+            None => write!(f, "Request failed with status {}", self.status),
         }
-    };
-    let response = match client.execute(request).await {
-        Ok(r) => r,
-        Err(e) => {
-            return Err(
-                traceback!(err e, "Error executing request").with_extra_data(json!({
-                    "url": url,
-                    "headers": headers,
-                    "body": body,
-                    "method": method,
-                })),
-            );
+    }
+}
+
+impl<E: std::fmt::Debug> std::error::Error for StatusError<E> {}
+
+/// Why `fetch_and_parse_or_error` failed.
+#[derive(Debug)]
+pub enum FetchError<E> {
+    /// The server answered, but not with a success status.
+    Status(StatusError<E>),
+    /// The request couldn't be sent, or the response couldn't be read or parsed.
+    Traceback(TracebackError),
+}
+
+impl<E> Display for FetchError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FetchError::Status(e) => write!(f, "{e}"),
+            FetchError::Traceback(e) => write!(f, "{e}"),
         }
-    };
-    let response = match response.text().await {
-        Ok(r) => r,
-        Err(e) => {
-            return Err(
-                traceback!(err e, "Error reading response").with_extra_data(json!({
-                    "url": url,
-                    "headers": headers,
-                    "body": body,
-                    "method": method,
-                })),
+    }
+}
+
+impl<E: std::fmt::Debug> std::error::Error for FetchError<E> {}
+
+/// A response read to the end, along with the request it answers, for error extra data.
+struct TextResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: String,
+    context: Value,
+}
+
+impl TextResponse {
+    fn parse<T>(&self) -> Result<T, TracebackError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        match serde_json::from_str(&self.body) {
+            Ok(r) => Ok(r),
+            Err(e) => {
+                let mut extra_data = self.context.clone();
+                extra_data["response"] = json!(self.body);
+                Err(traceback!(err e, "Error parsing response").with_extra_data(extra_data))
+            }
+        }
+    }
+}
+
+/// How many redirects an `HttpClient` follows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectPolicy {
+    /// Return redirect responses as they are.
+    None,
+    /// Follow up to this many redirects, then fail.
+    Limited(usize),
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        RedirectPolicy::Limited(10)
+    }
+}
+
+/// When an `HttpClient` sends a failed request again, and how long it waits before doing so.
+///
+/// Connect errors can always be retried, since the request never reached the server.
+/// Timeouts, `429 Too Many Requests` and `5xx` responses are only retried for idempotent methods
+/// (`GET`, `HEAD`, `PUT` and `DELETE`) unless `retry_non_idempotent` is set, so a `POST` isn't applied twice.
+///
+/// ## Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use utils::http::{HttpClient, RetryPolicy};
+///
+/// let client = HttpClient::builder()
+///     .retry_policy(RetryPolicy {
+///         max_attempts: 5,
+///         base_delay: Duration::from_millis(500),
+///         ..RetryPolicy::default()
+///     })
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The total number of attempts, including the first one. 1 disables retries.
+    pub max_attempts: u32,
+    /// The delay before the first retry, doubled for every retry after it.
+    pub base_delay: Duration,
+    /// The longest wait between two attempts, including waits asked for with `Retry-After`.
+    pub max_delay: Duration,
+    /// Waits a random time between zero and the backoff delay,
+    /// so clients that failed at the same moment don't all retry at the same moment.
+    pub jitter: bool,
+    pub retry_connect_errors: bool,
+    pub retry_timeouts: bool,
+    /// Retry `429 Too Many Requests` responses.
+    pub retry_too_many_requests: bool,
+    /// Retry `5xx` responses.
+    pub retry_server_errors: bool,
+    /// Wait as long as a `Retry-After` header asks, up to `max_delay`, instead of the backoff delay.
+    pub honor_retry_after: bool,
+    /// Retry `POST` and `PATCH` requests on timeouts and error statuses too.
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(10),
+            jitter: true,
+            retry_connect_errors: true,
+            retry_timeouts: true,
+            retry_too_many_requests: true,
+            retry_server_errors: true,
+            honor_retry_after: true,
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that sends every request exactly once.
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// The delay after failed attempt number `attempt`, counting from 1, before jitter is applied.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        match retry_after {
+            Some(retry_after) if self.honor_retry_after => retry_after.min(self.max_delay),
+            _ if self.jitter => self.backoff(attempt).mul_f64(fastrand::f64()),
+            _ => self.backoff(attempt),
+        }
+    }
+
+    fn retries_method(&self, method: Method) -> bool {
+        self.retry_non_idempotent
+            || matches!(
+                method,
+                Method::GET | Method::HEAD | Method::PUT | Method::DELETE
+            )
+    }
+}
+
+/// Parses a `Retry-After` header, given either in seconds or as an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// One attempt at sending a request, reported in the error when every attempt fails.
+#[derive(Debug, Serialize)]
+struct Attempt {
+    attempt: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// How long was waited before the next attempt.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delay_ms: Option<u128>,
+}
+
+/// An HTTP client that keeps its connection pool between requests, built with `HttpClient::builder`.
+///
+/// Cloning is cheap and shares the pool, so one client can be handed to every part of a service.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use serde::Deserialize;
+/// use utils::http::{HttpClient, Method};
+///
+/// #[derive(Debug, Deserialize)]
+/// struct Post {
+///     id: u32,
+///     title: String,
+/// }
+///
+/// # async fn run() -> Result<(), traceback_error::TracebackError> {
+/// let client = HttpClient::builder()
+///     .base_url("https://jsonplaceholder.typicode.com")
+///     .default_header("Accept", "application/json")
+///     .timeout(Duration::from_secs(10))
+///     .user_agent("my-service/1.0")
+///     .build()?;
+/// let post: Post = client.fetch_and_parse("posts/1", &None, None, Method::GET).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    base_url: Option<Url>,
+    retry: RetryPolicy,
+}
+
+/// Configures an `HttpClient`. Nothing is checked until `build`.
+#[derive(Debug, Clone, Default)]
+pub struct HttpClientBuilder {
+    base_url: Option<String>,
+    default_headers: Vec<(String, String)>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    user_agent: Option<String>,
+    proxy: Option<String>,
+    redirect: RedirectPolicy,
+    retry: RetryPolicy,
+}
+
+impl HttpClientBuilder {
+    /// The URL that relative request URLs are resolved against.
+    /// It is treated as a directory, so `https://api.example.com/v1` and `https://api.example.com/v1/` are the same.
+    pub fn base_url(mut self, base_url: &str) -> Self {
+        self.base_url = Some(base_url.to_string());
+        self
+    }
+
+    /// A header sent with every request, unless the request sets it itself.
+    pub fn default_header(mut self, name: &str, value: &str) -> Self {
+        self.default_headers
+            .push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// The time limit for a whole request, from connecting to reading the end of the body.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// The time limit for establishing a connection.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = Some(user_agent.to_string());
+        self
+    }
+
+    /// A proxy for every request, like `http://proxy.internal:3128`.
+    pub fn proxy(mut self, proxy_url: &str) -> Self {
+        self.proxy = Some(proxy_url.to_string());
+        self
+    }
+
+    pub fn redirect(mut self, policy: RedirectPolicy) -> Self {
+        self.redirect = policy;
+        self
+    }
+
+    /// How failed requests are retried, `RetryPolicy::default()` unless set.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Builds the client, failing if the base URL, a header or the proxy is invalid.
+    pub fn build(self) -> Result<HttpClient, TracebackError> {
+        let base_url = match &self.base_url {
+            Some(base_url) => match Url::parse(base_url) {
+                Ok(mut url) => {
+                    // Without a trailing slash, joining would replace the last path segment
+                    if !url.path().ends_with('/') {
+                        url.set_path(&format!("{}/", url.path()));
+                    }
+                    Some(url)
+                }
+                Err(e) => {
+                    return Err(traceback!("Invalid base URL")
+                        .with_extra_data(json!({ "error": e.to_string(), "base_url": base_url })))
+                }
+            },
+            None => None,
+        };
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.default_headers {
+            let name_value = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
             );
+            match name_value {
+                (Ok(name), Ok(value)) => {
+                    headers.insert(name, value);
+                }
+                _ => {
+                    return Err(traceback!("Invalid default header")
+                        .with_extra_data(json!({ "name": name, "value": value })))
+                }
+            }
         }
-    };
-    let response: T = match serde_json::from_str(&response) {
-        Ok(r) => r,
-        Err(e) => {
-            return Err(
-                traceback!(err e, "Error parsing response").with_extra_data(json!({
+        let mut builder = reqwest::Client::builder().default_headers(headers);
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        if let Some(proxy) = &self.proxy {
+            match reqwest::Proxy::all(proxy) {
+                Ok(proxy) => builder = builder.proxy(proxy),
+                Err(e) => {
+                    return Err(traceback!("Invalid proxy URL")
+                        .with_extra_data(json!({ "error": e.to_string(), "proxy": proxy })))
+                }
+            }
+        }
+        builder = builder.redirect(match self.redirect {
+            RedirectPolicy::None => reqwest::redirect::Policy::none(),
+            RedirectPolicy::Limited(max) => reqwest::redirect::Policy::limited(max),
+        });
+        match builder.build() {
+            Ok(client) => Ok(HttpClient {
+                client,
+                base_url,
+                retry: self.retry,
+            }),
+            Err(e) => Err(traceback!("Failed to build HTTP client")
+                .with_extra_data(json!({ "error": e.to_string() }))),
+        }
+    }
+}
+
+impl HttpClient {
+    pub fn builder() -> HttpClientBuilder {
+        HttpClientBuilder::default()
+    }
+
+    /// Resolves a request URL: absolute URLs are used as they are,
+    /// and anything else is relative to the base URL, with or without a leading `/`.
+    pub fn resolve_url(&self, url: &str) -> Result<Url, TracebackError> {
+        let resolved = match (Url::parse(url), &self.base_url) {
+            (Ok(url), _) => Ok(url),
+            (Err(url::ParseError::RelativeUrlWithoutBase), Some(base)) => {
+                base.join(url.trim_start_matches('/'))
+            }
+            (Err(e), _) => Err(e),
+        };
+        match resolved {
+            Ok(url) => Ok(url),
+            Err(e) => Err(
+                traceback!(err e, "Invalid request URL").with_extra_data(json!({
                     "url": url,
-                    "headers": headers,
-                    "body": body,
-                    "response": response,
-                    "method": method,
+                    "base_url": self.base_url,
                 })),
-            );
+            ),
+        }
+    }
+
+    /// Sends a request and parses the JSON response into `T`, like `attempt_fetch_and_parse`,
+    /// but through this client's connection pool and settings.
+    /// `url` may be relative to the base URL, see `resolve_url`.
+    ///
+    /// A response without a success status is an error carrying its status, headers and the start of its body,
+    /// see `StatusError::to_json`.
+    pub async fn fetch_and_parse<T>(
+        &self,
+        url: &str,
+        headers: &Option<HashMap<&str, &str>>,
+        body: Option<&str>,
+        method: Method,
+    ) -> Result<T, TracebackError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let response = match self.fetch_text(url, headers, body, method).await {
+            Ok(r) => r,
+            Err(e) => return Err(traceback!(err e)),
+        };
+        if !response.status.is_success() {
+            let error: StatusError<()> =
+                StatusError::new(response.status, &response.headers, &response.body, None);
+            let mut extra_data = response.context;
+            for (key, value) in error.to_json().as_object().into_iter().flatten() {
+                extra_data[key] = value.clone();
+            }
+            return Err(traceback!(error.to_string()).with_extra_data(extra_data));
+        }
+        match response.parse() {
+            Ok(parsed) => Ok(parsed),
+            Err(e) => Err(traceback!(err e)),
+        }
+    }
+
+    /// Like `fetch_and_parse`, but a response without a success status is returned as a `StatusError`,
+    /// with its body parsed into the error type `E` if possible.
+    pub async fn fetch_and_parse_or_error<T, E>(
+        &self,
+        url: &str,
+        headers: &Option<HashMap<&str, &str>>,
+        body: Option<&str>,
+        method: Method,
+    ) -> Result<T, FetchError<E>>
+    where
+        T: serde::de::DeserializeOwned,
+        E: serde::de::DeserializeOwned,
+    {
+        let response = match self.fetch_text(url, headers, body, method).await {
+            Ok(r) => r,
+            Err(e) => return Err(FetchError::Traceback(traceback!(err e))),
+        };
+        if !response.status.is_success() {
+            let error_body = serde_json::from_str(&response.body).ok();
+            return Err(FetchError::Status(StatusError::new(
+                response.status,
+                &response.headers,
+                &response.body,
+                error_body,
+            )));
+        }
+        match response.parse() {
+            Ok(parsed) => Ok(parsed),
+            Err(e) => Err(FetchError::Traceback(traceback!(err e))),
         }
+    }
+
+    /// Sends a request and reads the whole response as text, whatever its status.
+    async fn fetch_text(
+        &self,
+        url: &str,
+        headers: &Option<HashMap<&str, &str>>,
+        body: Option<&str>,
+        method: Method,
+    ) -> Result<TextResponse, TracebackError> {
+        let url = match self.resolve_url(url) {
+            Ok(url) => url,
+            Err(e) => return Err(traceback!(err e)),
+        };
+        let context = |attempts: &[Attempt]| {
+            json!({
+                "url": url,
+                "headers": headers,
+                "body": body,
+                "method": method,
+                "attempts": attempts,
+            })
+        };
+        let (response, attempts) = match self.send(&url, headers, body, method, &context).await {
+            Ok(sent) => sent,
+            Err(e) => return Err(traceback!(err e)),
+        };
+        let status = response.status();
+        let response_headers = response.headers().clone();
+        match response.text().await {
+            Ok(text) => Ok(TextResponse {
+                status,
+                headers: response_headers,
+                body: text,
+                context: context(&attempts),
+            }),
+            Err(e) => {
+                Err(traceback!(err e, "Error reading response").with_extra_data(context(&attempts)))
+            }
+        }
+    }
+
+    /// Sends a request, retrying it as the retry policy allows,
+    /// and returns the last response along with every attempt made.
+    async fn send(
+        &self,
+        url: &Url,
+        headers: &Option<HashMap<&str, &str>>,
+        body: Option<&str>,
+        method: Method,
+        context: &impl Fn(&[Attempt]) -> Value,
+    ) -> Result<(reqwest::Response, Vec<Attempt>), TracebackError> {
+        let policy = &self.retry;
+        let mut attempts: Vec<Attempt> = vec![];
+        loop {
+            let number = attempts.len() as u32 + 1;
+            let request = match self.build_request(url, headers, body, method) {
+                Ok(r) => r,
+                Err(e) => {
+                    return Err(traceback!(err e, "Error building request")
+                        .with_extra_data(context(&attempts)));
+                }
+            };
+            let last = number >= policy.max_attempts;
+            let retries_method = policy.retries_method(method);
+            let (mut attempt, retry_after) = match self.client.execute(request).await {
+                Ok(response) => {
+                    let status = response.status();
+                    let retry = retries_method
+                        && ((status.as_u16() == 429 && policy.retry_too_many_requests)
+                            || (status.is_server_error() && policy.retry_server_errors));
+                    let attempt = Attempt {
+                        attempt: number,
+                        status: Some(status.as_u16()),
+                        error: None,
+                        delay_ms: None,
+                    };
+                    if !retry || last {
+                        attempts.push(attempt);
+                        return Ok((response, attempts));
+                    }
+                    (attempt, retry_after(response.headers()))
+                }
+                Err(e) => {
+                    let retry = (e.is_connect() && policy.retry_connect_errors)
+                        || (e.is_timeout() && policy.retry_timeouts && retries_method);
+                    let attempt = Attempt {
+                        attempt: number,
+                        status: None,
+                        error: Some(e.to_string()),
+                        delay_ms: None,
+                    };
+                    if !retry || last {
+                        attempts.push(attempt);
+                        return Err(traceback!(err e, "Error executing request")
+                            .with_extra_data(context(&attempts)));
+                    }
+                    (attempt, None)
+                }
+            };
+            let delay = policy.delay(number, retry_after);
+            attempt.delay_ms = Some(delay.as_millis());
+            attempts.push(attempt);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    fn build_request(
+        &self,
+        url: &Url,
+        headers: &Option<HashMap<&str, &str>>,
+        body: Option<&str>,
+        method: Method,
+    ) -> Result<reqwest::Request, reqwest::Error> {
+        let client = &self.client;
+        let mut request_builder = match method {
+            Method::GET => client.get(url.clone()),
+            Method::POST => client.post(url.clone()),
+            Method::PUT => client.put(url.clone()),
+            Method::DELETE => client.delete(url.clone()),
+            Method::HEAD => client.head(url.clone()),
+            Method::PATCH => client.patch(url.clone()),
+        };
+        if let Some(h) = headers {
+            for (k, v) in h {
+                request_builder = request_builder.header(k.to_string(), v.to_string());
+            }
+        }
+        match &body {
+            Some(b) => request_builder.body(b.to_string()).build(),
+            None => request_builder.build(),
+        }
+    }
+
+    /// Sends a GET request and parses the JSON response into `T`, see `fetch_and_parse`.
+    pub async fn get<T>(&self, url: &str) -> Result<T, TracebackError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        self.fetch_and_parse(url, &None, None, Method::GET).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        thread::{self, JoinHandle},
     };
 
-    Ok(response)
+    use serde_json::Value;
+
+    use super::*;
+
+    /// Serves the given raw responses, one connection each, and returns the requests it received.
+    fn serve(responses: Vec<String>) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut requests = vec![];
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request = String::new();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(length) = line.to_lowercase().strip_prefix("content-length:") {
+                        content_length = length.trim().parse().unwrap();
+                    }
+                    request.push_str(&line);
+                    if line == "\r\n" || line.is_empty() {
+                        break;
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                request.push_str(&String::from_utf8_lossy(&body));
+                requests.push(request);
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+        (address, handle)
+    }
+
+    fn response(status: &str, extra_headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n{extra_headers}\r\n{body}",
+            body.len()
+        )
+    }
+
+    #[tokio::test]
+    async fn test_client_builder() {
+        let (address, server) = serve(vec![
+            response("200 OK", "", r#"{"id": 1}"#),
+            response("200 OK", "", "[]"),
+        ]);
+        let client = HttpClient::builder()
+            .base_url(&format!("{address}/api"))
+            .default_header("X-Team", "geo")
+            .user_agent("utils-test")
+            .timeout(Duration::from_secs(5))
+            .build()
+            .unwrap();
+        let item: Value = client.get("items/1").await.unwrap();
+        assert_eq!(item, json!({"id": 1}));
+        let mut headers = HashMap::new();
+        headers.insert("Content-Type", "application/json");
+        let items: Vec<Value> = client
+            .fetch_and_parse("/items", &Some(headers), Some("{}"), Method::POST)
+            .await
+            .unwrap();
+        assert!(items.is_empty());
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("GET /api/items/1 HTTP/1.1\r\n"));
+        assert!(requests[0].contains("x-team: geo\r\n"));
+        assert!(requests[0].contains("user-agent: utils-test\r\n"));
+        assert!(requests[1].starts_with("POST /api/items HTTP/1.1\r\n"));
+        assert!(requests[1].ends_with("\r\n\r\n{}"));
+
+        for (builder, message) in [
+            (
+                HttpClient::builder().base_url("not a url"),
+                "Invalid base URL",
+            ),
+            (
+                HttpClient::builder().default_header("bad header", "x"),
+                "Invalid default header",
+            ),
+            (HttpClient::builder().proxy("::"), "Invalid proxy URL"),
+        ] {
+            let mut err = builder.build().unwrap_err();
+            err.is_handled = true;
+            assert_eq!(err.message, message);
+        }
+    }
+
+    /// The attempt history recorded in an error or one of its parents.
+    fn attempts(err: &TracebackError) -> Vec<Value> {
+        let recorded = err
+            .extra_data
+            .iter()
+            .find_map(|data| data["attempts"].as_array().cloned());
+        match (recorded, &err.parent) {
+            (Some(attempts), _) => attempts,
+            (None, Some(parent)) => attempts(parent),
+            (None, None) => vec![],
+        }
+    }
+
+    fn client(policy: RetryPolicy, address: &str) -> HttpClient {
+        HttpClient::builder()
+            .base_url(address)
+            .retry_policy(policy)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_retries() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        };
+        let (address, server) = serve(vec![
+            response("503 Service Unavailable", "Retry-After: 0\r\n", "busy"),
+            response("429 Too Many Requests", "", "slow down"),
+            response("200 OK", "", r#"{"ok": true}"#),
+        ]);
+        let value: Value = client(policy, &address).get("status").await.unwrap();
+        assert_eq!(value, json!({"ok": true}));
+        assert_eq!(server.join().unwrap().len(), 3);
+
+        // Every attempt fails, so the error carries all of them
+        let (address, server) = serve(vec![
+            response("500 Internal Server Error", "", "<html></html>"),
+            response("502 Bad Gateway", "", "<html></html>"),
+            response("503 Service Unavailable", "", "<html></html>"),
+        ]);
+        let mut err = client(policy, &address)
+            .get::<Value>("status")
+            .await
+            .unwrap_err();
+        err.is_handled = true;
+        let statuses: Vec<Value> = attempts(&err).iter().map(|a| a["status"].clone()).collect();
+        assert_eq!(statuses, vec![json!(500), json!(502), json!(503)]);
+        assert!(attempts(&err)[0]["delay_ms"].is_u64());
+        assert!(attempts(&err)[2]["delay_ms"].is_null());
+        server.join().unwrap();
+
+        // A POST might have been applied, so it isn't sent again
+        let (address, server) = serve(vec![response("503 Service Unavailable", "", "")]);
+        let mut err = client(policy, &address)
+            .fetch_and_parse::<Value>("items", &None, Some("{}"), Method::POST)
+            .await
+            .unwrap_err();
+        err.is_handled = true;
+        assert_eq!(attempts(&err).len(), 1);
+        assert_eq!(server.join().unwrap().len(), 1);
+
+        // Nothing is listening, so every attempt fails to connect
+        let address = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+        let mut err = client(policy, &address)
+            .get::<Value>("status")
+            .await
+            .unwrap_err();
+        err.is_handled = true;
+        assert_eq!(attempts(&err).len(), 3);
+        assert!(attempts(&err).iter().all(|a| a["error"].is_string()));
+    }
+
+    #[tokio::test]
+    async fn test_status_errors() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct ApiError {
+            message: String,
+        }
+
+        let long_body = "é".repeat(MAX_ERROR_BODY_LEN);
+        let (address, server) = serve(vec![
+            response(
+                "404 Not Found",
+                "X-Request-Id: abc\r\n",
+                "<h1>Not Found</h1>",
+            ),
+            response("422 Unprocessable Entity", "", r#"{"message": "bad name"}"#),
+            response("500 Internal Server Error", "", &long_body),
+        ]);
+        let client = client(RetryPolicy::none(), &address);
+
+        let mut err = client.get::<Value>("missing").await.unwrap_err();
+        err.is_handled = true;
+        assert_eq!(err.message, "Request failed with status 404 Not Found");
+        assert_eq!(err.extra_data[0]["status"], json!(404));
+        assert_eq!(err.extra_data[0]["headers"]["x-request-id"], json!("abc"));
+        assert_eq!(err.extra_data[0]["response"], json!("<h1>Not Found</h1>"));
+        assert_eq!(err.extra_data[0]["method"], json!("GET"));
+
+        let err = client
+            .fetch_and_parse_or_error::<Value, ApiError>("items", &None, Some("{}"), Method::PUT)
+            .await
+            .unwrap_err();
+        match err {
+            FetchError::Status(e) => {
+                assert_eq!(e.status, 422);
+                assert_eq!(
+                    e.error_body,
+                    Some(ApiError {
+                        message: "bad name".to_string()
+                    })
+                );
+            }
+            FetchError::Traceback(mut e) => {
+                e.is_handled = true;
+                panic!("expected a status error, got {e}");
+            }
+        }
+
+        let err = client
+            .fetch_and_parse_or_error::<Value, ApiError>("crash", &None, None, Method::GET)
+            .await
+            .unwrap_err();
+        match err {
+            FetchError::Status(e) => {
+                assert_eq!(e.status, 500);
+                assert!(e.truncated);
+                assert_eq!(e.body.len(), MAX_ERROR_BODY_LEN);
+                assert!(e.error_body.is_none());
+            }
+            FetchError::Traceback(mut e) => {
+                e.is_handled = true;
+                panic!("expected a status error, got {e}");
+            }
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_retry_delays() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            jitter: false,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+        assert_eq!(policy.backoff(10), Duration::from_secs(1));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(1));
+        assert!(RetryPolicy::default().delay(2, None) <= Duration::from_millis(400));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+        assert_eq!(
+            policy.delay(1, retry_after(&headers)),
+            Duration::from_secs(1)
+        );
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+        let later = (Utc::now() + chrono::Duration::seconds(30)).to_rfc2822();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(&later).unwrap());
+        let delay = retry_after(&headers).unwrap();
+        assert!(delay > Duration::from_secs(25) && delay <= Duration::from_secs(30));
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("soon"));
+        assert_eq!(retry_after(&headers), None);
+    }
 }
@@ -1,12 +1,11 @@
-use std::{
-    fs::{create_dir_all, read_to_string, File},
-    io::Write,
-};
+use std::{fs::create_dir_all, path::Path};
 
 use serde_json::{Map, Value};
 
 use traceback_error::{traceback, TracebackError};
 
+use crate::compression::{read_file, strip_compression_extension, write_file};
+
 /// Splits a JSON array from a file into multiple smaller files.
 ///
 /// The purpose of this function is to split a large JSON array stored in a file
@@ -15,6 +14,10 @@ use traceback_error::{traceback, TracebackError};
 /// # Arguments
 ///
 /// * `filepath` - A string representing the path to the input JSON file.
+///   The file may be gzip or zstd compressed. If its name ends in `.gz` or `.zst`,
+///   the split files are compressed the same way.
+///   The split files are written to a folder next to the input file, named after it without its extensions:
+///   `data/items.json.gz` is split into `data/items/0.json.gz`, `data/items/1.json.gz` and so on.
 /// * `split_size` - The size of each split file.
 ///
 /// # Returns
@@ -77,13 +80,13 @@ use traceback_error::{traceback, TracebackError};
 /// In this example, the `split_array_from_json_file` function is used to split a JSON array from a file into smaller files.
 /// Make sure to specify the correct file path and desired split size for your use case.
 pub fn split_array_from_json_file(filepath: &str, split_size: usize) -> Result<(), TracebackError> {
-    let str = match read_to_string(filepath) {
-        Ok(s) => s,
+    let bytes = match read_file(filepath) {
+        Ok(b) => b,
         Err(e) => {
             return Err(traceback!(err e, "Error when reading roller JSON"));
         }
     };
-    let parsed: serde_json::Value = match serde_json::from_str(&str) {
+    let parsed: serde_json::Value = match serde_json::from_slice(&bytes) {
         Ok(p) => p,
         Err(e) => {
             return Err(traceback!(err e, "Error when parsing roller JSON"));
@@ -95,16 +98,24 @@ pub fn split_array_from_json_file(filepath: &str, split_size: usize) -> Result<(
             return Err(traceback!("Error when parsing roller JSON: not an array"));
         }
     };
-    let folder_path = filepath.split(".").collect::<Vec<&str>>()[filepath.split(".").count() - 2];
-    let extension = filepath.split(".").collect::<Vec<&str>>()[filepath.split(".").count() - 1];
-    let filename = filepath.split("/").collect::<Vec<&str>>()[filepath.split("/").count() - 1]
-        .split(".")
-        .collect::<Vec<&str>>()[0];
-    println!("Path: .{folder_path}/{filename}/0.{extension}");
-    println!("Folder path: {folder_path}");
-    println!("Extension: {extension}");
-    println!("Filename: {filename}");
-    match create_dir_all(format!(".{folder_path}")) {
+    let (filepath, compression) = strip_compression_extension(filepath);
+    let path = Path::new(filepath);
+    let extension = match path.extension() {
+        Some(extension) => extension.to_string_lossy(),
+        None => "json".into(),
+    };
+    let filename = match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => {
+            return Err(traceback!(
+                "Error when splitting JSON: the path has no file name"
+            ));
+        }
+    };
+    let stem = filename.split('.').next().unwrap_or_default();
+    let folder_path = path.with_file_name(stem);
+    let folder_path = folder_path.to_string_lossy();
+    match create_dir_all(folder_path.as_ref()) {
         Ok(_) => {}
         Err(e) => {
             return Err(traceback!(err e, "Error when creating directory"));
@@ -113,19 +124,14 @@ pub fn split_array_from_json_file(filepath: &str, split_size: usize) -> Result<(
     let mut i = 0;
     let parsed_split = parsed.chunks(split_size);
     for chunk in parsed_split {
-        let mut file = match File::create(format!(".{folder_path}/{i}.{extension}")) {
-            Ok(f) => f,
-            Err(e) => {
-                return Err(traceback!(err e, "Error when creating file"));
-            }
-        };
         let chunk = match serde_json::to_string(chunk) {
             Ok(c) => c,
             Err(e) => {
                 return Err(traceback!(err e, "Error when parsing chunk"));
             }
         };
-        match file.write_all(chunk.as_bytes()) {
+        let chunk_path = format!("{folder_path}/{i}.{extension}{}", compression.extension());
+        match write_file(&chunk_path, chunk.as_bytes()) {
             Ok(_) => {}
             Err(e) => {
                 return Err(traceback!(err e, "Error when writing to file"));
@@ -279,6 +285,34 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_split_compressed_array() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("items.json.gz");
+        let items = json!([{"id": 1}, {"id": 2}, {"id": 3}]);
+        write_file(path.to_str().unwrap(), items.to_string().as_bytes()).unwrap();
+        split_array_from_json_file(path.to_str().unwrap(), 2).unwrap();
+
+        let folder = dir.path().join("items");
+        let mut names: Vec<String> = std::fs::read_dir(&folder)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["0.json.gz", "1.json.gz"]);
+        let first = folder.join("0.json.gz");
+        // The chunks are really gzip compressed, not just named like it
+        assert_eq!(&std::fs::read(&first).unwrap()[..2], &[0x1f, 0x8b]);
+        let chunks: Vec<Value> = names
+            .iter()
+            .map(|name| {
+                let bytes = read_file(folder.join(name).to_str().unwrap()).unwrap();
+                serde_json::from_slice(&bytes).unwrap()
+            })
+            .collect();
+        assert_eq!(chunks, [json!([{"id": 1}, {"id": 2}]), json!([{"id": 3}])]);
+    }
+
     #[test]
     fn test_generate_schema_null() {
         let input = Value::Null;
@@ -1,8 +1,18 @@
+#![allow(clippy::result_large_err)]
+
 pub mod async_utils;
+pub mod compression;
 pub mod csv2json;
+pub mod csv_ops;
+pub mod encoding;
+pub mod excel;
+pub mod formats;
 pub mod geojson;
 pub mod http;
 pub mod json;
+pub mod mapping;
+pub mod profile;
+pub mod validate;
 
 pub use paste;
 pub use serde_json;
@@ -116,7 +126,6 @@ macro_rules! set_panic_handler {
     };
 }
 
-
 pub fn get_multi_mut<T>(v: &mut [T], i: usize, j: usize) -> Option<(&mut T, &mut T)> {
     if i == j {
         return None;
@@ -125,4 +134,4 @@ pub fn get_multi_mut<T>(v: &mut [T], i: usize, j: usize) -> Option<(&mut T, &mut
 
     let (first, second) = v.split_at_mut(start + 1);
     Some((&mut first[start], &mut second[end - start - 1]))
-}
\ No newline at end of file
+}
@@ -0,0 +1,367 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use chrono::{NaiveDate, NaiveDateTime};
+use serde_json::{json, Map, Value};
+
+use traceback_error::{traceback, TracebackError};
+
+/// A conversion applied to the value of a single column by a `ColumnMapping`.
+///
+/// Converters only touch strings. Values of other types, such as numbers produced
+/// by an earlier `ParseNumber`, are passed through unchanged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Converter {
+    /// Removes leading and trailing whitespace.
+    Trim,
+    Lowercase,
+    Uppercase,
+    /// Parses a date or date-time with a `chrono` format string, e.g. `"%d.%m.%Y"`,
+    /// and replaces it with its ISO 8601 form (`2023-09-14` or `2023-09-14T13:45:00`).
+    ParseDate {
+        format: String,
+    },
+    /// Parses a number into a JSON number.
+    ///
+    /// With `decimal_comma`, `1.234,5` parses into `1234.5`, like in most European locales.
+    /// Without it, `1,234.5` does. Spaces are always accepted as thousands separators.
+    ParseNumber {
+        decimal_comma: bool,
+    },
+}
+
+impl Converter {
+    /// Applies the converter to a value. Empty strings become `null` when parsing dates or numbers.
+    pub fn apply(&self, value: Value) -> Result<Value, TracebackError> {
+        let s = match &value {
+            Value::String(s) => s,
+            _ => return Ok(value),
+        };
+        match self {
+            Converter::Trim => Ok(Value::String(s.trim().to_string())),
+            Converter::Lowercase => Ok(Value::String(s.to_lowercase())),
+            Converter::Uppercase => Ok(Value::String(s.to_uppercase())),
+            Converter::ParseDate { format } => {
+                let trimmed = s.trim();
+                if trimmed.is_empty() {
+                    return Ok(Value::Null);
+                }
+                if let Ok(datetime) = NaiveDateTime::parse_from_str(trimmed, format) {
+                    return Ok(Value::String(
+                        datetime.format("%Y-%m-%dT%H:%M:%S").to_string(),
+                    ));
+                }
+                match NaiveDate::parse_from_str(trimmed, format) {
+                    Ok(date) => Ok(Value::String(date.format("%Y-%m-%d").to_string())),
+                    Err(e) => Err(
+                        traceback!(format!("Failed to parse date {s}")).with_extra_data(json!({
+                            "error": e.to_string(),
+                            "value": s,
+                            "format": format,
+                        })),
+                    ),
+                }
+            }
+            Converter::ParseNumber { decimal_comma } => {
+                match parse_localized_number(s, *decimal_comma) {
+                    Some(number) => Ok(number),
+                    None => Err(
+                        traceback!(format!("Failed to parse number {s}")).with_extra_data(json!({
+                            "value": s,
+                            "decimal_comma": decimal_comma,
+                        })),
+                    ),
+                }
+            }
+        }
+    }
+}
+
+/// Parses a number written with thousands separators, returning `Value::Null` for empty strings.
+fn parse_localized_number(s: &str, decimal_comma: bool) -> Option<Value> {
+    let (thousands, decimal) = if decimal_comma {
+        ('.', ',')
+    } else {
+        (',', '.')
+    };
+    let normalized: String = s
+        .trim()
+        .chars()
+        .filter(|c| *c != thousands && !c.is_whitespace())
+        .map(|c| if c == decimal { '.' } else { c })
+        .collect();
+    if normalized.is_empty() {
+        return Some(Value::Null);
+    }
+    if let Ok(i) = normalized.parse::<i64>() {
+        return Some(Value::from(i));
+    }
+    let f = normalized.parse::<f64>().ok()?;
+    serde_json::Number::from_f64(f).map(Value::Number)
+}
+
+type ComputeFn = Arc<dyn Fn(&Map<String, Value>) -> Value + Send + Sync>;
+
+/// A declarative description of how to reshape the rows of a table.
+///
+/// A mapping selects and reorders columns, renames them, fills in defaults,
+/// runs converters over their values and adds computed columns. It works on rows as JSON objects,
+/// and is used by `csv2json::csv_to_json_with_options` and `csv2json::json_to_csv_with_mapping`.
+///
+/// Every step refers to columns by their name in the input, and rows are processed in this order:
+///
+/// 1. Columns are selected. Without `select`, every input column is kept.
+/// 2. Missing, `null` and empty values are replaced by the column's default.
+/// 3. The column's converters run, in the order they were added.
+/// 4. The column is renamed.
+/// 5. Computed columns are added, in the order they were added.
+///    Their closures see the row as produced by the previous steps, so they use the new column names.
+///
+/// ## Notes
+///
+/// - `serde_json` sorts object keys alphabetically, so the column order only shows up in CSV output.
+///   `output_columns` returns it for other uses.
+///
+/// ## Example
+///
+/// ```rust
+/// use serde_json::json;
+/// use utils::mapping::{ColumnMapping, Converter};
+///
+/// let mapping = ColumnMapping::new()
+///     .select(["Name", "Price", "Sold"])
+///     .rename("Name", "name")
+///     .convert("Name", Converter::Trim)
+///     .convert("Price", Converter::ParseNumber { decimal_comma: true })
+///     .rename("Price", "price")
+///     .default_value("Sold", json!("0"))
+///     .convert("Sold", Converter::ParseNumber { decimal_comma: true })
+///     .rename("Sold", "sold")
+///     .computed("revenue", |row| {
+///         let price = row["price"].as_f64().unwrap_or_default();
+///         let sold = row["sold"].as_f64().unwrap_or_default();
+///         json!(price * sold)
+///     });
+///
+/// let row = json!({"Name": " Widget ", "Price": "2,5", "Sold": "", "Internal": "x"});
+/// let mapped = mapping.apply(row.as_object().unwrap()).unwrap();
+/// assert_eq!(
+///     serde_json::Value::Object(mapped),
+///     json!({"name": "Widget", "price": 2.5, "sold": 0, "revenue": 0.0})
+/// );
+/// ```
+#[derive(Clone, Default)]
+pub struct ColumnMapping {
+    selected: Option<Vec<String>>,
+    renames: HashMap<String, String>,
+    defaults: HashMap<String, Value>,
+    converters: HashMap<String, Vec<Converter>>,
+    computed: Vec<(String, ComputeFn)>,
+}
+
+impl fmt::Debug for ColumnMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let computed: Vec<&String> = self.computed.iter().map(|(name, _)| name).collect();
+        f.debug_struct("ColumnMapping")
+            .field("selected", &self.selected)
+            .field("renames", &self.renames)
+            .field("defaults", &self.defaults)
+            .field("converters", &self.converters)
+            .field("computed", &computed)
+            .finish()
+    }
+}
+
+impl ColumnMapping {
+    /// Creates a mapping that keeps every column as it is.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps only these columns, in this order. Columns missing from a row are treated as `null`.
+    pub fn select<I, S>(mut self, columns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.selected = Some(columns.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Renames the column `from` to `to` in the output.
+    pub fn rename(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.renames.insert(from.into(), to.into());
+        self
+    }
+
+    /// Uses `value` when the column is missing, `null` or an empty string.
+    pub fn default_value(mut self, column: impl Into<String>, value: Value) -> Self {
+        self.defaults.insert(column.into(), value);
+        self
+    }
+
+    /// Adds a converter to run over the values of the column.
+    pub fn convert(mut self, column: impl Into<String>, converter: Converter) -> Self {
+        self.converters
+            .entry(column.into())
+            .or_default()
+            .push(converter);
+        self
+    }
+
+    /// Adds a column whose value is computed from the rest of the (already mapped) row.
+    pub fn computed<F>(mut self, name: impl Into<String>, compute: F) -> Self
+    where
+        F: Fn(&Map<String, Value>) -> Value + Send + Sync + 'static,
+    {
+        self.computed.push((name.into(), Arc::new(compute)));
+        self
+    }
+
+    /// Returns the names of the output columns, in order, for a table with the given input columns.
+    pub fn output_columns(&self, input_columns: &[String]) -> Vec<String> {
+        let columns = match &self.selected {
+            Some(selected) => selected.as_slice(),
+            None => input_columns,
+        };
+        let mut output: Vec<String> = columns.iter().map(|c| self.output_name(c)).collect();
+        for (name, _) in &self.computed {
+            if !output.contains(name) {
+                output.push(name.clone());
+            }
+        }
+        output
+    }
+
+    fn output_name(&self, column: &str) -> String {
+        match self.renames.get(column) {
+            Some(renamed) => renamed.clone(),
+            None => column.to_string(),
+        }
+    }
+
+    /// Applies the mapping to a single row.
+    pub fn apply(&self, row: &Map<String, Value>) -> Result<Map<String, Value>, TracebackError> {
+        let columns: Vec<&String> = match &self.selected {
+            Some(selected) => selected.iter().collect(),
+            None => row.keys().collect(),
+        };
+        let mut output = Map::new();
+        for column in columns {
+            let mut value = row.get(column).cloned().unwrap_or(Value::Null);
+            let is_blank = match &value {
+                Value::Null => true,
+                Value::String(s) => s.is_empty(),
+                _ => false,
+            };
+            if is_blank {
+                if let Some(default) = self.defaults.get(column) {
+                    value = default.clone();
+                }
+            }
+            for converter in self.converters.get(column).into_iter().flatten() {
+                value = match converter.apply(value) {
+                    Ok(value) => value,
+                    Err(e) => {
+                        return Err(
+                            traceback!(err e, format!("Failed to convert column {column}"))
+                                .with_extra_data(json!({ "column": column })),
+                        )
+                    }
+                };
+            }
+            output.insert(self.output_name(column), value);
+        }
+        for (name, compute) in &self.computed {
+            let value = compute(&output);
+            output.insert(name.clone(), value);
+        }
+        Ok(output)
+    }
+
+    /// Applies the mapping to every object in a JSON array.
+    ///
+    /// Errors report the index of the row that failed.
+    pub fn apply_to_array(&self, json: &Value) -> Result<Value, TracebackError> {
+        let rows = match json.as_array() {
+            Some(rows) => rows,
+            None => {
+                return Err(traceback!("Expected a json array of objects")
+                    .with_extra_data(json!({ "json": json })))
+            }
+        };
+        let mut result = Vec::with_capacity(rows.len());
+        for (i, row) in rows.iter().enumerate() {
+            let obj = match row.as_object() {
+                Some(obj) => obj,
+                None => {
+                    return Err(traceback!(format!("Expected an object at index {i}"))
+                        .with_extra_data(json!({ "index": i, "value": row })))
+                }
+            };
+            match self.apply(obj) {
+                Ok(mapped) => result.push(Value::Object(mapped)),
+                Err(e) => {
+                    return Err(traceback!(err e, format!("Failed to map row {i}"))
+                        .with_extra_data(json!({ "index": i })))
+                }
+            }
+        }
+        Ok(Value::Array(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_number() {
+        let comma = Converter::ParseNumber {
+            decimal_comma: true,
+        };
+        let point = Converter::ParseNumber {
+            decimal_comma: false,
+        };
+        assert_eq!(comma.apply(json!("1.234,5")).unwrap(), json!(1234.5));
+        assert_eq!(comma.apply(json!("1 000")).unwrap(), json!(1000));
+        assert_eq!(point.apply(json!("1,234.5")).unwrap(), json!(1234.5));
+        assert_eq!(point.apply(json!("")).unwrap(), Value::Null);
+        let mut err = point.apply(json!("twelve")).unwrap_err();
+        err.is_handled = true;
+        assert_eq!(err.message, "Failed to parse number twelve");
+    }
+
+    #[test]
+    fn test_parse_date() {
+        let date = Converter::ParseDate {
+            format: "%d.%m.%Y".to_string(),
+        };
+        let datetime = Converter::ParseDate {
+            format: "%d.%m.%Y %H:%M".to_string(),
+        };
+        assert_eq!(
+            date.apply(json!("14.09.2023")).unwrap(),
+            json!("2023-09-14")
+        );
+        assert_eq!(
+            datetime.apply(json!("14.09.2023 13:45")).unwrap(),
+            json!("2023-09-14T13:45:00")
+        );
+    }
+
+    #[test]
+    fn test_output_columns() {
+        let mapping = ColumnMapping::new()
+            .select(["b", "a"])
+            .rename("a", "alpha")
+            .computed("c", |_| Value::Null);
+        let input = vec!["a".to_string(), "b".to_string(), "z".to_string()];
+        assert_eq!(mapping.output_columns(&input), ["b", "alpha", "c"]);
+        assert_eq!(
+            ColumnMapping::new()
+                .rename("z", "zeta")
+                .output_columns(&input),
+            ["a", "b", "zeta"]
+        );
+    }
+}
@@ -0,0 +1,533 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use chrono::{NaiveDate, NaiveDateTime};
+use csv::{Reader, ReaderBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use traceback_error::{traceback, TracebackError};
+
+use crate::compression::open_file;
+
+/// The type a column's values most likely have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InferredType {
+    /// The column only contains empty values.
+    Empty,
+    /// `true` or `false`, in any case.
+    Boolean,
+    Integer,
+    Float,
+    /// An ISO 8601 date, e.g. `2023-09-14`.
+    Date,
+    /// An ISO 8601 date-time, e.g. `2023-09-14T13:45:00` or `2023-09-14 13:45:00`.
+    DateTime,
+    String,
+}
+
+/// The types `profile_csv` tries, in order of preference when several match equally many values.
+const CANDIDATE_TYPES: [InferredType; 5] = [
+    InferredType::Boolean,
+    InferredType::Integer,
+    InferredType::Float,
+    InferredType::Date,
+    InferredType::DateTime,
+];
+
+impl InferredType {
+    /// Checks whether a (trimmed) value is of this type.
+    pub fn matches(&self, value: &str) -> bool {
+        match self {
+            InferredType::Empty => value.is_empty(),
+            InferredType::Boolean => {
+                value.eq_ignore_ascii_case("true") || value.eq_ignore_ascii_case("false")
+            }
+            InferredType::Integer => value.parse::<i64>().is_ok(),
+            InferredType::Float => value.parse::<f64>().is_ok_and(|f| f.is_finite()),
+            InferredType::Date => NaiveDate::parse_from_str(value, "%Y-%m-%d").is_ok(),
+            InferredType::DateTime => parse_datetime(value).is_some(),
+            InferredType::String => true,
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(self, InferredType::Integer | InferredType::Float)
+    }
+}
+
+fn parse_datetime(value: &str) -> Option<NaiveDateTime> {
+    ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            chrono::DateTime::parse_from_rfc3339(value)
+                .ok()
+                .map(|datetime| datetime.naive_utc())
+        })
+}
+
+/// Options for `profile_csv`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileOptions {
+    /// How many of the most common values to report per column.
+    pub top_n: usize,
+    /// How many distinct values to count exactly per column.
+    /// Beyond that, distinct counts are estimated with HyperLogLog and top values become approximate.
+    pub exact_distinct_limit: usize,
+    /// How many values that don't match the inferred type to report per column.
+    pub malformed_samples: usize,
+    /// The share of non-empty values, from 0 to 1, that must match a type for it to be inferred.
+    /// Columns where no type reaches the threshold are inferred as `InferredType::String`.
+    pub type_threshold: f64,
+    /// Values counted as empty besides blank strings, e.g. `NULL`.
+    pub null_markers: Vec<String>,
+}
+
+impl Default for ProfileOptions {
+    fn default() -> Self {
+        Self {
+            top_n: 10,
+            exact_distinct_limit: 100_000,
+            malformed_samples: 5,
+            type_threshold: 0.9,
+            null_markers: vec!["null".to_string(), "NULL".to_string()],
+        }
+    }
+}
+
+/// How often a value occurs in a column.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValueCount {
+    pub value: String,
+    pub count: usize,
+}
+
+/// A value that doesn't match the type inferred for its column.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MalformedValue {
+    /// The zero-based index of the data row, not counting the header row.
+    pub row: usize,
+    pub value: String,
+}
+
+/// The profile of a single CSV column.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColumnProfile {
+    pub name: String,
+    pub inferred_type: InferredType,
+    /// How many values are blank or one of `ProfileOptions::null_markers`.
+    pub empty_count: usize,
+    /// How many different non-empty values the column has.
+    pub distinct_count: usize,
+    /// Whether `distinct_count` is a HyperLogLog estimate and `top_values` are approximate.
+    pub distinct_is_estimate: bool,
+    /// The smallest value, compared as numbers for numeric columns and as strings otherwise.
+    /// ISO 8601 dates compare correctly as strings.
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+    pub top_values: Vec<ValueCount>,
+    /// The length of the longest value, in characters.
+    pub max_length: usize,
+    pub malformed_samples: Vec<MalformedValue>,
+}
+
+/// The profile of a CSV file, see `profile_csv`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CsvProfile {
+    pub row_count: usize,
+    pub columns: Vec<ColumnProfile>,
+}
+
+impl CsvProfile {
+    /// Renders the profile as JSON.
+    pub fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+}
+
+/// Profiles CSV data column by column, reading it a record at a time.
+///
+/// ## Arguments
+///
+/// * `csv` - A `csv::Reader` containing the CSV data to be profiled. The first row is the header row.
+/// * `options` - Limits and thresholds for the profile, see `ProfileOptions`.
+///
+/// ## Returns
+///
+/// * `Result<CsvProfile, TracebackError>` - The profile, or a `TracebackError` if the CSV can't be read.
+///
+/// ## Notes
+///
+/// - Rows shorter than the header row count as empty values for the missing columns.
+///   This requires a reader built with `csv::ReaderBuilder::flexible(true)`.
+/// - Memory use per column is bounded by `exact_distinct_limit`, so large files can be profiled.
+///
+/// ## Example
+///
+/// ```rust
+/// use csv::Reader;
+/// use utils::profile::{profile_csv, InferredType, ProfileOptions};
+///
+/// let csv_data: &[u8] = b"name,age\nalice,20\nbob,thirty\ncarol,40\n";
+/// let profile = profile_csv(Reader::from_reader(csv_data), &ProfileOptions {
+///     type_threshold: 0.6,
+///     ..Default::default()
+/// }).unwrap();
+///
+/// let age = &profile.columns[1];
+/// assert_eq!(age.inferred_type, InferredType::Integer);
+/// assert_eq!(age.malformed_samples[0].value, "thirty");
+/// println!("{}", profile.to_json());
+/// ```
+pub fn profile_csv<T: std::io::Read>(
+    mut csv: Reader<T>,
+    options: &ProfileOptions,
+) -> Result<CsvProfile, TracebackError> {
+    let headers = match csv.headers().cloned() {
+        Ok(headers) => headers,
+        Err(e) => {
+            return Err(traceback!("Failed to read CSV headers")
+                .with_extra_data(json!({ "error": e.to_string() })))
+        }
+    };
+    let mut columns: Vec<ColumnStats> = headers.iter().map(|_| ColumnStats::new()).collect();
+    let mut row_count = 0;
+    for result in csv.records() {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => {
+                return Err(traceback!("Failed to read CSV record")
+                    .with_extra_data(json!({ "error": e.to_string(), "row": row_count })))
+            }
+        };
+        for (i, stats) in columns.iter_mut().enumerate() {
+            let value = record.get(i).unwrap_or("").trim();
+            stats.add(value, row_count, options);
+        }
+        row_count += 1;
+    }
+    let columns = headers
+        .iter()
+        .zip(columns)
+        .map(|(name, stats)| stats.finish(name, options))
+        .collect();
+    Ok(CsvProfile { row_count, columns })
+}
+
+/// Profiles a CSV file, which may be gzip or zstd compressed, see `profile_csv`.
+pub fn profile_csv_file(
+    path: &str,
+    options: &ProfileOptions,
+) -> Result<CsvProfile, TracebackError> {
+    let rdr = match open_file(path) {
+        Ok(file) => ReaderBuilder::new().flexible(true).from_reader(file),
+        Err(e) => return Err(traceback!(err e, "Failed to read CSV file")),
+    };
+    match profile_csv(rdr, options) {
+        Ok(profile) => Ok(profile),
+        Err(e) => Err(traceback!(err e, "Failed to profile CSV file")),
+    }
+}
+
+/// The running statistics of a column while its values are being read.
+struct ColumnStats {
+    non_empty: usize,
+    empty: usize,
+    /// How many values match each of `CANDIDATE_TYPES`, by index.
+    type_matches: [usize; CANDIDATE_TYPES.len()],
+    /// The first few values not matching each of `CANDIDATE_TYPES`, by index.
+    type_mismatches: [Vec<MalformedValue>; CANDIDATE_TYPES.len()],
+    /// The bounds of the values matching each of `CANDIDATE_TYPES`, by index.
+    type_bounds: [Bounds; CANDIDATE_TYPES.len()],
+    /// The bounds of all values, for columns inferred as strings.
+    bounds: Bounds,
+    max_length: usize,
+    counts: HashMap<String, usize>,
+    counts_pruned: bool,
+    distinct: HyperLogLog,
+}
+
+impl ColumnStats {
+    fn new() -> Self {
+        Self {
+            non_empty: 0,
+            empty: 0,
+            type_matches: [0; CANDIDATE_TYPES.len()],
+            type_mismatches: Default::default(),
+            type_bounds: Default::default(),
+            bounds: Bounds::default(),
+            max_length: 0,
+            counts: HashMap::new(),
+            counts_pruned: false,
+            distinct: HyperLogLog::new(),
+        }
+    }
+
+    fn add(&mut self, value: &str, row: usize, options: &ProfileOptions) {
+        if value.is_empty() || options.null_markers.iter().any(|marker| marker == value) {
+            self.empty += 1;
+            return;
+        }
+        self.max_length = self.max_length.max(value.chars().count());
+        self.non_empty += 1;
+        for (i, candidate) in CANDIDATE_TYPES.iter().enumerate() {
+            if candidate.matches(value) {
+                self.type_matches[i] += 1;
+                self.type_bounds[i].add(value, candidate.is_numeric());
+            } else if self.type_mismatches[i].len() < options.malformed_samples {
+                self.type_mismatches[i].push(MalformedValue {
+                    row,
+                    value: value.to_string(),
+                });
+            }
+        }
+        self.bounds.add(value, false);
+        self.distinct.insert(value);
+        *self.counts.entry(value.to_string()).or_insert(0) += 1;
+        if self.counts.len() > options.exact_distinct_limit {
+            // Keep the most common half, so that frequent values keep being counted
+            let mut entries: Vec<(String, usize)> = self.counts.drain().collect();
+            entries.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+            entries.truncate(options.exact_distinct_limit / 2);
+            self.counts = entries.into_iter().collect();
+            self.counts_pruned = true;
+        }
+    }
+
+    fn finish(self, name: &str, options: &ProfileOptions) -> ColumnProfile {
+        let (inferred_type, malformed_samples) = self.infer_type(options);
+        // Values that don't match the inferred type are reported as malformed, not as bounds
+        let bounds = match CANDIDATE_TYPES.iter().position(|t| *t == inferred_type) {
+            Some(i) => &self.type_bounds[i],
+            None => &self.bounds,
+        };
+        let (min, max) = bounds.to_json();
+        let distinct_count = if self.counts_pruned {
+            self.distinct.estimate()
+        } else {
+            self.counts.len()
+        };
+        let mut top_values: Vec<ValueCount> = self
+            .counts
+            .into_iter()
+            .map(|(value, count)| ValueCount { value, count })
+            .collect();
+        top_values.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+        top_values.truncate(options.top_n);
+        ColumnProfile {
+            name: name.to_string(),
+            inferred_type,
+            empty_count: self.empty,
+            distinct_count,
+            distinct_is_estimate: self.counts_pruned,
+            min,
+            max,
+            top_values,
+            max_length: self.max_length,
+            malformed_samples,
+        }
+    }
+
+    fn infer_type(&self, options: &ProfileOptions) -> (InferredType, Vec<MalformedValue>) {
+        if self.non_empty == 0 {
+            return (InferredType::Empty, vec![]);
+        }
+        let mut best: Option<usize> = None;
+        for i in 0..CANDIDATE_TYPES.len() {
+            if best.is_none_or(|best| self.type_matches[i] > self.type_matches[best]) {
+                best = Some(i);
+            }
+        }
+        match best {
+            Some(i)
+                if self.type_matches[i] as f64 / self.non_empty as f64
+                    >= options.type_threshold =>
+            {
+                (CANDIDATE_TYPES[i], self.type_mismatches[i].clone())
+            }
+            _ => (InferredType::String, vec![]),
+        }
+    }
+}
+
+/// The smallest and largest of some values, compared as numbers for numeric types and as text otherwise.
+#[derive(Default)]
+struct Bounds {
+    min_number: Option<f64>,
+    max_number: Option<f64>,
+    min_string: Option<String>,
+    max_string: Option<String>,
+}
+
+impl Bounds {
+    fn add(&mut self, value: &str, numeric: bool) {
+        if numeric {
+            if let Ok(number) = value.parse::<f64>() {
+                self.min_number = Some(self.min_number.map_or(number, |min| min.min(number)));
+                self.max_number = Some(self.max_number.map_or(number, |max| max.max(number)));
+            }
+            return;
+        }
+        if self.min_string.as_deref().is_none_or(|min| value < min) {
+            self.min_string = Some(value.to_string());
+        }
+        if self.max_string.as_deref().is_none_or(|max| value > max) {
+            self.max_string = Some(value.to_string());
+        }
+    }
+
+    fn to_json(&self) -> (Option<Value>, Option<Value>) {
+        match (self.min_number, self.max_number) {
+            (Some(min), Some(max)) => (Some(number_to_json(min)), Some(number_to_json(max))),
+            _ => (
+                self.min_string.clone().map(Value::String),
+                self.max_string.clone().map(Value::String),
+            ),
+        }
+    }
+}
+
+fn number_to_json(number: f64) -> Value {
+    if number.fract() == 0.0 && number.abs() < i64::MAX as f64 {
+        return Value::from(number as i64);
+    }
+    serde_json::Number::from_f64(number).map_or(Value::Null, Value::Number)
+}
+
+/// The number of register index bits, giving 2^14 registers and a standard error of about 0.8%.
+const HLL_PRECISION: u32 = 14;
+
+/// A HyperLogLog sketch estimating how many distinct values have been inserted.
+struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    fn new() -> Self {
+        Self {
+            registers: vec![0; 1 << HLL_PRECISION],
+        }
+    }
+
+    fn insert(&mut self, value: &str) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let index = (hash >> (64 - HLL_PRECISION)) as usize;
+        // The remaining bits, with a sentinel bit so that the rank is bounded
+        let rest = (hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+        }
+    }
+
+    fn estimate(&self) -> usize {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&register| 2f64.powi(-(register as i32)))
+            .sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self
+            .registers
+            .iter()
+            .filter(|&&register| register == 0)
+            .count();
+        if raw <= 2.5 * m && zeros > 0 {
+            // Linear counting is more accurate for small cardinalities
+            (m * (m / zeros as f64).ln()).round() as usize
+        } else {
+            raw.round() as usize
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static PROFILE_CSV: &str = "id,name,joined,score
+1,alice,2023-09-14,1.5
+2,bob,2023-09-15,
+3,alice,not a date,2
+4,NULL,2023-09-16,x
+";
+
+    #[test]
+    fn test_profile_csv() {
+        let options = ProfileOptions {
+            type_threshold: 0.6,
+            ..Default::default()
+        };
+        let profile = profile_csv(Reader::from_reader(PROFILE_CSV.as_bytes()), &options).unwrap();
+        assert_eq!(profile.row_count, 4);
+
+        let id = &profile.columns[0];
+        assert_eq!(id.inferred_type, InferredType::Integer);
+        assert_eq!(
+            (id.min.clone(), id.max.clone()),
+            (Some(json!(1)), Some(json!(4)))
+        );
+        assert_eq!(id.distinct_count, 4);
+
+        let name = &profile.columns[1];
+        assert_eq!(name.inferred_type, InferredType::String);
+        assert_eq!(name.empty_count, 1);
+        assert_eq!(
+            name.top_values[0],
+            ValueCount {
+                value: "alice".to_string(),
+                count: 2
+            }
+        );
+        assert_eq!(name.max_length, 5);
+
+        let joined = &profile.columns[2];
+        assert_eq!(joined.inferred_type, InferredType::Date);
+        assert_eq!(
+            joined.malformed_samples,
+            vec![MalformedValue {
+                row: 2,
+                value: "not a date".to_string()
+            }]
+        );
+        assert_eq!(
+            (joined.min.clone(), joined.max.clone()),
+            (Some(json!("2023-09-14")), Some(json!("2023-09-16")))
+        );
+        assert_eq!(joined.max_length, 10);
+
+        let score = &profile.columns[3];
+        assert_eq!(score.inferred_type, InferredType::Float);
+        assert_eq!(score.max, Some(json!(2)));
+        assert_eq!(score.malformed_samples[0].value, "x");
+
+        // Null markers are empty, so they don't count towards the length
+        let csv = "value\nNULL\nab\n";
+        let profile = profile_csv(Reader::from_reader(csv.as_bytes()), &options).unwrap();
+        assert_eq!(profile.columns[0].max_length, 2);
+    }
+
+    #[test]
+    fn test_distinct_count_estimate() {
+        let mut csv = String::from("value\n");
+        for i in 0..20_000 {
+            csv.push_str(&format!("{}\n", i % 5_000));
+        }
+        let options = ProfileOptions {
+            exact_distinct_limit: 1_000,
+            ..Default::default()
+        };
+        let profile = profile_csv(Reader::from_reader(csv.as_bytes()), &options).unwrap();
+        let column = &profile.columns[0];
+        assert!(column.distinct_is_estimate);
+        assert!((4_800..5_200).contains(&column.distinct_count));
+    }
+}
@@ -0,0 +1,470 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use csv::{Reader, ReaderBuilder};
+use email_address::EmailAddress;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use traceback_error::{traceback, TracebackError};
+
+use crate::{compression::open_file, profile::InferredType};
+
+/// A check on the values of a single column.
+///
+/// Apart from `NotEmpty`, rules skip empty values, so optional columns can still be checked.
+#[derive(Debug, Clone)]
+pub enum ColumnRule {
+    /// The value must not be empty or blank.
+    NotEmpty,
+    /// The value must be of this type, see `InferredType::matches`.
+    Type(InferredType),
+    /// The value must match this regular expression.
+    Pattern(Regex),
+    /// The value must be one of these.
+    AllowedValues(Vec<String>),
+    /// The value must be a valid email address.
+    Email,
+}
+
+impl ColumnRule {
+    /// A short name for the rule, used in `Violation::rule`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColumnRule::NotEmpty => "not_empty",
+            ColumnRule::Type(_) => "type",
+            ColumnRule::Pattern(_) => "pattern",
+            ColumnRule::AllowedValues(_) => "allowed_values",
+            ColumnRule::Email => "email",
+        }
+    }
+
+    /// Checks a value, returning why it's invalid if it is.
+    pub fn check(&self, value: &str) -> Option<String> {
+        let value = value.trim();
+        if value.is_empty() {
+            return match self {
+                ColumnRule::NotEmpty => Some("Value is empty".to_string()),
+                _ => None,
+            };
+        }
+        match self {
+            ColumnRule::NotEmpty => None,
+            ColumnRule::Type(expected) if !expected.matches(value) => {
+                Some(format!("Expected a value of type {expected:?}"))
+            }
+            ColumnRule::Pattern(pattern) if !pattern.is_match(value) => {
+                Some(format!("Value doesn't match the pattern {pattern}"))
+            }
+            ColumnRule::AllowedValues(allowed) if !allowed.iter().any(|a| a == value) => {
+                Some(format!("Value isn't one of {}", allowed.join(", ")))
+            }
+            ColumnRule::Email if !EmailAddress::is_valid(value) => {
+                Some("Value isn't a valid email address".to_string())
+            }
+            _ => None,
+        }
+    }
+}
+
+type RowCheckFn = Arc<dyn Fn(&Map<String, Value>) -> Option<String> + Send + Sync>;
+
+/// A rule violation found by `ValidationRules`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Violation {
+    /// The zero-based index of the data row, not counting the header row.
+    /// `None` for violations about the table as a whole, like a missing column.
+    pub row: Option<usize>,
+    /// The column the violation is about. `None` for cross-column rules.
+    pub column: Option<String>,
+    /// The name of the rule, e.g. `email`, `unique` or the name given to a cross-column rule.
+    pub rule: String,
+    pub message: String,
+    pub value: Option<String>,
+}
+
+/// Every violation found when validating a table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidationReport {
+    pub rows_checked: usize,
+    pub violations: Vec<Violation>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// Renders the report as JSON.
+    pub fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+}
+
+/// A set of rules tabular data must follow.
+///
+/// Rules are checked against every row, and all violations are collected instead of stopping at the first one.
+/// Values are compared as strings. When validating JSON, `null` counts as empty
+/// and other non-string values are converted to their JSON representation.
+///
+/// ## Example
+///
+/// ```rust
+/// use csv::Reader;
+/// use regex::Regex;
+/// use utils::profile::InferredType;
+/// use utils::validate::ValidationRules;
+///
+/// let rules = ValidationRules::new()
+///     .require_columns(["id", "email", "start", "end"])
+///     .column_type("id", InferredType::Integer)
+///     .unique(["id"])
+///     .email("email")
+///     .pattern("start", Regex::new(r"^\d{4}-\d{2}-\d{2}$").unwrap())
+///     .row_rule("end_after_start", |row| {
+///         match (row["start"].as_str(), row["end"].as_str()) {
+///             (Some(start), Some(end)) if end < start => Some("end is before start".to_string()),
+///             _ => None,
+///         }
+///     });
+///
+/// let csv_data: &[u8] = b"id,email,start,end\n1,a@example.com,2023-01-01,2023-02-01\n1,nope,2023-03-01,2023-02-01\n";
+/// let report = rules.validate_csv(Reader::from_reader(csv_data)).unwrap();
+///
+/// assert_eq!(report.violations.len(), 3);
+/// for violation in &report.violations {
+///     println!("row {:?}, column {:?}: {}", violation.row, violation.column, violation.message);
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct ValidationRules {
+    required_columns: Vec<String>,
+    column_rules: Vec<(String, ColumnRule)>,
+    unique: Vec<Vec<String>>,
+    row_rules: Vec<(String, RowCheckFn)>,
+}
+
+impl fmt::Debug for ValidationRules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let row_rules: Vec<&String> = self.row_rules.iter().map(|(name, _)| name).collect();
+        f.debug_struct("ValidationRules")
+            .field("required_columns", &self.required_columns)
+            .field("column_rules", &self.column_rules)
+            .field("unique", &self.unique)
+            .field("row_rules", &row_rules)
+            .finish()
+    }
+}
+
+impl ValidationRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requires these columns to be present in the header.
+    pub fn require_columns<I, S>(mut self, columns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.required_columns
+            .extend(columns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Adds a rule for the values of a column.
+    pub fn rule(mut self, column: impl Into<String>, rule: ColumnRule) -> Self {
+        self.column_rules.push((column.into(), rule));
+        self
+    }
+
+    /// Requires every value of the column to be non-empty.
+    pub fn not_empty(self, column: impl Into<String>) -> Self {
+        self.rule(column, ColumnRule::NotEmpty)
+    }
+
+    /// Requires every value of the column to be of a type.
+    pub fn column_type(self, column: impl Into<String>, expected: InferredType) -> Self {
+        self.rule(column, ColumnRule::Type(expected))
+    }
+
+    /// Requires every value of the column to match a regular expression.
+    pub fn pattern(self, column: impl Into<String>, pattern: Regex) -> Self {
+        self.rule(column, ColumnRule::Pattern(pattern))
+    }
+
+    /// Requires every value of the column to be one of `allowed`.
+    pub fn allowed_values<I, S>(self, column: impl Into<String>, allowed: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let allowed = allowed.into_iter().map(Into::into).collect();
+        self.rule(column, ColumnRule::AllowedValues(allowed))
+    }
+
+    /// Requires every value of the column to be a valid email address.
+    pub fn email(self, column: impl Into<String>) -> Self {
+        self.rule(column, ColumnRule::Email)
+    }
+
+    /// Requires the combination of these columns to be unique across rows.
+    ///
+    /// Rows where any of the columns is empty are not checked.
+    pub fn unique<I, S>(mut self, columns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.unique
+            .push(columns.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Adds a rule over a whole row, for checks involving several columns.
+    ///
+    /// The closure returns a message describing the problem, or `None` if the row is valid.
+    pub fn row_rule<F>(mut self, name: impl Into<String>, check: F) -> Self
+    where
+        F: Fn(&Map<String, Value>) -> Option<String> + Send + Sync + 'static,
+    {
+        self.row_rules.push((name.into(), Arc::new(check)));
+        self
+    }
+
+    /// Validates CSV data, reading it a record at a time. The first row is the header row.
+    ///
+    /// Rows shorter than the header row have empty values for the missing columns,
+    /// which requires a reader built with `csv::ReaderBuilder::flexible(true)`.
+    pub fn validate_csv<T: std::io::Read>(
+        &self,
+        mut csv: Reader<T>,
+    ) -> Result<ValidationReport, TracebackError> {
+        let headers = match csv.headers().cloned() {
+            Ok(headers) => headers,
+            Err(e) => {
+                return Err(traceback!("Failed to read CSV headers")
+                    .with_extra_data(json!({ "error": e.to_string() })))
+            }
+        };
+        let columns: Vec<String> = headers.iter().map(str::to_string).collect();
+        let mut validator = Validator::new(self, &columns);
+        for result in csv.records() {
+            let record = match result {
+                Ok(record) => record,
+                Err(e) => {
+                    return Err(traceback!("Failed to read CSV record").with_extra_data(
+                        json!({ "error": e.to_string(), "row": validator.rows_checked }),
+                    ))
+                }
+            };
+            let row: Map<String, Value> = columns
+                .iter()
+                .enumerate()
+                .map(|(i, column)| {
+                    let value = record.get(i).unwrap_or("");
+                    (column.clone(), Value::String(value.to_string()))
+                })
+                .collect();
+            validator.check_row(&row);
+        }
+        Ok(validator.finish())
+    }
+
+    /// Validates a CSV file, which may be gzip or zstd compressed, see `validate_csv`.
+    pub fn validate_csv_file(&self, path: &str) -> Result<ValidationReport, TracebackError> {
+        let rdr = match open_file(path) {
+            Ok(file) => ReaderBuilder::new().flexible(true).from_reader(file),
+            Err(e) => return Err(traceback!(err e, "Failed to read CSV file")),
+        };
+        match self.validate_csv(rdr) {
+            Ok(report) => Ok(report),
+            Err(e) => Err(traceback!(err e, "Failed to validate CSV file")),
+        }
+    }
+
+    /// Validates a JSON array of objects, such as the output of `csv2json::csv_file_to_json`.
+    ///
+    /// The columns of the table are all keys found in any of the objects.
+    pub fn validate_json(&self, json: &Value) -> Result<ValidationReport, TracebackError> {
+        let rows = match json.as_array() {
+            Some(rows) => rows,
+            None => {
+                return Err(traceback!("Expected a json array of objects")
+                    .with_extra_data(json!({ "json": json })))
+            }
+        };
+        let mut objects = Vec::with_capacity(rows.len());
+        let mut columns: Vec<String> = Vec::new();
+        for (i, row) in rows.iter().enumerate() {
+            let obj = match row.as_object() {
+                Some(obj) => obj,
+                None => {
+                    return Err(traceback!(format!("Expected an object at index {i}"))
+                        .with_extra_data(json!({ "index": i, "value": row })))
+                }
+            };
+            for key in obj.keys() {
+                if !columns.contains(key) {
+                    columns.push(key.clone());
+                }
+            }
+            objects.push(obj);
+        }
+        let mut validator = Validator::new(self, &columns);
+        for obj in objects {
+            validator.check_row(obj);
+        }
+        Ok(validator.finish())
+    }
+}
+
+/// The state of a single validation run.
+struct Validator<'a> {
+    rules: &'a ValidationRules,
+    /// The value of each unique key, and the first row it was seen on, per `ValidationRules::unique` entry.
+    seen_keys: Vec<HashMap<Vec<String>, usize>>,
+    rows_checked: usize,
+    violations: Vec<Violation>,
+}
+
+impl<'a> Validator<'a> {
+    fn new(rules: &'a ValidationRules, columns: &[String]) -> Self {
+        let violations = rules
+            .required_columns
+            .iter()
+            .filter(|required| !columns.contains(required))
+            .map(|required| Violation {
+                row: None,
+                column: Some(required.clone()),
+                rule: "required_column".to_string(),
+                message: format!("Column {required} is missing"),
+                value: None,
+            })
+            .collect();
+        Self {
+            rules,
+            seen_keys: vec![HashMap::new(); rules.unique.len()],
+            rows_checked: 0,
+            violations,
+        }
+    }
+
+    fn check_row(&mut self, row: &Map<String, Value>) {
+        let index = self.rows_checked;
+        self.rows_checked += 1;
+        for (column, rule) in &self.rules.column_rules {
+            let value = value_to_string(row.get(column));
+            if let Some(message) = rule.check(&value) {
+                self.violations.push(Violation {
+                    row: Some(index),
+                    column: Some(column.clone()),
+                    rule: rule.name().to_string(),
+                    message,
+                    value: Some(value),
+                });
+            }
+        }
+        for (columns, seen) in self.rules.unique.iter().zip(self.seen_keys.iter_mut()) {
+            let key: Vec<String> = columns
+                .iter()
+                .map(|column| value_to_string(row.get(column)).trim().to_string())
+                .collect();
+            if key.iter().any(String::is_empty) {
+                continue;
+            }
+            match seen.get(&key) {
+                Some(first) => self.violations.push(Violation {
+                    row: Some(index),
+                    column: Some(columns.join(",")),
+                    rule: "unique".to_string(),
+                    message: format!("Duplicate of row {first}"),
+                    value: Some(key.join(",")),
+                }),
+                None => {
+                    seen.insert(key, index);
+                }
+            }
+        }
+        for (name, check) in &self.rules.row_rules {
+            if let Some(message) = check(row) {
+                self.violations.push(Violation {
+                    row: Some(index),
+                    column: None,
+                    rule: name.clone(),
+                    message,
+                    value: None,
+                });
+            }
+        }
+    }
+
+    fn finish(self) -> ValidationReport {
+        ValidationReport {
+            rows_checked: self.rows_checked,
+            violations: self.violations,
+        }
+    }
+}
+
+fn value_to_string(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(value) => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_json() {
+        let rules = ValidationRules::new()
+            .require_columns(["id", "status", "country"])
+            .not_empty("status")
+            .allowed_values("status", ["active", "inactive"])
+            .pattern("id", Regex::new(r"^[A-Z]\d+$").unwrap())
+            .unique(["id"]);
+        let json = json!([
+            {"id": "A1", "status": "active"},
+            {"id": "A2", "status": "paused"},
+            {"id": "A1", "status": null},
+            {"id": 7, "status": "inactive"}
+        ]);
+        let report = rules.validate_json(&json).unwrap();
+        assert_eq!(report.rows_checked, 4);
+        let found: Vec<(Option<usize>, Option<&str>, &str)> = report
+            .violations
+            .iter()
+            .map(|v| (v.row, v.column.as_deref(), v.rule.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (None, Some("country"), "required_column"),
+                (Some(1), Some("status"), "allowed_values"),
+                (Some(2), Some("status"), "not_empty"),
+                (Some(2), Some("id"), "unique"),
+                (Some(3), Some("id"), "pattern"),
+            ]
+        );
+        assert_eq!(report.violations[3].message, "Duplicate of row 0");
+    }
+
+    #[test]
+    fn test_validate_csv_email_and_type() {
+        let rules = ValidationRules::new()
+            .email("email")
+            .column_type("age", InferredType::Integer);
+        let csv = "email,age\nalice@example.com,20\nbob@,2x\n,\n";
+        let report = rules
+            .validate_csv(Reader::from_reader(csv.as_bytes()))
+            .unwrap();
+        assert_eq!(report.violations.len(), 2);
+        assert_eq!(report.violations[0].value.as_deref(), Some("bob@"));
+        assert_eq!(report.violations[1].row, Some(1));
+        assert!(!report.is_valid());
+    }
+}